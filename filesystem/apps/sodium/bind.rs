@@ -0,0 +1,81 @@
+use super::*;
+use redox::*;
+
+/// A command bound to a key
+#[derive(Clone)]
+pub struct Binding {
+    /// The key
+    pub key: Key,
+    /// The prompt command to invoke
+    pub cmd: String,
+}
+
+impl Key {
+    /// Get the function key with a given name (e.g. "F5")
+    pub fn from_name(name: &str) -> Option<Key> {
+        if name.len() > 1 && (name.starts_with('F') || name.starts_with('f')) {
+            let n = name[1..].to_string().to_num();
+            if n >= 1 && n <= 12 {
+                return Some(Key::F(n as u8));
+            }
+        }
+        None
+    }
+}
+
+impl Editor {
+    /// Bind a prompt command to a key, replacing any earlier binding
+    pub fn bind(&mut self, key: Key, cmd: &str) {
+        self.bindings.retain(|b| b.key != key);
+        self.bindings.push(Binding {
+            key: key,
+            cmd: cmd.to_string(),
+        });
+    }
+
+    /// Invoke the command bound to a key. Returns false if the key is unbound.
+    pub fn invoke_binding(&mut self, key: Key) -> bool {
+        let mut cmd = None;
+        for b in self.bindings.iter() {
+            if b.key == key {
+                cmd = Some(b.cmd.clone());
+            }
+        }
+
+        if let Some(cmd) = cmd {
+            self.invoke(&cmd);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_bindings() {
+        assert!(Key::from_name("F5") == Some(Key::F(5)));
+        assert!(Key::from_name("f12") == Some(Key::F(12)));
+        assert!(Key::from_name("F13") == None);
+        assert!(Key::from_name("F") == None);
+
+        let mut editor = Editor::with_text("");
+        editor.keys(&[Key::F(5)]);
+        assert_eq!(editor.status_bar.msg, "Key not bound");
+
+        editor.type_str(":map F5 :set number");
+        editor.keys(&[Key::Enter]);
+        editor.keys(&[Key::F(5)]);
+        assert!(editor.options.number);
+
+        // Binding the key again replaces the command
+        editor.type_str(":map F5 :set nonumber");
+        editor.keys(&[Key::Enter]);
+        editor.keys(&[Key::F(5)]);
+        assert!(! editor.options.number);
+        assert_eq!(editor.bindings.len(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+use super::*;
+use redox::*;
+
+/// A buffer, holding the state of a file not currently being edited
+pub struct Buffer {
+    /// The text
+    pub text: VecDeque<VecDeque<char>>,
+    /// The cursors
+    pub cursors: Vec<Cursor>,
+    /// The current cursor
+    pub current_cursor: u8,
+    /// The file the text is saved to
+    pub filename: Option<String>,
+    /// The undo history
+    pub history: History,
+    /// Whether the text was changed since it was last saved
+    pub dirty: bool,
+    /// The marks
+    pub marks: Vec<Mark>,
+}
+
+impl Buffer {
+    /// Create a new empty buffer
+    pub fn new() -> Buffer {
+        let mut text = VecDeque::new();
+        text.push_back(VecDeque::new());
+
+        Buffer {
+            text: text,
+            cursors: vec![Cursor::new()],
+            current_cursor: 0,
+            filename: None,
+            history: History::new(),
+            dirty: false,
+            marks: Vec::new(),
+        }
+    }
+}
+
+impl Editor {
+    /// Exchange the state of the editor with a stored buffer
+    fn swap_buffer(&mut self, n: usize) {
+        let buffer = &mut self.buffers[n];
+        mem::swap(&mut self.text, &mut buffer.text);
+        mem::swap(&mut self.cursors, &mut buffer.cursors);
+        mem::swap(&mut self.current_cursor, &mut buffer.current_cursor);
+        mem::swap(&mut self.filename, &mut buffer.filename);
+        mem::swap(&mut self.history, &mut buffer.history);
+        mem::swap(&mut self.dirty, &mut buffer.dirty);
+        mem::swap(&mut self.marks, &mut buffer.marks);
+    }
+
+    /// Switch to another buffer. The active buffer lives in the editor itself,
+    /// and its slot in `buffers` holds it only while it is inactive.
+    pub fn switch_buffer(&mut self, n: usize) {
+        if n >= self.buffers.len() {
+            self.status_bar.msg = format!("No buffer {}", n + 1);
+            return;
+        }
+
+        if n != self.current_buffer {
+            let current = self.current_buffer;
+            self.swap_buffer(current);
+            self.swap_buffer(n);
+            self.current_buffer = n;
+        }
+
+        self.status_bar.msg = format!("Buffer {}: {}", n + 1, match self.filename {
+            Some(ref filename) => filename.clone(),
+            None => "[No Name]".to_string(),
+        });
+    }
+
+    /// Edit a file in a new buffer, or switch to the buffer it is open in
+    pub fn edit(&mut self, path: &str) {
+        for n in 0..self.buffers.len() {
+            let open = {
+                let filename = if n == self.current_buffer {
+                    &self.filename
+                } else {
+                    &self.buffers[n].filename
+                };
+                filename.as_ref().map_or(false, |f| f == path)
+            };
+            if open {
+                self.switch_buffer(n);
+                return;
+            }
+        }
+
+        self.buffers.push(Buffer::new());
+        let n = self.buffers.len() - 1;
+        self.switch_buffer(n);
+        self.open(path);
+    }
+
+    /// Goto the next buffer
+    pub fn next_buffer(&mut self) {
+        let n = (self.current_buffer + 1) % self.buffers.len();
+        self.switch_buffer(n);
+    }
+
+    /// Goto the previous buffer
+    pub fn previous_buffer(&mut self) {
+        let n = (self.current_buffer + self.buffers.len() - 1) % self.buffers.len();
+        self.switch_buffer(n);
+    }
+
+    /// Check if any buffer has unsaved changes
+    pub fn has_unsaved(&self) -> bool {
+        self.dirty || self.buffers.iter().enumerate().any(|(n, b)| n != self.current_buffer && b.dirty)
+    }
+
+    /// Quit the editor, unless there are unsaved changes and `force` is not set
+    pub fn quit(&mut self, force: bool) {
+        if force || !self.has_unsaved() {
+            self.running = false;
+        } else {
+            self.status_bar.msg = "No write since last change (add ! to override)".to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_switch_buffers() {
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_str("jl");
+
+        editor.type_str(":e file:///sodium_test_buffer.txt");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.buffers.len(), 2);
+        assert_eq!(editor.current_buffer, 1);
+        assert_eq!(editor.text_string(), "");
+        editor.type_str("ihello");
+        editor.keys(&[Key::Escape]);
+        editor.type_str("hh");
+        let pos = (editor.x(), editor.y());
+
+        // Each buffer keeps its own text and cursor
+        editor.type_str(":bp");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.text_string(), "one\ntwo\nthree");
+        assert_eq!((editor.x(), editor.y()), (1, 1));
+
+        editor.type_str(":bn");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.text_string(), "hello");
+        assert_eq!((editor.x(), editor.y()), pos);
+
+        // The changed buffer stops :q, even from the other one
+        editor.type_str(":b 1");
+        editor.keys(&[Key::Enter]);
+        assert!(! editor.dirty);
+        editor.type_str(":q");
+        editor.keys(&[Key::Enter]);
+        assert!(editor.running);
+        editor.type_str(":q!");
+        editor.keys(&[Key::Enter]);
+        assert!(! editor.running);
+    }
+}
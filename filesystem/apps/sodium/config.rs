@@ -0,0 +1,91 @@
+use super::*;
+use redox::*;
+
+/// The file the settings and key bindings are loaded from
+pub const CONFIG_PATH: &'static str = "file:///home/.sodiumrc";
+
+impl Editor {
+    /// Load the config file, if there is one
+    pub fn load_config(&mut self) {
+        if let Some(mut file) = File::open(CONFIG_PATH) {
+            let mut string = String::new();
+            file.read_to_string(&mut string);
+            self.apply_config(&string);
+        }
+    }
+
+    /// Apply config lines, such as `set number`, `set shiftwidth 8` or `map F5 :w`.
+    /// Lines that are not understood are reported in the status bar.
+    pub fn apply_config(&mut self, config: &str) {
+        let mut errors = Vec::new();
+
+        for (n, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            let ok = match (args[0], args.len()) {
+                ("set", 2) => self.set_option(args[1]),
+                ("set", 3) if self.options.get_num_mut(args[1]).is_some() => {
+                    self.set_option(&format!("{}={}", args[1], args[2]))
+                },
+                ("set", 3) => match (self.options.get_mut(args[1]), args[2]) {
+                    (Some(option), "on") | (Some(option), "true") => {
+                        *option = true;
+                        true
+                    },
+                    (Some(option), "off") | (Some(option), "false") => {
+                        *option = false;
+                        true
+                    },
+                    _ => false,
+                },
+                ("map", len) if len > 2 => match Key::from_name(args[1]) {
+                    Some(key) => {
+                        self.bind(key, &args[2..].join(" "));
+                        true
+                    },
+                    None => false,
+                },
+                _ => false,
+            };
+
+            if ! ok {
+                errors.push(format!("{}: {}", n + 1, line));
+            }
+        }
+
+        if let Some(error) = errors.first() {
+            self.status_bar.msg = format!("Bad config line {} ({} in total)", error, errors.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_apply_config() {
+        let mut editor = Editor::with_text("");
+        editor.apply_config("set number\nmap F5 :w");
+        assert!(editor.options.number);
+        assert_eq!(editor.bindings.len(), 1);
+        assert!(editor.bindings[0].key == Key::F(5));
+        assert_eq!(editor.bindings[0].cmd, ":w");
+        assert_eq!(editor.status_bar.msg, "");
+
+        // Numeric options take a value, after a space or an equals sign
+        editor.apply_config("# indent\nset shiftwidth 8\n\nset noautoindent");
+        assert_eq!(editor.options.shiftwidth, 8);
+        assert!(! editor.options.autoindent);
+        editor.apply_config("set sw=2");
+        assert_eq!(editor.options.shiftwidth, 2);
+
+        editor.apply_config("set shiftwidth wide\nset number maybe");
+        assert_eq!(editor.options.shiftwidth, 2);
+        assert_eq!(editor.status_bar.msg, "Bad config line 1: set shiftwidth wide (2 in total)");
+    }
+}
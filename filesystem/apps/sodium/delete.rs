@@ -1,17 +1,154 @@
 use super::*;
+use redox::*;
 
 impl Editor {
-    /// Delete char
+    /// Delete the char under the cursor, joining the next line at the line end
     #[inline]
     pub fn delete(&mut self) {
         let y = self.y();
         let x = self.x();
-        if self.text[y].len() == 0 {
-            if self.text.len() != 1 {
+        if x < self.text[y].len() {
+            self.text[y].remove(x);
+        } else if y + 1 < self.text.len() {
+            if let Some(next) = self.text.remove(y + 1) {
+                for c in next {
+                    self.text[y].push_back(c);
+                }
+            }
+        }
+    }
+
+    /// Delete a number of lines, starting at the cursor
+    pub fn remove_lines(&mut self, n: usize) {
+        let y = self.y();
+        for _ in 0..n {
+            if y >= self.text.len() {
+                break;
+            }
+            if self.text.len() > 1 {
                 self.text.remove(y);
+            } else {
+                self.text[0].clear();
             }
-        } else if x < self.text[y].len() {
-            self.text[y].remove(x);
         }
+
+        let y = self.ln_pos(y);
+        self.goto((0, y));
+    }
+
+    /// Delete n chars from the cursor on, without crossing the line end (`x`)
+    pub fn delete_chars(&mut self, n: usize) {
+        let y = self.y();
+        let x = self.x();
+        let end = cmp::min(x + n, self.text[y].len());
+        if x < end {
+            let text = self.text[y].iter().skip(x).take(end - x).map(|c| *c).collect();
+            self.yank(text, false);
+            for _ in x..end {
+                self.text[y].remove(x);
+            }
+        }
+    }
+
+    /// Delete n chars before the cursor, without crossing the line start (`X`)
+    pub fn delete_chars_before(&mut self, n: usize) {
+        let x = self.x();
+        let start = x - cmp::min(n, x);
+        if start < x {
+            let y = self.y();
+            self.goto((start, y));
+            self.delete_chars(x - start);
+        }
+    }
+
+    /// Join the following n lines onto the current one, separated by single spaces (`J`)
+    pub fn join_lines(&mut self, n: usize) {
+        let y = self.y();
+        let mut x = self.x();
+        for _ in 0..n {
+            let next = match self.text.remove(y + 1) {
+                Some(next) => next,
+                None => break,
+            };
+
+            // Drop trailing whitespace here and leading whitespace there, leaving one space
+            while self.text[y].back().map_or(false, |c| c.is_whitespace()) {
+                self.text[y].pop_back();
+            }
+            x = self.text[y].len();
+            let mut chars = next.into_iter().skip_while(|c| c.is_whitespace()).peekable();
+            if x > 0 && chars.peek().is_some() {
+                self.text[y].push_back(' ');
+            }
+            for c in chars {
+                self.text[y].push_back(c);
+            }
+        }
+
+        self.goto((x, y));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_join_lines() {
+        let mut editor = Editor::with_text("one  \n   two\nthree");
+        editor.type_str("J");
+        assert_eq!(editor.text_string(), "one two\nthree");
+        assert_eq!(editor.x(), 3);
+
+        let mut editor = Editor::with_text("one\ntwo\nthree\nfour");
+        editor.type_str("2J");
+        assert_eq!(editor.text_string(), "one two three\nfour");
+    }
+
+    #[test]
+    fn test_scroll_keys() {
+        let mut editor = Editor::with_text("a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\no\np\nq\nr\ns\nt");
+        editor.keys(&[Key::Ctrl(true), Key::Char('d'), Key::Ctrl(false)]);
+        assert_eq!(editor.y(), 15);
+        editor.keys(&[Key::Ctrl(true), Key::Char('u'), Key::Ctrl(false)]);
+        assert_eq!(editor.y(), 0);
+        assert!(editor.history.undo.is_empty());
+    }
+
+    #[test]
+    fn test_delete_chars() {
+        // `x` stops at the line end instead of joining the next line
+        let mut editor = Editor::with_text("abc\ndef");
+        editor.type_str("l5x");
+        assert_eq!(editor.text_string(), "a\ndef");
+        assert_eq!(editor.register('"').map(|r| &r.text[..]), Some("bc"));
+
+        // `X` stops at the line start
+        editor.type_str("jlX");
+        assert_eq!(editor.text_string(), "a\ndf");
+        assert_eq!(editor.register('"').map(|r| &r.text[..]), Some("e"));
+        editor.type_str("9X");
+        assert_eq!(editor.text_string(), "a\nf");
+        editor.type_str("HX");
+        assert_eq!(editor.text_string(), "a\nf");
+        assert_eq!(editor.register('"').map(|r| &r.text[..]), Some("d"));
+    }
+
+    #[test]
+    fn test_delete_key() {
+        // In normal mode, Delete is `x`
+        let mut editor = Editor::with_text("abcdef\nghi");
+        editor.keys(&[Key::Delete]);
+        assert_eq!(editor.text_string(), "bcdef\nghi");
+        editor.keys(&[Key::Char('2'), Key::Delete]);
+        assert_eq!(editor.text_string(), "def\nghi");
+
+        // In insert mode, it deletes the char under the cursor, joining lines at the end
+        editor.type_str("Li");
+        editor.keys(&[Key::Delete]);
+        assert_eq!(editor.text_string(), "defghi");
+        editor.keys(&[Key::Delete, Key::Escape]);
+        assert_eq!(editor.text_string(), "defhi");
+        assert_eq!(editor.history.undo.len(), 3);
     }
 }
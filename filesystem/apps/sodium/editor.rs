@@ -13,12 +13,48 @@ pub struct Editor {
     pub scroll_x: usize,
     /// The y coordinate of the scroll
     pub scroll_y: usize,
+    /// What was drawn on the screen by the last redraw
+    pub screen: Option<Screen>,
     /// The window
     pub window: Window,
     /// The key state
     pub key_state: KeyState,
     /// The status bar
     pub status_bar: StatusBar,
+    /// The file the text is saved to
+    pub filename: Option<String>,
+    /// The undo history
+    pub history: History,
+    /// Whether the text was changed since it was last saved
+    pub dirty: bool,
+    /// The marks
+    pub marks: Vec<Mark>,
+    /// The buffers
+    pub buffers: Vec<Buffer>,
+    /// The index of the active buffer
+    pub current_buffer: usize,
+    /// Whether the editor is running
+    pub running: bool,
+    /// The key bindings
+    pub bindings: Vec<Binding>,
+    /// The last searched pattern
+    pub last_search: Option<String>,
+    /// Whether the matches of the last search are highlighted, until `:noh`
+    pub search_highlight: bool,
+    /// The registers
+    pub registers: Vec<Register>,
+    /// The register selected with `"` for the next yank or paste
+    pub selected_register: Option<char>,
+    /// The options
+    pub options: Options,
+    /// The instructions of the change in progress
+    pub recording: Vec<Inst>,
+    /// The instructions of the last change, for `.`
+    pub last_change: Vec<Inst>,
+    /// Instructions to be replayed instead of reading the keyboard
+    pub replay: VecDeque<Inst>,
+    /// Keys typed ahead, read before the keyboard
+    pub typed: VecDeque<Key>,
 }
 
 impl Editor {
@@ -31,31 +67,71 @@ impl Editor {
                                  500,
                                  &"Sodium").unwrap();
 
+        let mut editor = Editor::with_window(*window);
+
+        editor.load_config();
+
+        if let Some(path) = args().get(1) {
+            editor.open(path);
+        }
+
+        editor.redraw();
+        while editor.running {
+            let inp = editor.next_inst();
+            editor.step(inp);
+            editor.status_bar.mode = editor.cursor().mode.to_string();
+            editor.redraw();
+        }
+
+        editor
+    }
+
+    /// Create an editor with an empty text, drawing to a window
+    pub fn with_window(window: Window) -> Editor {
         let mut editor = Editor {
             current_cursor: 0,
             cursors: Vec::new(),
             text: VecDeque::new(),
             scroll_x: 0,
             scroll_y: 0,
-            window: *window,
+            screen: None,
+            window: window,
             key_state: KeyState::new(),
             status_bar: StatusBar::new(),
+            filename: None,
+            history: History::new(),
+            dirty: false,
+            marks: Vec::new(),
+            buffers: vec![Buffer::new()],
+            current_buffer: 0,
+            running: true,
+            bindings: Vec::new(),
+            last_search: None,
+            search_highlight: false,
+            registers: Vec::new(),
+            selected_register: None,
+            options: Options::new(),
+            recording: Vec::new(),
+            last_change: Vec::new(),
+            replay: VecDeque::new(),
+            typed: VecDeque::new(),
         };
 
         editor.cursors.push(Cursor::new());
         editor.text.push_back(VecDeque::new());
 
-        editor.redraw();
-        loop {
-            let inp = editor.next_inst();
-            editor.exec(inp);
-            editor.redraw();
-            editor.status_bar.mode = editor.cursor().mode.to_string();
-        }
-
         editor
     }
 
+    /// Execute an instruction, undoing whatever it changes as one step
+    pub fn step(&mut self, inst: Inst) {
+        if self.mutates(inst) {
+            self.begin_change();
+        }
+        self.exec(inst);
+        self.end_change();
+    }
+
     /// Get a slice of the current line
     pub fn get_ln(&self, n: usize) -> &[char] {
         self.text[n].as_slices().0
@@ -64,8 +140,7 @@ impl Editor {
     /// Get the leading whitespaces
     pub fn get_indent(&self, n: usize) -> VecDeque<char> {
         let mut ind = VecDeque::new();
-        let ln = self.get_ln(n);
-        for &c in ln {
+        for &c in self.text[n].iter() {
             match c {
                 '\t' | ' ' => ind.push_back(c),
                 _ => break,
@@ -75,3 +150,35 @@ impl Editor {
     }
 }
 
+
+#[cfg(test)]
+impl Editor {
+    /// Create an editor holding some text, for tests
+    pub fn with_text(text: &str) -> Editor {
+        let window = Window::new(0, 0, 100, 100, &"Sodium").unwrap();
+        let mut editor = Editor::with_window(*window);
+        editor.text = text.split('\n').map(|ln| ln.chars().collect()).collect();
+        editor
+    }
+
+    /// Execute keys as if they were typed
+    pub fn keys(&mut self, keys: &[Key]) {
+        self.typed.extend(keys.iter().cloned());
+        while ! self.typed.is_empty() {
+            let inst = self.next_inst();
+            self.step(inst);
+        }
+    }
+
+    /// Execute the chars of a string as if they were typed
+    pub fn type_str(&mut self, s: &str) {
+        let keys: Vec<Key> = s.chars().map(|c| Key::Char(c)).collect();
+        self.keys(&keys);
+    }
+
+    /// Get the text, with the lines joined by newlines
+    pub fn text_string(&self) -> String {
+        let lines: Vec<String> = self.text.iter().map(|ln| ln.iter().cloned().collect()).collect();
+        lines.join("\n")
+    }
+}
@@ -21,6 +21,10 @@ impl Editor {
             self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
         } else if self.key_state.alt && cmd == Key::Char(' ') {
             self.next_cursor();
+        } else if let F(_) = cmd {
+            if !self.invoke_binding(cmd) {
+                self.status_bar.msg = "Key not bound".to_string();
+            }
         } else if self.key_state.alt {
             let new_pos = self.to_motion(Inst(para, cmd));
             self.goto(new_pos);
@@ -34,63 +38,161 @@ impl Editor {
                             }));
 
                     },
-                    Char('o') => {
-                        // TODO: Autoindent (keep the same indentation level)
-                        let y = self.y();
-                        let ind = self.get_indent(y);
-                        let last = ind.len();
-                        self.text.insert(y + 1, ind);
-                        self.goto((last, y + 1));
-                        self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Insert(
-                            InsertOptions {
-                                mode: InsertMode::Insert,
-                            }));
-                    }
+                    Char('o') => self.open_line(true),
+                    Char('O') => self.open_line(false),
                     Char('h') => self.goto_left(n),
                     Char('j') => self.goto_down(n),
                     Char('k') => self.goto_up(n),
                     Char('l') => self.goto_right(n),
-                    Char('J') => self.goto_down(15),
-                    Char('K') => self.goto_up(15),
-                    Char('x') => self.delete(),
-                    Char('X') => {
-                        self.goto_previous();
-                        self.delete();
-                    },
+                    Char('J') => self.join_lines(n),
+                    Char('x') | Delete => self.delete_chars(n),
+                    Char('X') => self.delete_chars_before(n),
                     Char('L') => self.goto_ln_end(),
                     Char('H') => self.cursor_mut().x = 0,
+                    Char('.') => self.repeat(para),
+                    Char('u') if self.key_state.ctrl => self.goto_up(15),
+                    Char('u') => self.undo(),
+                    Char('r') if self.key_state.ctrl => self.redo(),
                     Char('r') => {
-                        loop {
-                            if let EventOption::Key(k) = self.window.poll()
-                                                         .unwrap_or(Event::new())
-                                                         .to_option() {
-                                if k.pressed {
-                                    let x = self.x();
-                                    let y = self.y();
-                                    self.text[y][x] = k.character;
-                                    break;
-                                }
+                        // Replace n chars, if the line has that many left
+                        let c = self.next_char();
+                        let x = self.x();
+                        let y = self.y();
+                        if x + n <= self.text[y].len() {
+                            for i in x..x + n {
+                                self.text[y][i] = c;
                             }
+                            self.goto((x + n - 1, y));
                         }
                     },
+                    Char('m') => {
+                        let c = self.next_char();
+                        self.set_mark(c);
+                    },
+                    Char('`') => {
+                        let c = self.next_char();
+                        self.goto_mark(c, true);
+                    },
+                    Char('\'') => {
+                        let c = self.next_char();
+                        self.goto_mark(c, false);
+                    },
                     Char('R') => {
                         self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Insert(
                             InsertOptions {
                                 mode: InsertMode::Replace,
                             }));
                     },
+                    Char('d') if self.key_state.ctrl => self.goto_down(15),
                     Char('d') => {
-                        let ins = self.next_inst();
-                        let motion = self.to_motion(ins);
-                        self.remove_rb(motion);
+                        let Inst(m, motion_cmd) = self.next_inst();
+                        // The counts of the operator and the motion multiply (`2d3j`)
+                        let count = Parameter::Int(cmp::min(n.saturating_mul(m.d()), MAX_COUNT));
+                        if motion_cmd == Char('d') {
+                            self.yank_lines(count.d());
+                            self.remove_lines(count.d());
+                        } else if motion_cmd == Char('i') || motion_cmd == Char('a') {
+                            let c = self.next_char();
+                            self.text_object_op('d', motion_cmd == Char('i'), c);
+                        } else {
+                            let motion = self.to_motion(Inst(count, motion_cmd));
+                            self.yank_rb(motion);
+                            self.remove_rb(motion);
+                            let y = cmp::min(self.y(), self.text.len() - 1);
+                            self.cursor_mut().y = y;
+                        }
+                    },
+                    Char('y') => {
+                        let Inst(m, motion_cmd) = self.next_inst();
+                        let count = Parameter::Int(cmp::min(n.saturating_mul(m.d()), MAX_COUNT));
+                        if motion_cmd == Char('y') {
+                            self.yank_lines(count.d());
+                        } else if motion_cmd == Char('i') || motion_cmd == Char('a') {
+                            let c = self.next_char();
+                            self.text_object_op('y', motion_cmd == Char('i'), c);
+                        } else {
+                            let motion = self.to_motion(Inst(count, motion_cmd));
+                            self.yank_rb(motion);
+                        }
+                    },
+                    Char('c') => {
+                        let Inst(m, motion_cmd) = self.next_inst();
+                        let count = Parameter::Int(cmp::min(n.saturating_mul(m.d()), MAX_COUNT));
+                        if motion_cmd == Char('c') {
+                            // Clear the lines, leaving one to insert on
+                            self.yank_lines(count.d());
+                            let y = self.y();
+                            for _ in 1..count.d() {
+                                if y + 1 < self.text.len() {
+                                    self.text.remove(y + 1);
+                                }
+                            }
+                            self.text[y].clear();
+                            self.goto((0, y));
+                        } else if motion_cmd == Char('i') || motion_cmd == Char('a') {
+                            let c = self.next_char();
+                            self.text_object_op('c', motion_cmd == Char('i'), c);
+                        } else {
+                            let mut motion = self.to_motion(Inst(count, motion_cmd));
+                            // As in Vim, `cw` on a word changes to its end, keeping the space after it
+                            if motion_cmd == Char('w') && char_class(self.char_at((self.x(), self.y()))) != CharClass::Space {
+                                let (x, y) = self.to_motion(Inst(count, Char('e')));
+                                if y == self.y() {
+                                    motion = (x + 1, y);
+                                }
+                            }
+                            self.yank_rb(motion);
+                            self.remove_rb(motion);
+                            let y = cmp::min(self.y(), self.text.len() - 1);
+                            self.cursor_mut().y = y;
+                        }
+                        self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Insert(
+                            InsertOptions {
+                                mode: InsertMode::Insert,
+                            }));
                     },
-                    Char('G') => {
-                        let last = self.text.len() - 1;
-                        self.goto((0, last));
+                    Char('>') | Char('<') => {
+                        // Shift n lines (`>>`), or the lines a motion covers (`>j`)
+                        let Inst(m, motion_cmd) = self.next_inst();
+                        let y = self.y();
+                        if motion_cmd == cmd {
+                            let count = cmp::min(n.saturating_mul(m.d()), MAX_COUNT);
+                            self.shift_lines(y, y + count - 1, cmd == Char('>'));
+                        } else {
+                            let count = Parameter::Int(cmp::min(n.saturating_mul(m.d()), MAX_COUNT));
+                            let (_, to) = self.to_motion(Inst(count, motion_cmd));
+                            self.shift_lines(cmp::min(y, to), cmp::max(y, to), cmd == Char('>'));
+                        }
+                    },
+                    Char('C') => {
+                        // Change to the line end, like `c$`
+                        let len = self.text[self.y()].len();
+                        let x = self.x();
+                        if x < len {
+                            self.delete_chars(len - x);
+                        }
+                        self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Insert(
+                            InsertOptions {
+                                mode: InsertMode::Insert,
+                            }));
+                    },
+                    Char('p') => self.paste(true),
+                    Char('P') => self.paste(false),
+                    Char('"') => {
+                        if let Inst(_, Char(c)) = self.next_inst() {
+                            self.selected_register = Some(c);
+                        }
+                    },
+                    Char('G') => match para {
+                        Parameter::Int(n) => self.goto_line(n),
+                        Parameter::Null => {
+                            let last = self.text.len();
+                            self.goto_line(last);
+                        },
                     },
                     Char('g') => {
                         if let Parameter::Int(n) = para {
-                            self.goto((0, n - 1));
+                            self.goto_line(n);
                         } else {
                             let inst = self.next_inst();
                             let new = self.to_motion(inst);
@@ -99,7 +201,7 @@ impl Editor {
                         }
 
                     },
-                    Char('b') => {
+                    Char('b') if self.key_state.ctrl => {
                         // Branch cursor
                         let cursor = self.cursor().clone();
                         self.cursors.push(cursor);
@@ -117,12 +219,130 @@ impl Editor {
 //                        self.goto((0, self.text.len() - 1));
 //                    },
                     Char(' ') => self.goto_next(),
+                    Char('w') | Char('b') | Char('e') => {
+                        let p = self.to_motion(Inst(para, cmd));
+                        self.goto(p);
+                    },
+                    Home | End | PageUp | PageDown => {
+                        let p = self.to_motion(Inst(para, cmd));
+                        self.goto(p);
+                    },
+                    Enter => {
+                        self.goto_down(n);
+                        self.cursor_mut().x = 0;
+                    },
+                    Char('v') => {
+                        let anchor = (self.x(), self.y());
+                        self.cursor_mut().mode = Mode::Command(CommandMode::Visual(
+                            VisualOptions {
+                                anchor: anchor,
+                            }));
+                    },
+                    Char(':') => {
+                        self.status_bar.cmd = ":".to_string();
+                        self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Prompt);
+                    },
+                    Char('/') => {
+                        self.status_bar.cmd = "/".to_string();
+                        self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Prompt);
+                    },
+                    Char('n') => self.search_next(true),
+                    Char('N') => self.search_next(false),
                     _ => {},
                 },
-                Primitive(Insert(opt)) => {
-                    self.insert(cmd, opt);
+                Command(Visual(_)) => match cmd {
+                    Char('d') | Char('x') => {
+                        if let Some((start, end)) = self.selection() {
+                            let text = self.get_range(start, end);
+                            self.yank(text, false);
+                        }
+                        self.remove_selection();
+                    },
+                    Char('"') => {
+                        if let Inst(_, Char(c)) = self.next_inst() {
+                            self.selected_register = Some(c);
+                        }
+                    },
+                    Char('y') => {
+                        if let Some((start, end)) = self.selection() {
+                            let text = self.get_range(start, end);
+                            self.yank(text, false);
+                        }
+                        self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
+                    },
+                    Char('>') | Char('<') => {
+                        // Shift the selected lines n times
+                        if let Some(((_, start), (_, end))) = self.selection() {
+                            for _ in 0..n {
+                                self.shift_lines(start, end, cmd == Char('>'));
+                            }
+                        }
+                        self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
+                    },
+                    Escape => self.cursor_mut().mode = Mode::Command(CommandMode::Normal),
+                    _ => {
+                        let p = self.to_motion(Inst(para, cmd));
+                        self.goto(p);
+                    },
+                },
+                Primitive(Insert(opt)) => match cmd {
+                    Home | End | PageUp | PageDown => {
+                        let p = self.to_motion(Inst(para, cmd));
+                        self.goto(p);
+                    },
+                    _ => self.insert(cmd, opt),
+                },
+                Primitive(Prompt) => {
+                    self.prompt(cmd);
                 },
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_change_word() {
+        let mut editor = Editor::with_text("foo bar");
+        editor.type_str("cwbaz");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "baz bar");
+
+        // The change is undone as one step
+        assert_eq!(editor.history.undo.len(), 1);
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "foo bar");
+
+        let mut editor = Editor::with_text("one two three");
+        editor.type_str("c2wx");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "x three");
+    }
+
+    #[test]
+    fn test_change_lines() {
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_str("jccnew");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "one\nnew\nthree");
+        assert_eq!(editor.history.undo.len(), 1);
+
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_str("2ccnew");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "new\nthree");
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn test_change_to_end() {
+        let mut editor = Editor::with_text("hello world");
+        editor.type_str("wCthere");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "hello there");
+    }
+}
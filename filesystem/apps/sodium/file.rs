@@ -0,0 +1,135 @@
+use super::*;
+use redox::*;
+
+impl Editor {
+    /// Open a file, starting with empty text if it does not exist
+    pub fn open(&mut self, path: &str) {
+        self.text = VecDeque::new();
+        self.marks.clear();
+
+        match File::open(path) {
+            Some(mut file) => {
+                // Decode the whole file at once, so multi-byte chars are never split
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes);
+                let string = String::from_utf8_lossy(&bytes);
+                for ln in string.split('\n') {
+                    self.text.push_back(ln.chars().collect());
+                }
+                self.status_bar.msg = format!("Read {} lines from {}", self.text.len(), path);
+            },
+            None => {
+                self.text.push_back(VecDeque::new());
+                self.status_bar.msg = "New file ".to_string() + path;
+            },
+        }
+
+        self.filename = Some(path.to_string());
+        self.dirty = false;
+        self.goto((0, 0));
+    }
+
+    /// Save the text to a file, by default the file it was opened from
+    pub fn save(&mut self, path: Option<&str>) {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => match self.filename {
+                Some(ref filename) => filename.clone(),
+                None => {
+                    self.status_bar.msg = "No file name".to_string();
+                    return;
+                }
+            },
+        };
+
+        let mut string = String::new();
+        for (n, ln) in self.text.iter().enumerate() {
+            if n > 0 {
+                string.push('\n');
+            }
+            for &c in ln.iter() {
+                string.push(c);
+            }
+        }
+
+        match File::create(&path) {
+            Some(mut file) => {
+                if file.write(string.as_bytes()) == Some(string.len()) && file.sync() {
+                    self.status_bar.msg = format!("Wrote {} lines to {}", self.text.len(), path);
+                    if self.filename.is_none() {
+                        self.filename = Some(path.clone());
+                    }
+                    if self.filename == Some(path) {
+                        self.dirty = false;
+                    }
+                } else {
+                    self.status_bar.msg = "Could not write ".to_string() + &path;
+                }
+            },
+            None => self.status_bar.msg = "Could not create ".to_string() + &path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_save_then_reload() {
+        let path = "file:///sodium_test_save.txt";
+        let mut editor = Editor::with_text("xone\n    two\n\nthree ü");
+        editor.type_str("x");
+        assert!(editor.dirty);
+
+        editor.type_str(":w ");
+        editor.type_str(path);
+        editor.keys(&[Key::Enter]);
+        assert!(! editor.dirty);
+        assert_eq!(editor.filename.as_ref().map(|f| &f[..]), Some(path));
+
+        let mut reloaded = Editor::with_text("");
+        reloaded.open(path);
+        assert_eq!(reloaded.text_string(), editor.text_string());
+        assert_eq!(reloaded.text_string(), "one\n    two\n\nthree ü");
+    }
+
+    #[test]
+    fn test_open() {
+        let path = "file:///sodium_test_open.txt";
+        let mut editor = Editor::with_text("first\nsecond");
+        editor.save(Some(path));
+
+        let mut editor = Editor::with_text("other\ntext");
+        editor.type_str("jl");
+        editor.open(path);
+        assert_eq!(editor.text_string(), "first\nsecond");
+        assert_eq!(editor.filename.as_ref().map(|f| &f[..]), Some(path));
+        assert_eq!((editor.x(), editor.y()), (0, 0));
+        assert!(! editor.dirty);
+
+        // A file that does not exist yet is opened empty
+        let missing = "file:///sodium_test_missing.txt";
+        editor.open(missing);
+        assert_eq!(editor.text_string(), "");
+        assert!(editor.status_bar.msg.starts_with("New file"));
+        assert_eq!(editor.filename.as_ref().map(|f| &f[..]), Some(missing));
+    }
+
+    #[test]
+    fn test_multi_byte_chars() {
+        // Long enough that a char would be split if the file were decoded in pieces
+        let path = "file:///sodium_test_utf8.txt";
+        let mut editor = Editor::with_text("日本\n");
+        for _ in 0..3000 {
+            editor.text[1].push_back('é');
+        }
+        editor.save(Some(path));
+
+        let mut reloaded = Editor::with_text("");
+        reloaded.open(path);
+        assert_eq!(reloaded.text.len(), 2);
+        assert_eq!(reloaded.text_string(), editor.text_string());
+        assert!(reloaded.text[1].iter().all(|&c| c == 'é'));
+    }
+}
@@ -1,52 +1,270 @@
 use super::*;
 use redox::*;
 
+/// What was drawn on the screen, to find the rows that need to be drawn again
+pub struct Screen {
+    /// The visible lines
+    pub lines: Vec<VecDeque<char>>,
+    /// The first visible line
+    pub scroll: usize,
+    /// The cursor position
+    pub cursor: (usize, usize),
+    /// The selection
+    pub selection: Option<((usize, usize), (usize, usize))>,
+    /// The width of the gutter
+    pub gutter: usize,
+    /// Whether the first visible line starts inside a string
+    pub string: bool,
+    /// The pattern whose matches were highlighted
+    pub highlight: Option<String>,
+}
+
+/// Check if a line changes whether the following text is inside a string
+fn toggles_string(line: &VecDeque<char>) -> bool {
+    line.iter().filter(|&&c| c == '\'' || c == '"').count() % 2 == 1
+}
+
 impl Editor {
-    /// Redraw the window
+    /// Redraw the window, drawing only the rows that changed since the last redraw
     pub fn redraw(&mut self) {
-        // TODO: Only draw when relevant for the window
-        let x = self.x();
-        let y = self.y();
-        // Redraw window
-        self.window.set(Color::rgb(25, 25, 25));
-
-        self.window.rect(8 * (x - self.scroll_y) as isize,
-                         16 * (y - self.scroll_x) as isize,
-                         8,
-                         16,
-                         Color::WHITE);
-
-        let mut string = false;
-
-        for (y, row) in self.text.iter().enumerate() {
-            for (x, &c) in row.iter().enumerate() {
-                // TODO: Move outta here
-                let color = match c {
-                    '\'' | '"' => {
-                        string = !string;
-                        (226, 225, 167) //(167, 222, 156)
-                    },
-                    _ if string => (226, 225, 167), //(167, 222, 156)
-                    '!' | '@' | '#' | '$' | '%' | '^' | '&' | '|' | '*' | '+' | '-' | '/' | ':' | '=' | '<' | '>' => (198, 83, 83), //(228, 190, 175), //(194, 106, 71),
-                    '.' | ',' => (241, 213, 226),
-                    '(' | ')' | '[' | ']' | '{' | '}' => (164, 212, 125), //(195, 139, 75),
-                    '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => (209, 209, 177),
-                    _ => (255, 255, 255),
-                };
-
-                if self.x() == x && self.y() == y {
-                    self.window.char(8 * (x - self.scroll_y) as isize,
-                                     16 * (y - self.scroll_x) as isize,
-                                     c,
-                                     Color::rgb(color.0 / 3, color.1 / 3, color.2 / 3));
-                } else {
-                    self.window.char(8 * (x - self.scroll_y) as isize,
-                                     16 * (y - self.scroll_x) as isize,
-                                     c,
-                                     Color::rgb(color.0, color.1, color.2));
+        self.scroll_to_cursor();
+        let height = self.page_height();
+        let h = self.window.height();
+
+        let scroll = self.scroll_x;
+        let mut string = self.string_at(scroll);
+
+        let rows = self.dirty_rows(string);
+        match rows {
+            Some(ref rows) => {
+                // Move what is still visible along with the scroll, so only exposed rows are drawn
+                if let Some(ref screen) = self.screen {
+                    if screen.scroll < self.scroll_x {
+                        let d = self.scroll_x - screen.scroll;
+                        self.window.copy_rows(16 * d, 0, 16 * height.saturating_sub(d));
+                    } else if screen.scroll > self.scroll_x {
+                        let d = screen.scroll - self.scroll_x;
+                        self.window.copy_rows(0, 16 * d, 16 * height.saturating_sub(d));
+                    }
+                }
+
+                for row in 0..height {
+                    if rows.contains(&row) {
+                        self.draw_row(row, string);
+                    }
+                    if let Some(line) = self.text.get(self.scroll_x + row) {
+                        if toggles_string(line) {
+                            string = !string;
+                        }
+                    }
+                }
+            },
+            None => {
+                self.window.set(Color::rgb(25, 25, 25));
+                for row in 0..height {
+                    self.draw_row(row, string);
+                    if let Some(line) = self.text.get(self.scroll_x + row) {
+                        if toggles_string(line) {
+                            string = !string;
+                        }
+                    }
                 }
+            },
+        }
+
+        self.remember_screen();
+        self.draw_status_bar();
+
+        match rows {
+            Some(rows) => {
+                let mut sync = vec![(h - 18, 18)];
+                for row in rows {
+                    sync.push((16 * row, 16));
+                }
+                self.window.sync_rows(&sync);
+            },
+            None => {
+                self.window.sync();
+            },
+        }
+    }
+
+    /// Find the rows of the screen that need to be drawn again, or `None` if all of them do
+    ///
+    /// A row is drawn again if its line changed or scrolled into view, or if the cursor or
+    /// the selection was or is on it. Moving the cursor within the screen draws two rows.
+    /// Changing the highlighted search pattern draws all of them.
+    pub fn dirty_rows(&self, string: bool) -> Option<Vec<usize>> {
+        let screen = match self.screen {
+            Some(ref screen) => screen,
+            None => return None,
+        };
+        if screen.gutter != self.gutter_width() || screen.string != string ||
+           screen.highlight != self.highlight_pattern() {
+            return None;
+        }
+
+        let height = self.page_height();
+        let selection = self.selection();
+
+        // The lines whose highlighting may have changed
+        let mut highlighted = vec![screen.cursor.1, self.y()];
+        for sel in screen.selection.iter().chain(selection.iter()) {
+            let &((_, sy), (_, ey)) = sel;
+            for y in sy..ey + 1 {
+                highlighted.push(y);
+            }
+        }
+
+        let mut rows = Vec::new();
+        let mut rest = false;
+        for row in 0..height {
+            let y = self.scroll_x + row;
+            let old = if y >= screen.scroll && y < screen.scroll + height {
+                Some(screen.lines.get(y - screen.scroll))
+            } else {
+                None
+            };
+            let new = self.text.get(y);
+
+            let dirty = match old {
+                Some(old) => {
+                    if old != new {
+                        // Changing a quote changes the highlighting of the following lines
+                        let toggled = |line: Option<&VecDeque<char>>| line.map_or(false, |l| toggles_string(l));
+                        if toggled(old) != toggled(new) {
+                            rest = true;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                },
+                None => true,
+            };
+
+            if dirty || rest || highlighted.contains(&y) {
+                rows.push(row);
+            }
+        }
+
+        Some(rows)
+    }
+
+    /// Check if a line starts inside a string
+    fn string_at(&self, y: usize) -> bool {
+        self.text.iter().take(y).filter(|line| toggles_string(line)).count() % 2 == 1
+    }
+
+    /// Remember what was drawn, for the next redraw
+    fn remember_screen(&mut self) {
+        let height = self.page_height();
+        let scroll = self.scroll_x;
+        let string = self.string_at(scroll);
+
+        self.screen = Some(Screen {
+            lines: self.text.iter().skip(self.scroll_x).take(height).map(|l| l.clone()).collect(),
+            scroll: self.scroll_x,
+            cursor: (self.x(), self.y()),
+            selection: self.selection(),
+            gutter: self.gutter_width(),
+            string: string,
+            highlight: self.highlight_pattern(),
+        });
+    }
+
+    /// Draw a row of the screen, with the line scrolled to it
+    fn draw_row(&mut self, row: usize, mut string: bool) {
+        let w = self.window.width();
+        let gutter = self.gutter_width();
+        let y = self.scroll_x + row;
+
+        self.window.rect(0, 16 * row as isize, w, 16, Color::rgb(25, 25, 25));
+
+        if y >= self.text.len() {
+            return;
+        }
+
+        if y == self.y() {
+            let x = self.x();
+            self.window.rect(8 * self.screen_col(x) as isize,
+                             16 * row as isize,
+                             8,
+                             16,
+                             Color::WHITE);
+        }
+
+        if gutter > 0 {
+            let num = format!("{}", y + 1);
+            let color = if y == self.y() {
+                Color::rgb(255, 255, 255)
+            } else {
+                Color::rgb(128, 128, 128)
+            };
+            // Right-align, leaving a column of space before the text
+            for (n, c) in num.chars().enumerate() {
+                self.window.char(8 * (gutter - 1 - num.len() + n) as isize,
+                                 16 * row as isize,
+                                 c,
+                                 color);
             }
         }
+
+        // Changed lines are drawn again, so their matches are found again
+        let matches = match self.highlight_pattern() {
+            Some(pat) => self.search_matches(y, &pat),
+            None => Vec::new(),
+        };
+
+        for x in 0..self.text[y].len() {
+            let c = self.text[y][x];
+            // TODO: Move outta here
+            let color = match c {
+                '\'' | '"' => {
+                    string = !string;
+                    (226, 225, 167) //(167, 222, 156)
+                },
+                _ if string => (226, 225, 167), //(167, 222, 156)
+                '!' | '@' | '#' | '$' | '%' | '^' | '&' | '|' | '*' | '+' | '-' | '/' | ':' | '=' | '<' | '>' => (198, 83, 83), //(228, 190, 175), //(194, 106, 71),
+                '.' | ',' => (241, 213, 226),
+                '(' | ')' | '[' | ']' | '{' | '}' => (164, 212, 125), //(195, 139, 75),
+                '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => (209, 209, 177),
+                _ => (255, 255, 255),
+            };
+
+            let cursor = self.x() == x && self.y() == y;
+            if ! cursor && matches.iter().any(|&(start, end)| x >= start && x < end) {
+                self.window.rect(8 * self.screen_col(x) as isize,
+                                 16 * row as isize,
+                                 8,
+                                 16,
+                                 Color::rgb(110, 95, 40));
+            }
+
+            if self.is_selected(x, y) {
+                self.window.rect(8 * self.screen_col(x) as isize,
+                                 16 * row as isize,
+                                 8,
+                                 16,
+                                 Color::rgb(74, 74, 74));
+            }
+
+            if self.x() == x && self.y() == y {
+                self.window.char(8 * self.screen_col(x) as isize,
+                                 16 * row as isize,
+                                 c,
+                                 Color::rgb(color.0 / 3, color.1 / 3, color.2 / 3));
+            } else {
+                self.window.char(8 * self.screen_col(x) as isize,
+                                 16 * row as isize,
+                                 c,
+                                 Color::rgb(color.0, color.1, color.2));
+            }
+        }
+    }
+
+    /// Draw the status bar
+    fn draw_status_bar(&mut self) {
         let h = self.window.height();
         let w = self.window.width();
         self.window.rect(0, h as isize - 18, w, 18, Color::rgba(74, 74, 74, 255));
@@ -60,7 +278,68 @@ impl Editor {
             self.window.char(n as isize * 8, h as isize - 16 - 1, if c == '\t' { ' ' } else { c }, Color::WHITE);
         }
 
-        self.window.sync();
+        // The command being entered, or else the last message
+        let info = if self.status_bar.cmd.len() > 0 {
+            &self.status_bar.cmd
+        } else {
+            &self.status_bar.msg
+        };
+        for (n, c) in info.chars().take(w / 8 - w / (8 * 4)).enumerate() {
+            self.window.char((w / (8 * 4) + n) as isize * 8, h as isize - 16 - 1, if c == '\t' { ' ' } else { c }, Color::WHITE);
+        }
+
+        // The file and cursor position, aligned to the right
+        let pos = self.status_pos();
+        let start = (w / 8).saturating_sub(pos.len());
+        self.window.rect(start as isize * 8, h as isize - 18, w - start * 8, 18, Color::rgba(74, 74, 74, 255));
+        for (n, c) in pos.chars().enumerate() {
+            self.window.char((start + n) as isize * 8, h as isize - 16 - 1, c, Color::WHITE);
+        }
+    }
+
+    /// Scroll vertically so that the cursor is on the screen
+    pub fn scroll_to_cursor(&mut self) {
+        let y = self.y();
+        let height = self.page_height();
+        if y < self.scroll_x {
+            self.scroll_x = y;
+        } else if y >= self.scroll_x + height {
+            self.scroll_x = y + 1 - height;
+        }
+    }
+
+    /// Get the file name, dirty state and cursor position (line:column), as shown in the status bar
+    pub fn status_pos(&self) -> String {
+        format!("{}{} {}:{} ",
+                match self.filename {
+                    Some(ref filename) => filename.clone(),
+                    None => "[No Name]".to_string(),
+                },
+                if self.dirty { " [+]" } else { "" },
+                self.y() + 1,
+                self.x() + 1)
+    }
+}
+
+impl Editor {
+    /// Get the width of the line number gutter in chars (zero if disabled)
+    pub fn gutter_width(&self) -> usize {
+        if self.options.number {
+            let mut digits = 1;
+            let mut lines = self.text.len();
+            while lines >= 10 {
+                lines /= 10;
+                digits += 1;
+            }
+            digits + 1
+        } else {
+            0
+        }
+    }
+
+    /// Get the column of the screen that a char of a line is drawn at, right of the gutter
+    pub fn screen_col(&self, x: usize) -> usize {
+        self.gutter_width() + x - self.scroll_y
     }
 }
 
@@ -87,3 +366,56 @@ impl StatusBar {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use redox::*;
+
+    #[test]
+    fn test_gutter() {
+        let mut editor = Editor::with_text("a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl");
+        assert_eq!(editor.gutter_width(), 0);
+        assert_eq!(editor.screen_col(2), 2);
+
+        // Wide enough for the number of the last line, and a space
+        editor.type_str(":set number");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.gutter_width(), 3);
+        assert_eq!(editor.screen_col(0), 3);
+        assert_eq!(editor.screen_col(2), 5);
+
+        editor.type_str(":set nonumber");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.screen_col(2), 2);
+    }
+
+    #[test]
+    fn test_status_pos() {
+        let mut editor = Editor::with_text("one\ntwo");
+        assert_eq!(editor.status_pos(), "[No Name] 1:1 ");
+
+        editor.filename = Some("file:///notes.txt".to_string());
+        editor.type_str("jlx");
+        assert_eq!(editor.status_pos(), "file:///notes.txt [+] 2:2 ");
+
+        editor.type_str("v");
+        assert_eq!(editor.cursor().mode.to_string(), "Visual");
+    }
+
+    #[test]
+    fn test_dirty_rows() {
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        assert_eq!(editor.dirty_rows(false), None);
+        editor.redraw();
+
+        // Moving down a line draws the old and the new cursor row
+        editor.type_str("j");
+        assert_eq!(editor.dirty_rows(false), Some(vec![0, 1]));
+        editor.redraw();
+
+        // Changing a line draws it, with the cursor on it
+        editor.type_str("x");
+        assert_eq!(editor.dirty_rows(false), Some(vec![1]));
+    }
+}
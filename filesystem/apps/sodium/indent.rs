@@ -0,0 +1,65 @@
+use super::*;
+use redox::*;
+
+impl Editor {
+    /// Shift the lines from `start` to `end` (inclusive) by one shiftwidth (`>>` and `<<`)
+    ///
+    /// Indenting adds spaces to lines that are not empty. Dedenting removes up to a shiftwidth
+    /// of leading spaces, or a leading tab.
+    pub fn shift_lines(&mut self, start: usize, end: usize, right: bool) {
+        let width = self.options.shiftwidth;
+        let end = cmp::min(end, self.text.len() - 1);
+
+        for y in start..end + 1 {
+            if right {
+                if ! self.text[y].is_empty() {
+                    for _ in 0..width {
+                        self.text[y].push_front(' ');
+                    }
+                }
+            } else if self.text[y].front() == Some(&'\t') {
+                self.text[y].pop_front();
+            } else {
+                let mut i = 0;
+                while i < width && self.text[y].front() == Some(&' ') {
+                    self.text[y].pop_front();
+                    i += 1;
+                }
+            }
+        }
+
+        // Put the cursor on the first char of the first line
+        let x = self.text[start].iter().take_while(|c| c.is_whitespace()).count();
+        self.goto((x, start));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_shift_selection() {
+        let mut editor = Editor::with_text("a\nb\nc");
+        editor.type_str("vj>");
+        assert_eq!(editor.text_string(), "    a\n    b\nc");
+        assert!(editor.cursor().mode == Mode::Command(CommandMode::Normal));
+        assert_eq!((editor.x(), editor.y()), (4, 0));
+
+        editor.type_str("vj<");
+        assert_eq!(editor.text_string(), "a\nb\nc");
+
+        // With a count, and removing less than a shiftwidth if that is all there is
+        editor.type_str(":set sw=2");
+        editor.keys(&[Key::Enter]);
+        editor.type_str("3>>");
+        assert_eq!(editor.text_string(), "  a\n  b\n  c");
+        editor.type_str("j>>");
+        assert_eq!(editor.text_string(), "  a\n    b\n  c");
+        editor.type_str("gg");
+        editor.type_str("vjj<");
+        assert_eq!(editor.text_string(), "a\n  b\nc");
+        editor.type_str("vjj<");
+        assert_eq!(editor.text_string(), "a\nb\nc");
+    }
+}
@@ -23,22 +23,40 @@ pub struct InsertOptions {
 impl Editor {
     /// Insert text
     pub fn insert(&mut self, k: Key, InsertOptions { mode: mode }: InsertOptions) {
-        let mut x = self.x();
-        let mut y = self.y();
+        // The Tab key inserts a tab
+        let k = if k == Key::Tab {
+            Key::Char('\t')
+        } else {
+            k
+        };
+        let x = self.x();
+        let y = self.y();
         match mode {
             InsertMode::Insert => match k {
-                Key::Char('\n') => {
-                    let ln = self.text[y].clone();
-                    let (slice, _) = ln.as_slices();
+                Key::Enter | Key::Char('\n') => {
+                    let ln: Vec<char> = self.text[y].iter().map(|c| *c).collect();
+                    let (first_part, second_part) = ln.split_at(x);
 
-                    let first_part = (&slice[..x]).clone();
-                    let second_part = (&slice[x..]).clone();
-
-                    self.text[y] = VecDeque::from_iter(first_part.iter().map(|x| *x));
-
-                    let ind = self.get_indent(y);
+                    let (ind, second_part) = if self.options.autoindent {
+                        let ind = self.get_indent(y);
+                        // The new line gets the indent anyway, so drop the
+                        // whitespace it would otherwise start with
+                        let text_start = second_part.iter()
+                                                    .position(|&c| c != ' ' && c != '\t')
+                                                    .unwrap_or(second_part.len());
+                        (ind, &second_part[text_start..])
+                    } else {
+                        (VecDeque::new(), second_part)
+                    };
                     let begin = ind.len();
 
+                    // Splitting inside the indent leaves nothing but whitespace behind
+                    if first_part.iter().all(|&c| c == ' ' || c == '\t') && self.options.autoindent {
+                        self.text[y] = VecDeque::new();
+                    } else {
+                        self.text[y] = VecDeque::from_iter(first_part.iter().map(|x| *x));
+                    }
+
                     self.text.insert(y + 1, VecDeque::from_iter(
                             ind.into_iter().chain(second_part.iter().map(|x| *x))));
 
@@ -53,6 +71,7 @@ impl Editor {
                         self.delete();
                     }
                 },
+                Key::Delete => self.delete(),
                 Key::Char(c) => {
                     self.text[y].insert(x, c);
                     self.goto_next();
@@ -60,28 +79,42 @@ impl Editor {
                 _ => {},
             },
             InsertMode::Replace => match k {
-                Key::Char(c) => {
-                    if x == self.text[y].len() {
-                        self.goto_next();
-                        x = self.x();
-                        y = self.y();
-                    }
-
-                    if self.text.len() != y {
-                        if self.text[y].len() == x {
-                            self.goto_next();
-                        } else {
-                            self.text[y][x] = c;
-                        }
+                // Past the end of the line, chars are appended
+                Key::Char(c) if c != '\n' => {
+                    if x < self.text[y].len() {
+                        self.text[y][x] = c;
+                    } else {
+                        self.text[y].push_back(c);
                     }
-                    self.goto_next();
+                    self.goto((x + 1, y));
                 },
+                Key::Enter | Key::Char('\n') | Key::Escape | Key::Delete => {
+                    self.insert(k, InsertOptions { mode: InsertMode::Insert });
+                },
+                Key::Backspace => self.goto_left(1),
                 _ => {},
             },
             _ => {},
         }
     }
 
+    /// Open a new line below or above the current one, and start inserting on it (`o` and `O`)
+    pub fn open_line(&mut self, below: bool) {
+        let y = self.y();
+        let ind = if self.options.autoindent {
+            self.get_indent(y)
+        } else {
+            VecDeque::new()
+        };
+        let x = ind.len();
+        let new_y = if below { y + 1 } else { y };
+        self.text.insert(new_y, ind);
+        self.goto((x, new_y));
+        self.cursor_mut().mode = Mode::Primitive(PrimitiveMode::Insert(InsertOptions {
+            mode: InsertMode::Insert,
+        }));
+    }
+
     /// Insert a string
     pub fn insert_str(&mut self, txt: String, opt: InsertOptions) {
         for c in txt.chars() {
@@ -90,3 +123,107 @@ impl Editor {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_enter() {
+        // In insert mode, Enter splits the line, keeping the indent
+        let mut editor = Editor::with_text("    foo bar");
+        editor.type_str("7li");
+        editor.keys(&[Key::Enter, Key::Escape]);
+        assert_eq!(editor.text_string(), "    foo\n    bar");
+        assert_eq!(editor.y(), 1);
+
+        // In normal mode, it moves to the start of a following line
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_str("l");
+        editor.keys(&[Key::Enter]);
+        assert_eq!((editor.x(), editor.y()), (0, 1));
+        editor.type_str("l");
+        editor.keys(&[Key::Char('5'), Key::Enter]);
+        assert_eq!((editor.x(), editor.y()), (0, 2));
+        assert_eq!(editor.text_string(), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn test_autoindent() {
+        let mut editor = Editor::with_text("    foo");
+        editor.type_str("obar");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "    foo\n    bar");
+
+        // Splitting inside the indent leaves an empty line behind
+        editor.type_str("Hli");
+        editor.keys(&[Key::Enter, Key::Escape]);
+        assert_eq!(editor.text_string(), "    foo\n\n    bar");
+
+        let mut editor = Editor::with_text("    foo");
+        editor.type_str(":set noautoindent");
+        editor.keys(&[Key::Enter]);
+        editor.type_str("obar");
+        editor.keys(&[Key::Escape]);
+        editor.type_str("Li");
+        editor.keys(&[Key::Enter, Key::Escape]);
+        assert_eq!(editor.text_string(), "    foo\nbar\n");
+    }
+
+    #[test]
+    fn test_open_line() {
+        let insert = Mode::Primitive(PrimitiveMode::Insert(InsertOptions {
+            mode: InsertMode::Insert,
+        }));
+
+        // On the last line, o appends a line and starts inserting on it
+        let mut editor = Editor::with_text("one\ntwo");
+        editor.type_str("jo");
+        assert!(editor.cursor().mode == insert);
+        assert_eq!(editor.text_string(), "one\ntwo\n");
+        assert_eq!((editor.x(), editor.y()), (0, 2));
+
+        editor.type_str("three");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "one\ntwo\nthree");
+
+        // Opening the line and typing on it are undone together
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "one\ntwo");
+
+        editor.type_str("kOzero");
+        assert!(editor.cursor().mode == insert);
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "zero\none\ntwo");
+        assert_eq!(editor.y(), 0);
+    }
+
+    #[test]
+    fn test_replace() {
+        // r replaces chars under the cursor, but only if the line has that many left
+        let mut editor = Editor::with_text("abcd");
+        editor.type_str("rX");
+        assert_eq!(editor.text_string(), "Xbcd");
+        assert!(editor.cursor().mode == Mode::Command(CommandMode::Normal));
+        editor.type_str("l2rY");
+        assert_eq!(editor.text_string(), "XYYd");
+        assert_eq!(editor.x(), 2);
+        editor.type_str("5rZ");
+        assert_eq!(editor.text_string(), "XYYd");
+
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "Xbcd");
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "abcd");
+
+        // R overwrites chars, and appends past the end of the line
+        let mut editor = Editor::with_text("abc\ndef");
+        editor.type_str("lRxyz");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "axyz\ndef");
+
+        // The overwritten chars come back in one step
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "abc\ndef");
+    }
+}
@@ -1,3 +1,5 @@
+use redox::*;
+
 #[derive(Copy, Clone, PartialEq)]
 /// A key
 pub enum Key {
@@ -7,11 +9,166 @@ pub enum Key {
     Ctrl(bool),
     // TODO: Space modifier?
     Backspace,
+    Delete,
     Escape,
+    Enter,
     Left,
     Right,
     Up,
     Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
     Tab,
+    /// A function key (F1 to F12)
+    F(u8),
     Unknown(u8),
 }
+
+impl Key {
+    /// Decode a key from the start of raw terminal input, returning the number of bytes used.
+    /// An incomplete sequence gives `(None, 0)`, so that more input can be read first.
+    pub fn from_escape(bytes: &[u8]) -> (Option<Key>, usize) {
+        let b = match bytes.first() {
+            Some(&b) => b,
+            None => return (None, 0),
+        };
+
+        match b {
+            0x1B => match bytes.get(1) {
+                None => (Some(Key::Escape), 1),
+                Some(&b'[') => Key::from_csi(bytes),
+                Some(&b'O') => match bytes.get(2) {
+                    None => (None, 0),
+                    Some(&c) => (Some(match c {
+                        b'A' => Key::Up,
+                        b'B' => Key::Down,
+                        b'C' => Key::Right,
+                        b'D' => Key::Left,
+                        b'H' => Key::Home,
+                        b'F' => Key::End,
+                        b'P' ... b'S' => Key::F(c - b'P' + 1),
+                        _ => Key::Unknown(0x1B),
+                    }), 3),
+                },
+                Some(_) => (Some(Key::Escape), 1),
+            },
+            b'\t' => (Some(Key::Tab), 1),
+            b'\r' | b'\n' => (Some(Key::Enter), 1),
+            0x08 | 0x7F => (Some(Key::Backspace), 1),
+            0x20 ... 0x7E => (Some(Key::Char(b as char)), 1),
+            0xC0 ... 0xF7 => {
+                let len = if b < 0xE0 {
+                    2
+                } else if b < 0xF0 {
+                    3
+                } else {
+                    4
+                };
+                if bytes.len() < len {
+                    return (None, 0);
+                }
+                match str::from_utf8(&bytes[..len]).ok().and_then(|s| s.chars().next()) {
+                    Some(c) => (Some(Key::Char(c)), len),
+                    None => (Some(Key::Unknown(b)), 1),
+                }
+            },
+            _ => (Some(Key::Unknown(b)), 1),
+        }
+    }
+
+    /// Decode a control sequence, `ESC [` followed by parameters and a final byte
+    fn from_csi(bytes: &[u8]) -> (Option<Key>, usize) {
+        // Only the first parameter matters, the others are modifiers
+        let mut i = 2;
+        let mut param = 0u32;
+        let mut first = true;
+        loop {
+            match bytes.get(i) {
+                None => return (None, 0),
+                Some(&c) if c >= b'0' && c <= b'9' => if first {
+                    param = param.saturating_mul(10).saturating_add((c - b'0') as u32);
+                },
+                Some(&b';') => first = false,
+                Some(_) => break,
+            }
+            i += 1;
+        }
+
+        let key = match (bytes[i], param) {
+            (b'A', _) => Key::Up,
+            (b'B', _) => Key::Down,
+            (b'C', _) => Key::Right,
+            (b'D', _) => Key::Left,
+            (b'H', _) => Key::Home,
+            (b'F', _) => Key::End,
+            (b'~', 1) | (b'~', 7) => Key::Home,
+            (b'~', 4) | (b'~', 8) => Key::End,
+            (b'~', 3) => Key::Delete,
+            (b'~', 5) => Key::PageUp,
+            (b'~', 6) => Key::PageDown,
+            (b'~', 11 ... 15) => Key::F((param - 10) as u8),
+            (b'~', 17 ... 21) => Key::F((param - 11) as u8),
+            (b'~', 23) | (b'~', 24) => Key::F((param - 12) as u8),
+            _ => Key::Unknown(0x1B),
+        };
+
+        (Some(key), i + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_arrows() {
+        assert!(Key::from_escape(b"\x1B[A") == (Some(Key::Up), 3));
+        assert!(Key::from_escape(b"\x1B[B") == (Some(Key::Down), 3));
+        assert!(Key::from_escape(b"\x1B[C") == (Some(Key::Right), 3));
+        assert!(Key::from_escape(b"\x1B[D") == (Some(Key::Left), 3));
+        assert!(Key::from_escape(b"\x1BOA") == (Some(Key::Up), 3));
+        assert!(Key::from_escape(b"\x1BOD") == (Some(Key::Left), 3));
+
+        // Modifiers after the first parameter are ignored
+        assert!(Key::from_escape(b"\x1B[1;5C") == (Some(Key::Right), 6));
+
+        // Only the first key is decoded
+        assert!(Key::from_escape(b"\x1B[Ax") == (Some(Key::Up), 3));
+    }
+
+    #[test]
+    fn test_special_keys() {
+        assert!(Key::from_escape(b"\x1B[3~") == (Some(Key::Delete), 4));
+        assert!(Key::from_escape(b"\x1B[5~") == (Some(Key::PageUp), 4));
+        assert!(Key::from_escape(b"\x1B[H") == (Some(Key::Home), 3));
+        assert!(Key::from_escape(b"\x1B[4~") == (Some(Key::End), 4));
+        assert!(Key::from_escape(b"\x1BOP") == (Some(Key::F(1)), 3));
+        assert!(Key::from_escape(b"\x1B[24~") == (Some(Key::F(12)), 5));
+        assert!(Key::from_escape(b"\x1B") == (Some(Key::Escape), 1));
+        assert!(Key::from_escape(b"\x1Bx") == (Some(Key::Escape), 1));
+        assert!(Key::from_escape(b"\t") == (Some(Key::Tab), 1));
+        assert!(Key::from_escape(b"\r") == (Some(Key::Enter), 1));
+        assert!(Key::from_escape(b"\x7F") == (Some(Key::Backspace), 1));
+    }
+
+    #[test]
+    fn test_chars() {
+        assert!(Key::from_escape(b"a") == (Some(Key::Char('a')), 1));
+        assert!(Key::from_escape(b"ab") == (Some(Key::Char('a')), 1));
+        assert!(Key::from_escape(b" ") == (Some(Key::Char(' ')), 1));
+        assert!(Key::from_escape("é".as_bytes()) == (Some(Key::Char('é')), 2));
+        assert!(Key::from_escape("€".as_bytes()) == (Some(Key::Char('€')), 3));
+        assert!(Key::from_escape(b"\x01") == (Some(Key::Unknown(1)), 1));
+    }
+
+    #[test]
+    fn test_incomplete() {
+        assert!(Key::from_escape(b"") == (None, 0));
+        assert!(Key::from_escape(b"\x1B[") == (None, 0));
+        assert!(Key::from_escape(b"\x1B[1;5") == (None, 0));
+        assert!(Key::from_escape(b"\x1BO") == (None, 0));
+        assert!(Key::from_escape(&"€".as_bytes()[.. 2]) == (None, 0));
+    }
+}
@@ -0,0 +1,99 @@
+use super::*;
+use redox::*;
+
+#[derive(Clone, Copy)]
+/// A mark, set with `m`
+pub struct Mark {
+    /// The name of the mark
+    pub name: char,
+    /// The x coordinate
+    pub x: usize,
+    /// The y coordinate
+    pub y: usize,
+}
+
+impl Editor {
+    /// Set a mark at the cursor
+    pub fn set_mark(&mut self, name: char) {
+        if name < 'a' || name > 'z' {
+            self.status_bar.msg = format!("Invalid mark: {}", name);
+            return;
+        }
+
+        let (x, y) = (self.x(), self.y());
+        self.marks.retain(|m| m.name != name);
+        self.marks.push(Mark {
+            name: name,
+            x: x,
+            y: y,
+        });
+    }
+
+    /// Jump to a mark, either to its exact position (`` ` ``) or to the start of its line (`'`)
+    pub fn goto_mark(&mut self, name: char, exact: bool) {
+        let pos = self.marks.iter().find(|m| m.name == name).map(|m| (m.x, m.y));
+        match pos {
+            Some((x, y)) => {
+                let y = self.ln_pos(y);
+                self.goto((if exact { x } else { 0 }, y));
+            },
+            None => self.status_bar.msg = format!("Mark not set: {}", name),
+        }
+    }
+
+    /// Move the marks along with their lines, after the text changed from `old` to the current text
+    pub fn shift_marks(&mut self, old: &VecDeque<VecDeque<char>>) {
+        let old_len = old.len();
+        let new_len = self.text.len();
+
+        // The lines which are the same at the start and at the end enclose the changed ones
+        let mut start = 0;
+        while start < old_len && start < new_len && old[start] == self.text[start] {
+            start += 1;
+        }
+        let mut end = 0;
+        while end < old_len - start && end < new_len - start &&
+              old[old_len - 1 - end] == self.text[new_len - 1 - end] {
+            end += 1;
+        }
+
+        for mark in self.marks.iter_mut() {
+            if mark.y >= old_len - end {
+                mark.y = mark.y + new_len - old_len;
+            } else if mark.y >= new_len - end {
+                // The line of the mark was removed
+                mark.y = cmp::max(new_len - end, 1) - 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_marks() {
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_str("jjllma");
+        editor.type_str("gg");
+        assert_eq!((editor.x(), editor.y()), (0, 0));
+
+        // A line inserted above the mark moves it down
+        editor.type_str("Ozero");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "zero\none\ntwo\nthree");
+        editor.type_str("`a");
+        assert_eq!((editor.x(), editor.y()), (2, 3));
+        editor.type_str("gg'a");
+        assert_eq!((editor.x(), editor.y()), (0, 3));
+
+        // And a line deleted above it moves it back up
+        editor.type_str("ggdd`a");
+        assert_eq!(editor.text_string(), "one\ntwo\nthree");
+        assert_eq!((editor.x(), editor.y()), (2, 2));
+
+        editor.type_str("`b");
+        assert_eq!(editor.status_bar.msg, "Mark not set: b");
+    }
+}
@@ -18,7 +18,10 @@ impl Mode {
         use self::CommandMode::*;
         match self {
             Command(Normal) => "Normal",
+            Command(Visual(_)) => "Visual",
+            Primitive(Insert(InsertOptions { mode: InsertMode::Replace })) => "Replace",
             Primitive(Insert(_)) => "Insert",
+            Primitive(Prompt) => "Prompt",
         }.to_string()
     }
 }
@@ -26,7 +29,8 @@ impl Mode {
 #[derive(Clone, PartialEq, Copy)]
 /// A command mode
 pub enum CommandMode {
-//    Visual(VisualOptions),
+    /// Visual mode, for selecting text
+    Visual(VisualOptions),
     /// Normal mode
     Normal,
 }
@@ -36,4 +40,6 @@ pub enum CommandMode {
 pub enum PrimitiveMode {
     /// Insert mode
     Insert(InsertOptions),
+    /// Prompt mode, for entering a command in the status bar
+    Prompt,
 }
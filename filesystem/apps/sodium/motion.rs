@@ -9,10 +9,19 @@ impl Editor {
             Char('l') => self.right_pos(n.d()),
             Char('j') => self.down_pos(n.d()),
             Char('k') => self.up_pos(n.d()),
-            Char('g') => (0, n.or(1) - 1),
-            Char('G') => (0, self.text.len() - 1),
+            Char('g') => (0, self.ln_pos(n.d() - 1)),
+            Char('G') => match n {
+                Parameter::Int(n) => (0, self.ln_pos(n.saturating_sub(1))),
+                Parameter::Null => (0, self.text.len() - 1),
+            },
             Char('L') => self.ln_end_pos(),
-            Char('H') => (0, self.y()),
+            Char('w') => self.next_word_pos(n.d()),
+            Char('b') => self.previous_word_pos(n.d()),
+            Char('e') => self.word_end_pos(n.d()),
+            Char('H') | Home => (0, self.y()),
+            End => self.ln_end_pos(),
+            PageUp => self.up_pos(n.d() * self.page_height()),
+            PageDown => self.page_down_pos(n.d()),
             _ => (self.x(), self.y()),
         }
     }
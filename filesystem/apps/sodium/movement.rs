@@ -1,4 +1,5 @@
 use super::*;
+use redox::*;
 
 impl Editor {
     /// Goto a given position
@@ -95,7 +96,12 @@ impl Editor {
     /// Get the position under the char
     #[inline]
     pub fn down_pos(&self, n: usize) -> (usize, usize) {
-        (self.cursor().x, self.y() + n)
+        let last = self.text.len() - 1;
+        if self.y() + n <= last {
+            (self.cursor().x, self.y() + n)
+        } else {
+            (self.cursor().x, last)
+        }
     }
 
     /// Go down
@@ -117,4 +123,238 @@ impl Editor {
         let p = self.ln_end_pos();
         self.goto(p);
     }
+
+    /// Get the number of lines fitting on the screen
+    #[inline]
+    pub fn page_height(&self) -> usize {
+        // Leave out the status bar
+        let h = self.window.height();
+        if h > 18 {
+            (h - 18) / 16
+        } else {
+            1
+        }
+    }
+
+    /// Get the position a screenful below the cursor
+    #[inline]
+    pub fn page_down_pos(&self, n: usize) -> (usize, usize) {
+        let y = self.y() + n * self.page_height();
+        let last = self.text.len() - 1;
+        (self.x(), if y > last { last } else { y })
+    }
+
+    /// Go to the start of a line, counting from one and clamped to the buffer (`:N` and `NG`)
+    pub fn goto_line(&mut self, n: usize) {
+        let y = self.ln_pos(n.saturating_sub(1));
+        self.goto((0, y));
+    }
+
+    /// Clamp a line number to the buffer
+    #[inline]
+    pub fn ln_pos(&self, y: usize) -> usize {
+        cmp::min(y, self.text.len() - 1)
+    }
+
+    /// Get the char at a position, a line end being a newline
+    pub fn char_at(&self, (x, y): (usize, usize)) -> char {
+        if x < self.text[y].len() {
+            self.text[y][x]
+        } else {
+            '\n'
+        }
+    }
+
+    /// Step one char forward from a position (line ends included)
+    pub fn step_forward(&self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        if x < self.text[y].len() {
+            Some((x + 1, y))
+        } else if y + 1 < self.text.len() {
+            Some((0, y + 1))
+        } else {
+            None
+        }
+    }
+
+    /// Step one char backward from a position (line ends included)
+    pub fn step_backward(&self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        if x > 0 {
+            Some((x - 1, y))
+        } else if y > 0 {
+            Some((self.text[y - 1].len(), y - 1))
+        } else {
+            None
+        }
+    }
+
+    /// Get the position of the start of the n'th next word
+    pub fn next_word_pos(&self, n: usize) -> (usize, usize) {
+        let mut pos = (self.x(), self.y());
+        for _ in 0..n {
+            let class = char_class(self.char_at(pos));
+            // Skip the rest of the current word, then the whitespace after it
+            while let Some(next) = self.step_forward(pos) {
+                pos = next;
+                if char_class(self.char_at(pos)) != class {
+                    break;
+                }
+            }
+            while char_class(self.char_at(pos)) == CharClass::Space {
+                match self.step_forward(pos) {
+                    Some(next) => pos = next,
+                    None => break,
+                }
+            }
+        }
+        pos
+    }
+
+    /// Get the position of the end of the n'th next word
+    pub fn word_end_pos(&self, n: usize) -> (usize, usize) {
+        let mut pos = (self.x(), self.y());
+        for _ in 0..n {
+            // Always move at least one char, then skip whitespace
+            match self.step_forward(pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+            while char_class(self.char_at(pos)) == CharClass::Space {
+                match self.step_forward(pos) {
+                    Some(next) => pos = next,
+                    None => break,
+                }
+            }
+            let class = char_class(self.char_at(pos));
+            while let Some(next) = self.step_forward(pos) {
+                if char_class(self.char_at(next)) != class {
+                    break;
+                }
+                pos = next;
+            }
+        }
+        pos
+    }
+
+    /// Get the position of the start of the n'th previous word
+    pub fn previous_word_pos(&self, n: usize) -> (usize, usize) {
+        let mut pos = (self.x(), self.y());
+        for _ in 0..n {
+            match self.step_backward(pos) {
+                Some(prev) => pos = prev,
+                None => break,
+            }
+            while char_class(self.char_at(pos)) == CharClass::Space {
+                match self.step_backward(pos) {
+                    Some(prev) => pos = prev,
+                    None => break,
+                }
+            }
+            let class = char_class(self.char_at(pos));
+            while let Some(prev) = self.step_backward(pos) {
+                if char_class(self.char_at(prev)) != class {
+                    break;
+                }
+                pos = prev;
+            }
+        }
+        pos
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The class of a char, for finding word boundaries
+pub enum CharClass {
+    /// Whitespace (and line ends)
+    Space,
+    /// Alphanumeric chars and underscores
+    Word,
+    /// Anything else
+    Punctuation,
+}
+
+/// Get the class of a char
+pub fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_word_motions() {
+        let mut editor = Editor::with_text("foo.bar  baz");
+        assert_eq!(editor.next_word_pos(1), (3, 0));
+        assert_eq!(editor.next_word_pos(2), (4, 0));
+        assert_eq!(editor.next_word_pos(3), (9, 0));
+        assert_eq!(editor.word_end_pos(1), (2, 0));
+        assert_eq!(editor.word_end_pos(3), (6, 0));
+
+        editor.goto((9, 0));
+        assert_eq!(editor.previous_word_pos(1), (4, 0));
+        assert_eq!(editor.previous_word_pos(2), (3, 0));
+        assert_eq!(editor.previous_word_pos(3), (0, 0));
+    }
+
+    #[test]
+    fn test_dw() {
+        let mut editor = Editor::with_text("foo.bar  baz");
+        editor.type_str("dw");
+        assert_eq!(editor.text_string(), ".bar  baz");
+        editor.type_str("wdw");
+        assert_eq!(editor.text_string(), ".baz");
+    }
+
+    #[test]
+    fn test_page_keys() {
+        let mut editor = Editor::with_text("one two\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl");
+        let page = editor.page_height();
+        assert_eq!(page, 5);
+
+        editor.type_str("l");
+        editor.keys(&[Key::End]);
+        assert_eq!(editor.x(), 7);
+        editor.keys(&[Key::Home]);
+        assert_eq!(editor.x(), 0);
+
+        editor.keys(&[Key::PageDown]);
+        assert_eq!(editor.y(), page);
+        editor.keys(&[Key::Char('2'), Key::PageDown]);
+        assert_eq!(editor.y(), 11);
+        editor.keys(&[Key::PageUp]);
+        assert_eq!(editor.y(), 11 - page);
+        editor.keys(&[Key::Char('3'), Key::PageUp]);
+        assert_eq!(editor.y(), 0);
+    }
+
+    #[test]
+    fn test_goto_line() {
+        let mut editor = Editor::with_text("a\nb\nc\nd\ne\nf\ng\nh");
+        editor.type_str("G");
+        assert_eq!(editor.y(), 7);
+        editor.type_str("3G");
+        assert_eq!(editor.y(), 2);
+        editor.type_str("gg");
+        assert_eq!(editor.y(), 0);
+
+        // Past the end is the last line
+        editor.type_str("99G");
+        assert_eq!(editor.y(), 7);
+        editor.type_str(":1");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.y(), 0);
+        editor.type_str(":42");
+        editor.keys(&[Key::Enter]);
+        assert_eq!((editor.x(), editor.y()), (0, 7));
+
+        // The screen scrolls to show the line
+        editor.scroll_to_cursor();
+        assert_eq!(editor.scroll_x, 3);
+    }
 }
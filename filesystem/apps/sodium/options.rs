@@ -0,0 +1,83 @@
+use super::*;
+use redox::*;
+
+/// The editor options, changed with `:set`
+pub struct Options {
+    /// Show line numbers
+    pub number: bool,
+    /// Keep the indent of the current line when opening a new one
+    pub autoindent: bool,
+    /// Highlight the matches of the last search
+    pub hlsearch: bool,
+    /// The number of spaces `>>` and `<<` shift a line by
+    pub shiftwidth: usize,
+}
+
+impl Options {
+    /// Create the default options
+    pub fn new() -> Options {
+        Options {
+            number: false,
+            autoindent: true,
+            hlsearch: true,
+            shiftwidth: 4,
+        }
+    }
+
+    /// Get a boolean option by name
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "number" | "nu" => Some(&mut self.number),
+            "autoindent" | "ai" => Some(&mut self.autoindent),
+            "hlsearch" | "hls" => Some(&mut self.hlsearch),
+            _ => None,
+        }
+    }
+
+    /// Get a numeric option by name
+    pub fn get_num_mut(&mut self, name: &str) -> Option<&mut usize> {
+        match name {
+            "shiftwidth" | "sw" => Some(&mut self.shiftwidth),
+            _ => None,
+        }
+    }
+}
+
+impl Editor {
+    /// Set an option, as given to `:set` (`name` enables it and `noname` disables it,
+    /// `name=N` sets a number). Returns false if the option is not known
+    pub fn set_option(&mut self, arg: &str) -> bool {
+        if let Some(i) = arg.find('=') {
+            let (name, value) = (&arg[..i], &arg[i + 1..]);
+            let number = value.len() > 0 && value.chars().all(|c| c.is_digit(10));
+            let ok = match self.options.get_num_mut(name) {
+                Some(option) if number && value.to_string().to_num() > 0 => {
+                    *option = value.to_string().to_num();
+                    true
+                },
+                _ => false,
+            };
+            if ! ok {
+                self.status_bar.msg = "Bad option: ".to_string() + arg;
+            }
+            return ok;
+        }
+
+        let (name, value) = if arg.starts_with("no") && self.options.get_mut(&arg[2..]).is_some() {
+            (&arg[2..], false)
+        } else {
+            (arg, true)
+        };
+
+        match self.options.get_mut(name) {
+            Some(option) => {
+                *option = value;
+                true
+            },
+            None => {
+                self.status_bar.msg = "Unknown option: ".to_string() + arg;
+                false
+            },
+        }
+    }
+}
@@ -5,6 +5,9 @@ use redox::*;
 /// An instruction
 pub struct Inst(pub Parameter, pub Key);
 
+/// The largest repeat count accepted
+pub const MAX_COUNT: usize = 99999;
+
 /// A numeral parameter
 #[derive(Copy, Clone)]
 pub enum Parameter {
@@ -23,101 +26,140 @@ impl Parameter {
             fallback
         }
     }
-    /// Fallback to one (default)
+    /// Fallback to one (default). A count of zero is treated as one.
     #[inline]
     pub fn d(self) -> usize {
-        self.or(1)
+        match self.or(1) {
+            0 => 1,
+            n => n,
+        }
     }
 }
 
 impl Editor {
-    /// Get the next instruction
+    /// Get the next instruction, either replayed or read from the keyboard
     pub fn next_inst(&mut self) -> Inst {
-        let mut n = 0;
-        let mut unset = true;
+        let inst = match self.replay.pop_front() {
+            Some(inst) => inst,
+            None => self.read_inst(),
+        };
+        self.recording.push(inst);
+        inst
+    }
+
+    /// Get the next char typed, without parsing it as a parameter
+    pub fn next_char(&mut self) -> char {
+        let mut c = '\0';
+        if let Some(Inst(_, Key::Char(replayed))) = self.replay.pop_front() {
+            c = replayed;
+        }
+        while c == '\0' {
+            c = match self.read_key() {
+                Key::Char(typed) => typed,
+                Key::Enter => '\n',
+                Key::Tab => '\t',
+                _ => '\0',
+            };
+        }
+        self.recording.push(Inst(Parameter::Null, Key::Char(c)));
+        c
+    }
+
+    /// Read the next key, either typed ahead or pressed on the keyboard
+    fn read_key(&mut self) -> Key {
+        if let Some(key) = self.typed.pop_front() {
+            return key;
+        }
 
         loop {
             if let EventOption::Key(k) = self.window.poll().unwrap_or(Event::new()).to_option() {
                 let c = k.character;
+                // Tell the Enter key apart from a newline in inserted text
+                if k.pressed && k.scancode == K_ENTER {
+                    return Key::Enter;
+                }
                 match c {
                     '\0' => {
-                        return Inst(Parameter::Null, match k.scancode {
+                        return match k.scancode {
                             K_ALT => Key::Alt(k.pressed),
                             K_CTRL => Key::Ctrl(k.pressed),
                             K_LEFT_SHIFT | K_RIGHT_SHIFT => Key::Shift(k.pressed),
                             s if k.pressed => match s {
                                 K_BKSP => Key::Backspace,
+                                K_DEL => Key::Delete,
                                 K_LEFT => Key::Left,
                                 K_RIGHT => Key::Right,
                                 K_UP => Key::Up,
                                 K_DOWN => Key::Down,
+                                K_HOME => Key::Home,
+                                K_END => Key::End,
+                                K_PGUP => Key::PageUp,
+                                K_PGDN => Key::PageDown,
                                 K_TAB => Key::Tab,
                                 K_ESC => Key::Escape,
+                                f if f >= K_F1 && f <= K_F10 => Key::F(f - K_F1 + 1),
+                                K_F11 => Key::F(11),
+                                K_F12 => Key::F(12),
                                 _ => Key::Unknown(s),
                             },
                             s => Key::Unknown(s),
-                        })
+                        }
                     }
                     _ => if k.pressed {
-                        match self.cursor().mode {
-                            Mode::Primitive(_) => {
-                                return Inst(Parameter::Null, Key::Char(c));
-                            },
-                            Mode::Command(_) => {
-                                n = match c {
-                                    '0' => {
-                                        unset = false;
-                                        n * 10
-                                    },
-                                    '1' => {
-                                        unset = false;
-                                        n * 10 + 1
-                                    },
-                                    '2' => {
-                                        unset = false;
-                                        n * 10 + 2
-                                    },
-                                    '3' => {
-                                        unset = false;
-                                        n * 10 + 3
-                                    },
-                                    '4' => {
-                                        unset = false;
-                                        n * 10 + 4
-                                    },
-                                    '5' => {
-                                        unset = false;
-                                        n * 10 + 5
-                                    },
-                                    '6' => {
-                                        unset = false;
-                                        n * 10 + 6
-                                    },
-                                    '7' => {
-                                        unset = false;
-                                        n * 10 + 7
-                                    },
-                                    '8' => {
-                                        unset = false;
-                                        n * 10 + 8
-                                    },
-                                    '9' => {
-                                        unset = false;
-                                        n * 10 + 9
-                                    },
-                                    _   => {
+                        // Control characters, like Escape and Tab, are decoded to their keys
+                        return match Key::from_escape(c.to_string().as_bytes()) {
+                            (Some(key), _) => key,
+                            (None, _) => Key::Char(c),
+                        };
+                    },
+                }
+            }
+        }
+    }
 
-                                        return Inst(if unset { Parameter::Null } else { Parameter::Int(n) }, Key::Char(c));
-                                    }
-                                }
-                            }
-                        }
+    /// Read the next instruction, parsing the count typed before it in command mode
+    fn read_inst(&mut self) -> Inst {
+        let mut n = 0;
+        let mut unset = true;
 
-                    },
+        loop {
+            let key = self.read_key();
+            if let (Key::Char(c), Mode::Command(_)) = (key, self.cursor().mode) {
+                if let Some(d) = c.to_digit(10) {
+                    unset = false;
+                    // Clamp instead of overflowing on absurdly long counts
+                    n = n.saturating_mul(10).saturating_add(d as usize);
+                    if n > MAX_COUNT {
+                        n = MAX_COUNT;
+                    }
+                    continue;
                 }
             }
+
+            return Inst(if unset { Parameter::Null } else { Parameter::Int(n) }, key);
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_counts() {
+        let mut editor = Editor::with_text("one\ntwo\nthree\nfour\nfive");
+        editor.type_str("3j");
+        assert_eq!(editor.y(), 3);
+
+        editor.type_str("gg2dd");
+        assert_eq!(editor.text_string(), "three\nfour\nfive");
+
+        // A count of zero is taken as one
+        editor.type_str("0dd");
+        assert_eq!(editor.text_string(), "four\nfive");
 
-        unreachable!()
+        // Absurd counts are clamped instead of overflowing
+        editor.type_str("99999999999999999999999dd");
+        assert_eq!(editor.text_string(), "");
     }
 }
@@ -0,0 +1,80 @@
+use super::*;
+use redox::*;
+
+impl Editor {
+    /// Handle a key in prompt mode
+    pub fn prompt(&mut self, k: Key) {
+        match k {
+            Key::Enter | Key::Char('\n') => {
+                let cmd = self.status_bar.cmd.clone();
+                self.status_bar.cmd = String::new();
+                self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
+                self.invoke(&cmd);
+            },
+            Key::Escape => {
+                self.status_bar.cmd = String::new();
+                self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
+            },
+            Key::Backspace => {
+                self.status_bar.cmd.pop();
+                if self.status_bar.cmd.len() == 0 {
+                    self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
+                }
+            },
+            Key::Char(c) => self.status_bar.cmd.push(c),
+            _ => {},
+        }
+    }
+
+    /// Invoke a command entered in the prompt
+    pub fn invoke(&mut self, cmd: &str) {
+        if cmd.starts_with(":s/") {
+            self.substitute(&cmd[2..], false);
+        } else if cmd.starts_with(":%s/") {
+            self.substitute(&cmd[3..], true);
+        } else if cmd.starts_with(':') {
+            let args: Vec<&str> = cmd[1..].split_whitespace().collect();
+            match args.get(0) {
+                Some(&"w") => self.save(args.get(1).map(|s| *s)),
+                Some(&"set") => match args.get(1) {
+                    Some(arg) => {
+                        self.set_option(arg);
+                    },
+                    None => self.status_bar.msg = "Usage: :set <option>".to_string(),
+                },
+                Some(&"noh") | Some(&"nohlsearch") => self.search_highlight = false,
+                Some(&"q") => self.quit(false),
+                Some(&"q!") => self.quit(true),
+                Some(&"e") => match args.get(1) {
+                    Some(path) => self.edit(path),
+                    None => self.status_bar.msg = "Usage: :e <file>".to_string(),
+                },
+                Some(&"bn") => self.next_buffer(),
+                Some(&"bp") => self.previous_buffer(),
+                Some(&"b") => match args.get(1) {
+                    Some(n) => {
+                        let n = n.to_string().to_num();
+                        if n > 0 {
+                            self.switch_buffer(n - 1);
+                        } else {
+                            self.status_bar.msg = "Usage: :b <number>".to_string();
+                        }
+                    },
+                    None => self.status_bar.msg = "Usage: :b <number>".to_string(),
+                },
+                Some(&"map") => match (args.get(1).and_then(|k| Key::from_name(k)), args.len() > 2) {
+                    (Some(key), true) => {
+                        let bound = args[2..].join(" ");
+                        self.bind(key, &bound);
+                    },
+                    _ => self.status_bar.msg = "Usage: :map F<n> <command>".to_string(),
+                },
+                Some(n) if n.chars().all(|c| c.is_digit(10)) => self.goto_line(n.to_string().to_num()),
+                Some(c) => self.status_bar.msg = "Unknown command: ".to_string() + c,
+                None => {},
+            }
+        } else if cmd.starts_with('/') {
+            self.search(&cmd[1..]);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use super::*;
+use redox::*;
+
+#[derive(Clone)]
+/// A register, holding yanked or deleted text
+pub struct Register {
+    /// The name of the register
+    pub name: char,
+    /// The text, with lines separated by '\n'
+    pub text: String,
+    /// Whether whole lines were yanked
+    pub linewise: bool,
+}
+
+impl Editor {
+    /// Store text in the selected register (and the unnamed one)
+    pub fn yank(&mut self, text: String, linewise: bool) {
+        let name = self.selected_register.take().unwrap_or('"');
+        self.set_register(name, text.clone(), linewise);
+        if name != '"' {
+            self.set_register('"', text, linewise);
+        }
+    }
+
+    /// Set the content of a register
+    fn set_register(&mut self, name: char, text: String, linewise: bool) {
+        self.registers.retain(|r| r.name != name);
+        self.registers.push(Register {
+            name: name,
+            text: text,
+            linewise: linewise,
+        });
+    }
+
+    /// Get a register by name
+    pub fn register(&self, name: char) -> Option<&Register> {
+        self.registers.iter().find(|r| r.name == name)
+    }
+
+    /// Get the text of a range of lines (both inclusive), without the trailing line break
+    fn get_lines(&self, a: usize, b: usize) -> String {
+        let mut lines = Vec::new();
+        for ln in a..b + 1 {
+            lines.push(self.text[ln].iter().map(|c| *c).collect::<String>());
+        }
+        lines.join("\n")
+    }
+
+    /// Yank a number of lines, starting at the cursor
+    pub fn yank_lines(&mut self, n: usize) {
+        let y = self.y();
+        let end = self.ln_pos(y + n - 1);
+        let text = self.get_lines(y, end);
+        self.yank(text, true);
+    }
+
+    /// Yank the text up to a motion (row based, like `remove_rb`)
+    pub fn yank_rb(&mut self, (x, y): (usize, usize)) {
+        if y == self.y() {
+            let (a, b) = if self.x() < x {
+                (self.x(), x)
+            } else {
+                (x, self.x())
+            };
+            let text = self.text[y].iter().skip(a).take(b - a).map(|c| *c).collect();
+            self.yank(text, false);
+        } else {
+            let (a, b) = if self.y() < y {
+                (self.y(), y)
+            } else {
+                (y, self.y())
+            };
+            let text = self.get_lines(a, b);
+            self.yank(text, true);
+        }
+    }
+
+    /// Paste the selected register after (or before) the cursor
+    pub fn paste(&mut self, after: bool) {
+        let name = self.selected_register.take().unwrap_or('"');
+        let reg = match self.register(name) {
+            Some(reg) => reg.clone(),
+            None => {
+                self.status_bar.msg = format!("Register {} is empty", name);
+                return;
+            },
+        };
+
+        let y = self.y();
+        if reg.linewise {
+            // Open new lines below (or above) the current one
+            let mut at = if after { y + 1 } else { y };
+            let first = at;
+            for ln in reg.text.split('\n') {
+                self.text.insert(at, ln.chars().collect());
+                at += 1;
+            }
+            self.goto((0, first));
+        } else {
+            let x = if after && self.text[y].len() > 0 {
+                self.x() + 1
+            } else {
+                self.x()
+            };
+            let x = cmp::min(x, self.text[y].len());
+
+            let tail: VecDeque<char> = self.text[y].iter().skip(x).map(|c| *c).collect();
+            while self.text[y].len() > x {
+                self.text[y].pop_back();
+            }
+
+            let (mut cx, mut cy) = (x, y);
+            for (i, part) in reg.text.split('\n').enumerate() {
+                if i > 0 {
+                    cy += 1;
+                    cx = 0;
+                    self.text.insert(cy, VecDeque::new());
+                }
+                for c in part.chars() {
+                    self.text[cy].push_back(c);
+                    cx += 1;
+                }
+            }
+            for c in tail {
+                self.text[cy].push_back(c);
+            }
+
+            // Leave the cursor on the last pasted char
+            self.goto((if cx > 0 { cx - 1 } else { 0 }, cy));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_yank_and_paste() {
+        let mut editor = Editor::with_text("foo bar\nbaz");
+        editor.type_str("yyjp");
+        assert_eq!(editor.text_string(), "foo bar\nbaz\nfoo bar");
+        assert_eq!(editor.y(), 2);
+
+        // A named register keeps its text when the unnamed one is overwritten
+        let mut editor = Editor::with_text("foo bar");
+        editor.type_str("\"ayw");
+        assert_eq!(editor.register('a').map(|r| &r.text[..]), Some("foo "));
+        editor.type_str("x");
+        assert_eq!(editor.register('"').map(|r| &r.text[..]), Some("f"));
+        editor.type_str("\"aP");
+        assert_eq!(editor.text_string(), "foo oo bar");
+
+        editor.type_str("\"zp");
+        assert_eq!(editor.status_bar.msg, "Register z is empty");
+        assert_eq!(editor.text_string(), "foo oo bar");
+    }
+}
@@ -0,0 +1,60 @@
+use super::*;
+use redox::*;
+
+impl Editor {
+    /// Repeat the last change (`.`), optionally with a new count
+    pub fn repeat(&mut self, para: Parameter) {
+        // Drop the `.` itself; the replayed instructions are recorded instead
+        self.recording.clear();
+
+        let mut change = self.last_change.clone();
+        if let Parameter::Int(_) = para {
+            if let Some(&Inst(_, cmd)) = change.first() {
+                change[0] = Inst(para, cmd);
+            }
+        }
+
+        self.replay = change.into_iter().collect();
+        while let Some(inst) = self.replay.pop_front() {
+            self.recording.push(inst);
+            self.exec(inst);
+        }
+    }
+
+    /// Remember the instructions of a finished change, so it can be repeated
+    pub fn record_change(&mut self, changed: bool) {
+        if changed {
+            self.last_change = self.recording.clone();
+        }
+        self.recording.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_repeat() {
+        let mut editor = Editor::with_text("a\nb\nc\nd\ne");
+        editor.type_str("dd.");
+        assert_eq!(editor.text_string(), "c\nd\ne");
+
+        // A new count replaces the one of the change
+        editor.type_str("2.");
+        assert_eq!(editor.text_string(), "e");
+
+        // An insert is repeated with its text, and undone as one step
+        let mut editor = Editor::with_text("one\ntwo");
+        editor.type_str("ihi ");
+        editor.keys(&[Key::Escape]);
+        editor.type_str("jH.");
+        assert_eq!(editor.text_string(), "hi one\nhi two");
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "hi one\ntwo");
+
+        // Motions are not changes, so they do not replace the one to repeat
+        editor.type_str("jH.");
+        assert_eq!(editor.text_string(), "hi one\nhi two");
+    }
+}
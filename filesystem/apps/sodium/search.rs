@@ -0,0 +1,281 @@
+use super::*;
+use redox::*;
+
+/// Check if a pattern occurs in a line at a given position
+fn matches_at(ln: &VecDeque<char>, x: usize, pat: &[char]) -> bool {
+    if x + pat.len() > ln.len() {
+        return false;
+    }
+    for (i, &c) in pat.iter().enumerate() {
+        if ln[x + i] != c {
+            return false;
+        }
+    }
+    true
+}
+
+impl Editor {
+    /// Find the next occurrence of a pattern, wrapping at the buffer ends
+    pub fn find(&self, pat: &str, forward: bool) -> Option<(usize, usize)> {
+        let pat: Vec<char> = pat.chars().collect();
+        if pat.is_empty() {
+            return None;
+        }
+
+        let lines = self.text.len();
+        let (cx, cy) = (self.x(), self.y());
+
+        // Visit the cursor line twice: once after the cursor, and once more
+        // (after wrapping) before it
+        for i in 0..lines + 1 {
+            let y = if forward {
+                (cy + i) % lines
+            } else {
+                (cy + lines - i % lines) % lines
+            };
+            let ln = &self.text[y];
+
+            let mut xs: Vec<usize> = (0..ln.len() + 1).collect();
+            if !forward {
+                xs.reverse();
+            }
+
+            for x in xs {
+                let skip = if i == 0 {
+                    if forward { x <= cx } else { x >= cx }
+                } else if i == lines {
+                    if forward { x > cx } else { x < cx }
+                } else {
+                    false
+                };
+
+                if !skip && matches_at(ln, x, &pat) {
+                    return Some((x, y));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Search for a pattern and remember it for `n` and `N`
+    pub fn search(&mut self, pat: &str) {
+        self.last_search = Some(pat.to_string());
+        self.search_next(true);
+    }
+
+    /// Goto the next (or previous) match of the last search
+    pub fn search_next(&mut self, forward: bool) {
+        if let Some(pat) = self.last_search.clone() {
+            self.search_highlight = true;
+            match self.find(&pat, forward) {
+                Some(p) => self.goto(p),
+                None => self.status_bar.msg = "Pattern not found: ".to_string() + &pat,
+            }
+        } else {
+            self.status_bar.msg = "No previous search".to_string();
+        }
+    }
+
+    /// Get the pattern whose matches are highlighted: the one being typed after `/`,
+    /// or else the last search, unless it was cleared with `:noh` or `hlsearch` is off
+    pub fn highlight_pattern(&self) -> Option<String> {
+        if let Mode::Primitive(PrimitiveMode::Prompt) = self.cursor().mode {
+            if self.status_bar.cmd.starts_with('/') {
+                return Some(self.status_bar.cmd[1..].to_string());
+            }
+        }
+
+        if self.options.hlsearch && self.search_highlight {
+            self.last_search.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Find the matches of a pattern in a line, as the start and end of each
+    pub fn search_matches(&self, y: usize, pat: &str) -> Vec<(usize, usize)> {
+        let pat: Vec<char> = pat.chars().collect();
+        let mut matches = Vec::new();
+        if pat.is_empty() {
+            return matches;
+        }
+
+        if let Some(ln) = self.text.get(y) {
+            let mut x = 0;
+            while x < ln.len() {
+                if matches_at(ln, x, &pat) {
+                    matches.push((x, x + pat.len()));
+                    x += pat.len();
+                } else {
+                    x += 1;
+                }
+            }
+        }
+
+        matches
+    }
+
+    /// Replace occurrences of a pattern in a line. Returns the number of
+    /// replacements and the position of the last one.
+    fn replace_ln(&mut self, y: usize, find: &[char], replace: &[char], global: bool) -> (usize, usize) {
+        let mut count = 0;
+        let mut last = 0;
+        let mut ln = VecDeque::new();
+        let mut x = 0;
+        while x < self.text[y].len() {
+            if (global || count == 0) && matches_at(&self.text[y], x, find) {
+                last = ln.len();
+                for &c in replace {
+                    ln.push_back(c);
+                }
+                x += find.len();
+                count += 1;
+            } else {
+                ln.push_back(self.text[y][x]);
+                x += 1;
+            }
+        }
+        if count > 0 {
+            self.text[y] = ln;
+        }
+        (count, last)
+    }
+
+    /// Substitute text, given the arguments of `:s` (`/find/replace/[g]`).
+    /// If `whole` is set, every line is affected, otherwise only the current one.
+    pub fn substitute(&mut self, args: &str, whole: bool) {
+        let parts: Vec<&str> = args.splitn(4, '/').collect();
+        if parts.len() < 3 || !parts[0].is_empty() || parts[1].is_empty() {
+            self.status_bar.msg = "Usage: :s/find/replace/[g]".to_string();
+            return;
+        }
+
+        let find: Vec<char> = parts[1].chars().collect();
+        let replace: Vec<char> = parts[2].chars().collect();
+        let global = parts.get(3).map_or(false, |f| f.contains('g'));
+
+        let (start, end) = if whole {
+            (0, self.text.len())
+        } else {
+            (self.y(), self.y() + 1)
+        };
+
+        let mut total = 0;
+        for y in start..end {
+            let (count, x) = self.replace_ln(y, &find, &replace, global);
+            if count > 0 {
+                total += count;
+                self.goto((x, y));
+            }
+        }
+
+        self.status_bar.msg = if total == 0 {
+            "Pattern not found: ".to_string() + parts[1]
+        } else if total == 1 {
+            "1 substitution".to_string()
+        } else {
+            format!("{} substitutions", total)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_find() {
+        let mut editor = Editor::with_text("foo bar\nbaz foo\nqux");
+        assert_eq!(editor.find("foo", true), Some((4, 1)));
+        assert_eq!(editor.find("bar", true), Some((4, 0)));
+        assert_eq!(editor.find("nope", true), None);
+        assert_eq!(editor.find("", true), None);
+
+        // The match under the cursor is found again only after wrapping around
+        assert_eq!(editor.find("foo", false), Some((4, 1)));
+        editor.goto((4, 1));
+        assert_eq!(editor.find("foo", true), Some((0, 0)));
+        assert_eq!(editor.find("foo", false), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_search_next() {
+        let mut editor = Editor::with_text("foo bar\nbaz foo\nqux foo");
+        editor.type_str("/foo");
+        editor.keys(&[Key::Enter]);
+        assert_eq!((editor.x(), editor.y()), (4, 1));
+
+        editor.type_str("n");
+        assert_eq!((editor.x(), editor.y()), (4, 2));
+        editor.type_str("n");
+        assert_eq!((editor.x(), editor.y()), (0, 0));
+
+        editor.type_str("N");
+        assert_eq!((editor.x(), editor.y()), (4, 2));
+        editor.type_str("NN");
+        assert_eq!((editor.x(), editor.y()), (0, 0));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut editor = Editor::with_text("a a a\na a");
+        editor.substitute("/a/b/", false);
+        assert_eq!(editor.text_string(), "b a a\na a");
+        assert_eq!(editor.status_bar.msg, "1 substitution");
+
+        editor.substitute("/a/cc/g", false);
+        assert_eq!(editor.text_string(), "b cc cc\na a");
+        assert_eq!(editor.status_bar.msg, "2 substitutions");
+        assert_eq!((editor.x(), editor.y()), (5, 0));
+
+        editor.substitute("/x/y/", false);
+        assert_eq!(editor.text_string(), "b cc cc\na a");
+        assert_eq!(editor.status_bar.msg, "Pattern not found: x");
+    }
+
+    #[test]
+    fn test_substitute_whole() {
+        let mut editor = Editor::with_text("a a\nb\na");
+        editor.type_str(":%s/a/x/g");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(editor.text_string(), "x x\nb\nx");
+        assert_eq!(editor.status_bar.msg, "3 substitutions");
+
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "a a\nb\na");
+    }
+
+    #[test]
+    fn test_highlight() {
+        let mut editor = Editor::with_text("foo\nbar\nbaz foo\nqux");
+        let rows = |editor: &Editor| -> Vec<usize> {
+            match editor.highlight_pattern() {
+                Some(pat) => (0..editor.text.len()).filter(|&y| {
+                    ! editor.search_matches(y, &pat).is_empty()
+                }).collect(),
+                None => Vec::new(),
+            }
+        };
+
+        // The matches are highlighted while the pattern is typed, and after the search
+        editor.type_str("/ba");
+        assert_eq!(rows(&editor), vec![1, 2]);
+        editor.keys(&[Key::Backspace, Key::Backspace]);
+        assert_eq!(rows(&editor), Vec::<usize>::new());
+        editor.type_str("foo");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(rows(&editor), vec![0, 2]);
+        assert_eq!(editor.search_matches(2, "foo"), vec![(4, 7)]);
+
+        editor.type_str(":noh");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(rows(&editor), Vec::<usize>::new());
+        editor.type_str("n");
+        assert_eq!(rows(&editor), vec![0, 2]);
+
+        editor.type_str(":set nohlsearch");
+        editor.keys(&[Key::Enter]);
+        assert_eq!(rows(&editor), Vec::<usize>::new());
+    }
+}
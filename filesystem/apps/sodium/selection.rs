@@ -1,4 +1,12 @@
 use super::*;
+use redox::*;
+
+#[derive(Clone, PartialEq, Copy)]
+/// The visual mode options
+pub struct VisualOptions {
+    /// The position where the selection started
+    pub anchor: (usize, usize),
+}
 
 impl Editor {
     /// Remove from a given motion (row based)
@@ -27,4 +35,320 @@ impl Editor {
             }
         }
     }
+
+    /// Get the selected range as ordered (start, end) positions, both inclusive
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        if let Mode::Command(CommandMode::Visual(opt)) = self.cursor().mode {
+            let (ax, ay) = opt.anchor;
+            let (cx, cy) = (self.x(), self.y());
+            if (ay, ax) <= (cy, cx) {
+                Some(((ax, ay), (cx, cy)))
+            } else {
+                Some(((cx, cy), (ax, ay)))
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Check if a char is selected
+    pub fn is_selected(&self, x: usize, y: usize) -> bool {
+        if let Some(((sx, sy), (ex, ey))) = self.selection() {
+            (y, x) >= (sy, sx) && (y, x) <= (ey, ex)
+        } else {
+            false
+        }
+    }
+
+    /// Get the text in a range (both ends inclusive)
+    pub fn get_range(&self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) -> String {
+        let mut string = String::new();
+        for y in sy..ey + 1 {
+            let ln = &self.text[y];
+            let from = if y == sy { sx } else { 0 };
+            let to = if y == ey { cmp::min(ex + 1, ln.len()) } else { ln.len() };
+            for x in from..to {
+                string.push(ln[x]);
+            }
+            if y != ey || ex >= ln.len() {
+                string.push('\n');
+            }
+        }
+        string
+    }
+
+    /// Remove the text in a range (both ends inclusive), joining the lines at its ends
+    pub fn remove_range(&mut self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) {
+        let ln_len = self.text[ey].len();
+        let mut tail: VecDeque<char> = self.text[ey].iter().skip(ex + 1).map(|c| *c).collect();
+
+        // Selecting past the end of a line takes its line break as well
+        if ex >= ln_len && ey + 1 < self.text.len() {
+            tail = self.text.remove(ey + 1).unwrap_or(VecDeque::new());
+        }
+
+        while self.text[sy].len() > sx {
+            self.text[sy].pop_back();
+        }
+        for c in tail {
+            self.text[sy].push_back(c);
+        }
+        for _ in sy..ey {
+            self.text.remove(sy + 1);
+        }
+
+        self.goto((sx, sy));
+    }
+
+    /// Remove the selected text and leave visual mode
+    pub fn remove_selection(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.remove_range(start, end);
+        }
+        self.cursor_mut().mode = Mode::Command(CommandMode::Normal);
+    }
+
+    /// Get the range (both ends inclusive) of a text object at the cursor, such as `iw` or `a(`
+    ///
+    /// `inner` leaves out the surrounding whitespace, quotes or brackets. Returns `None` if
+    /// the cursor is not inside such an object, or if it is empty.
+    pub fn text_object(&self, inner: bool, c: char) -> Option<((usize, usize), (usize, usize))> {
+        match c {
+            'w' => self.word_object(inner),
+            '"' | '\'' | '`' => self.quote_object(inner, c),
+            '(' | ')' | 'b' => self.bracket_object(inner, '(', ')'),
+            '{' | '}' | 'B' => self.bracket_object(inner, '{', '}'),
+            '[' | ']' => self.bracket_object(inner, '[', ']'),
+            '<' | '>' => self.bracket_object(inner, '<', '>'),
+            _ => None,
+        }
+    }
+
+    /// Get the word (or run of whitespace) under the cursor, and its trailing whitespace if not inner
+    fn word_object(&self, inner: bool) -> Option<((usize, usize), (usize, usize))> {
+        let y = self.y();
+        let ln = &self.text[y];
+        if ln.len() == 0 {
+            return None;
+        }
+
+        let x = cmp::min(self.x(), ln.len() - 1);
+        let class = char_class(ln[x]);
+        let mut start = x;
+        while start > 0 && char_class(ln[start - 1]) == class {
+            start -= 1;
+        }
+        let mut end = x;
+        while end + 1 < ln.len() && char_class(ln[end + 1]) == class {
+            end += 1;
+        }
+
+        if ! inner && class != CharClass::Space {
+            // Take the whitespace after the word, or else the whitespace before it
+            if end + 1 < ln.len() && char_class(ln[end + 1]) == CharClass::Space {
+                while end + 1 < ln.len() && char_class(ln[end + 1]) == CharClass::Space {
+                    end += 1;
+                }
+            } else {
+                while start > 0 && char_class(ln[start - 1]) == CharClass::Space {
+                    start -= 1;
+                }
+            }
+        }
+
+        Some(((start, y), (end, y)))
+    }
+
+    /// Get the quoted text around the cursor on its line, or else the next quoted text on it
+    fn quote_object(&self, inner: bool, quote: char) -> Option<((usize, usize), (usize, usize))> {
+        let y = self.y();
+        let x = self.x();
+        let quotes: Vec<usize> = self.text[y].iter()
+                                             .enumerate()
+                                             .filter(|&(_, &c)| c == quote)
+                                             .map(|(i, _)| i)
+                                             .collect();
+
+        let mut i = 0;
+        while i + 1 < quotes.len() {
+            let (open, close) = (quotes[i], quotes[i + 1]);
+            if x <= close {
+                return if ! inner {
+                    Some(((open, y), (close, y)))
+                } else if open + 1 < close {
+                    Some(((open + 1, y), (close - 1, y)))
+                } else {
+                    None
+                };
+            }
+            i += 2;
+        }
+
+        None
+    }
+
+    /// Get the text between the brackets enclosing the cursor, which may span lines
+    fn bracket_object(&self, inner: bool, open: char, close: char) -> Option<((usize, usize), (usize, usize))> {
+        let cursor = (self.x(), self.y());
+
+        // Find the unmatched opening bracket before the cursor (or on it)
+        let mut start = cursor;
+        let mut depth = 0;
+        loop {
+            let c = self.char_at(start);
+            if c == open {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            } else if c == close && start != cursor {
+                depth += 1;
+            }
+
+            match self.step_backward(start) {
+                Some(pos) => start = pos,
+                None => return None,
+            }
+        }
+
+        // Find the bracket that closes it
+        let mut end = start;
+        let mut depth = 0;
+        loop {
+            match self.step_forward(end) {
+                Some(pos) => end = pos,
+                None => return None,
+            }
+
+            let c = self.char_at(end);
+            if c == close {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            } else if c == open {
+                depth += 1;
+            }
+        }
+
+        if ! inner {
+            return Some((start, end));
+        }
+
+        match (self.step_forward(start), self.step_backward(end)) {
+            (Some(inner_start), Some(inner_end)) if inner_start != end => Some((inner_start, inner_end)),
+            _ => None,
+        }
+    }
+
+    /// Apply an operator (`d`, `c` or `y`) to a text object, such as in `diw` or `ci(`
+    pub fn text_object_op(&mut self, op: char, inner: bool, c: char) {
+        match self.text_object(inner, c) {
+            Some((start, end)) => {
+                let text = self.get_range(start, end);
+                self.yank(text, false);
+                if op == 'y' {
+                    self.goto(start);
+                } else {
+                    self.remove_range(start, end);
+                }
+            },
+            None => self.status_bar.msg = "No text object".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_word_object() {
+        let mut editor = Editor::with_text("foo bar baz");
+        editor.type_str("wdiw");
+        assert_eq!(editor.text_string(), "foo  baz");
+
+        let mut editor = Editor::with_text("foo bar baz");
+        editor.type_str("wdaw");
+        assert_eq!(editor.text_string(), "foo baz");
+
+        // At the end of the line, the whitespace before the word goes instead
+        let mut editor = Editor::with_text("foo bar");
+        editor.type_str("wdaw");
+        assert_eq!(editor.text_string(), "foo");
+    }
+
+    #[test]
+    fn test_bracket_object() {
+        let mut editor = Editor::with_text("f(a, (b), c)");
+        editor.type_str("2ldi(");
+        assert_eq!(editor.text_string(), "f()");
+
+        // The innermost brackets around the cursor are used
+        let mut editor = Editor::with_text("f(a, (b), c)");
+        editor.type_str("6ldi)");
+        assert_eq!(editor.text_string(), "f(a, (), c)");
+
+        let mut editor = Editor::with_text("f(a, (b), c)");
+        editor.type_str("6lda(");
+        assert_eq!(editor.text_string(), "f(a, , c)");
+
+        let mut editor = Editor::with_text("x = [1, 2]");
+        editor.type_str("5lci[3");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "x = [3]");
+    }
+
+    #[test]
+    fn test_quote_object() {
+        let mut editor = Editor::with_text("say \"hi there\" now");
+        editor.type_str("di\"");
+        assert_eq!(editor.text_string(), "say \"\" now");
+
+        let mut editor = Editor::with_text("say \"hi there\" now");
+        editor.type_str("6lda\"");
+        assert_eq!(editor.text_string(), "say  now");
+    }
+
+    #[test]
+    fn test_missing_object() {
+        // Unbalanced brackets are left alone
+        let mut editor = Editor::with_text("f(a");
+        editor.type_str("2ldi(");
+        assert_eq!(editor.text_string(), "f(a");
+
+        let mut editor = Editor::with_text("f(a)");
+        editor.type_str("2ldi[");
+        assert_eq!(editor.text_string(), "f(a)");
+
+        // There is nothing inside empty brackets
+        let mut editor = Editor::with_text("f()");
+        editor.type_str("ldi(");
+        assert_eq!(editor.text_string(), "f()");
+        assert!(editor.history.undo.is_empty());
+    }
+
+    #[test]
+    fn test_visual_mode() {
+        let mut editor = Editor::with_text("abcdef");
+        editor.type_str("lvll");
+        assert!(editor.selection() == Some(((1, 0), (3, 0))));
+        assert!(editor.is_selected(3, 0));
+        assert!(! editor.is_selected(4, 0));
+        editor.type_str("d");
+        assert_eq!(editor.text_string(), "aef");
+        assert!(editor.selection().is_none());
+
+        // The selection runs across lines, whichever way it was made
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_str("jlvkd");
+        assert_eq!(editor.text_string(), "oo\nthree");
+
+        // Escape leaves the text alone
+        let mut editor = Editor::with_text("one");
+        editor.type_str("vl");
+        editor.keys(&[Key::Escape]);
+        assert!(editor.selection().is_none());
+        assert_eq!(editor.text_string(), "one");
+    }
 }
@@ -43,6 +43,42 @@ pub use self::delete::*;
 mod exec;
 pub use self::exec::*;
 
+mod prompt;
+pub use self::prompt::*;
+
+mod file;
+pub use self::file::*;
+
+mod undo;
+pub use self::undo::*;
+
+mod bind;
+pub use self::bind::*;
+
+mod search;
+pub use self::search::*;
+
+mod register;
+pub use self::register::*;
+
+mod buffer;
+pub use self::buffer::*;
+
+mod options;
+pub use self::options::*;
+
+mod repeat;
+pub use self::repeat::*;
+
+mod config;
+pub use self::config::*;
+
+mod mark;
+pub use self::mark::*;
+
+mod indent;
+pub use self::indent::*;
+
 pub fn main() {
     let editor = Editor::new();
 }
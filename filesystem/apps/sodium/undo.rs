@@ -0,0 +1,178 @@
+use super::*;
+use redox::*;
+
+#[derive(Clone)]
+/// A state of the text, which can be restored
+pub struct Snapshot {
+    /// The text
+    pub text: VecDeque<VecDeque<char>>,
+    /// The x coordinate of the cursor
+    pub x: usize,
+    /// The y coordinate of the cursor
+    pub y: usize,
+}
+
+/// The undo and redo history
+pub struct History {
+    /// The states to undo to
+    pub undo: Vec<Snapshot>,
+    /// The states to redo to
+    pub redo: Vec<Snapshot>,
+    /// The state before the change in progress
+    pub pending: Option<Snapshot>,
+}
+
+impl History {
+    /// Create a new empty history
+    pub fn new() -> History {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            pending: None,
+        }
+    }
+}
+
+impl Editor {
+    /// Take a snapshot of the text and cursor
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            text: self.text.clone(),
+            x: self.x(),
+            y: self.y(),
+        }
+    }
+
+    /// Restore a snapshot, returning the replaced state
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        let current = self.snapshot();
+        self.text = snapshot.text;
+        self.shift_marks(&current.text);
+        self.goto((snapshot.x, snapshot.y));
+        current
+    }
+
+    /// Check if an instruction may change the text, so that only those take a snapshot
+    pub fn mutates(&self, Inst(_, cmd): Inst) -> bool {
+        use super::Key::*;
+
+        match self.cursor().mode {
+            Mode::Primitive(PrimitiveMode::Insert(_)) => true,
+            // Prompt commands, like `:s`, run on Enter
+            Mode::Primitive(PrimitiveMode::Prompt) => cmd == Enter || cmd == Char('\n'),
+            Mode::Command(CommandMode::Visual(_)) => match cmd {
+                Char('d') | Char('x') | Char('>') | Char('<') => true,
+                _ => false,
+            },
+            Mode::Command(CommandMode::Normal) => match cmd {
+                Char('d') if self.key_state.ctrl => false,
+                Char('i') | Char('R') | Char('o') | Char('O') | Char('J') | Char('x') | Char('X') |
+                Char('r') | Char('d') | Char('c') | Char('C') | Char('>') | Char('<') | Char('p') |
+                Char('P') | Char('.') | Delete | F(_) => true,
+                _ => false,
+            },
+        }
+    }
+
+    /// Start a change, unless one is already in progress
+    pub fn begin_change(&mut self) {
+        if self.history.pending.is_none() {
+            self.history.pending = Some(self.snapshot());
+        }
+    }
+
+    /// Finish a change when back in normal mode, so that an insert session is undone as one step
+    pub fn end_change(&mut self) {
+        if self.cursor().mode == Mode::Command(CommandMode::Normal) {
+            let mut changed = false;
+            if let Some(pending) = self.history.pending.take() {
+                if pending.text != self.text {
+                    self.shift_marks(&pending.text);
+                    self.history.undo.push(pending);
+                    self.history.redo.clear();
+                    self.dirty = true;
+                    changed = true;
+                }
+            }
+            self.record_change(changed);
+        }
+    }
+
+    /// Undo the last change
+    pub fn undo(&mut self) {
+        self.history.pending = None;
+        match self.history.undo.pop() {
+            Some(snapshot) => {
+                let current = self.restore(snapshot);
+                self.history.redo.push(current);
+                self.dirty = true;
+            },
+            None => self.status_bar.msg = "Already at oldest change".to_string(),
+        }
+    }
+
+    /// Redo the last undone change
+    pub fn redo(&mut self) {
+        self.history.pending = None;
+        match self.history.redo.pop() {
+            Some(snapshot) => {
+                let current = self.restore(snapshot);
+                self.history.undo.push(current);
+                self.dirty = true;
+            },
+            None => self.status_bar.msg = "Already at newest change".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn test_undo_insert() {
+        let mut editor = Editor::with_text("one\ntwo");
+        editor.type_str("ihello ");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.text_string(), "hello one\ntwo");
+
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "one\ntwo");
+
+        editor.keys(&[Key::Ctrl(true), Key::Char('r'), Key::Ctrl(false)]);
+        assert_eq!(editor.text_string(), "hello one\ntwo");
+    }
+
+    #[test]
+    fn test_undo_groups_insert_session() {
+        let mut editor = Editor::with_text("");
+        editor.type_str("ia");
+        editor.keys(&[Key::Enter]);
+        editor.type_str("b");
+        editor.keys(&[Key::Escape]);
+        editor.type_str("ic");
+        editor.keys(&[Key::Escape]);
+        assert_eq!(editor.history.undo.len(), 2);
+
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "a\nb");
+        editor.type_str("u");
+        assert_eq!(editor.text_string(), "");
+    }
+
+    #[test]
+    fn test_motions_leave_history() {
+        let mut editor = Editor::with_text("one\ntwo");
+        editor.type_str("jlhk");
+        assert!(editor.history.undo.is_empty());
+
+        editor.type_str("x");
+        assert_eq!(editor.history.undo.len(), 1);
+        assert_eq!(editor.text_string(), "ne\ntwo");
+
+        // A command that changes nothing is not a step
+        let mut editor = Editor::with_text("");
+        editor.type_str("x");
+        assert!(editor.history.undo.is_empty());
+    }
+}
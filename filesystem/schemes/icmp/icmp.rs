@@ -1,3 +1,17 @@
+use redox::Box;
+use redox::fs::File;
+use redox::io::{Read, Write, SeekFrom};
+use redox::mem;
+use redox::net::*;
+use redox::ptr;
+use redox::rand;
+use redox::slice;
+use redox::{String, ToString};
+use redox::syscall::sys_yield;
+use redox::time::{Duration, Instant};
+use redox::Vec;
+use redox::URL;
+
 #[derive(Copy, Clone)]
 #[repr(packed)]
 pub struct ICMPHeader {
@@ -12,14 +26,19 @@ pub struct ICMP {
     pub data: Vec<u8>,
 }
 
+pub const ICMP_ECHO_REPLY: u8 = 0x00;
+pub const ICMP_ECHO_REQUEST: u8 = 0x08;
+
+/// The number of seconds to wait for an echo reply
+pub const ICMP_TIMEOUT: i64 = 5;
+
 impl FromBytes for ICMP {
     fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
-        if bytes.len() >= size_of::<ICMPHeader>() {
+        if bytes.len() >= mem::size_of::<ICMPHeader>() {
             unsafe {
                 return Some(ICMP {
-                    header: *(bytes.as_ptr() as *const ICMPHeader),
-                    data: bytes.sub(size_of::<ICMPHeader>(),
-                                    bytes.len() - size_of::<ICMPHeader>()),
+                    header: ptr::read(bytes.as_ptr() as *const ICMPHeader),
+                    data: bytes[mem::size_of::<ICMPHeader>() ..].to_vec(),
                 });
             }
         }
@@ -31,46 +50,241 @@ impl ToBytes for ICMP {
     fn to_bytes(&self) -> Vec<u8> {
         unsafe {
             let header_ptr: *const ICMPHeader = &self.header;
-            let mut ret = Vec::from_raw_buf(header_ptr as *const u8, size_of::<ICMPHeader>());
+            let mut ret = Vec::from(slice::from_raw_parts(header_ptr as *const u8, mem::size_of::<ICMPHeader>()));
             ret.push_all(&self.data);
             ret
         }
     }
 }
 
+impl ICMP {
+    /// Create an echo request or reply, identified by the identifier and sequence number
+    pub fn echo(_type: u8, identifier: u16, sequence: u16, data: Vec<u8>) -> ICMP {
+        let mut icmp = ICMP {
+            header: ICMPHeader {
+                _type: _type,
+                code: 0,
+                checksum: Checksum { data: 0 },
+                data: [(identifier >> 8) as u8, identifier as u8, (sequence >> 8) as u8, sequence as u8],
+            },
+            data: data,
+        };
+
+        icmp.header.checksum.data = unsafe { Checksum::compile(icmp.sum()) };
+
+        icmp
+    }
+
+    /// Sum the message, for computing or checking the checksum. Unlike TCP and UDP, there is no pseudo header.
+    pub unsafe fn sum(&self) -> usize {
+        Checksum::sum((&self.header as *const ICMPHeader) as usize, mem::size_of::<ICMPHeader>()) +
+        Checksum::sum(self.data.as_ptr() as usize, self.data.len())
+    }
+
+    /// Check the checksum of a received message
+    pub fn valid(&self) -> bool {
+        unsafe { Checksum::compile(self.sum()) == 0 }
+    }
+
+    /// Get the identifier and sequence number of an echo request or reply
+    pub fn echo_id(&self) -> (u16, u16) {
+        let data = self.header.data;
+        (((data[0] as u16) << 8) | data[1] as u16, ((data[2] as u16) << 8) | data[3] as u16)
+    }
+
+    /// Create the reply to a valid echo request, carrying the same identifier, sequence number and data
+    pub fn reply(&self) -> Option<ICMP> {
+        if self.header._type == ICMP_ECHO_REQUEST && self.valid() {
+            let (identifier, sequence) = self.echo_id();
+            Some(ICMP::echo(ICMP_ECHO_REPLY, identifier, sequence, self.data.clone()))
+        } else {
+            None
+        }
+    }
+}
+
+/// An ICMP resource, sending echo requests to a host
+pub struct Resource {
+    ip: File,
+    peer_addr: IPv4Addr,
+    /// The identifier of our echo requests
+    identifier: u16,
+    /// The sequence number of the last echo request
+    sequence: u16,
+    /// When the last echo request was sent, if its reply was not read yet
+    sent: Option<Instant>,
+}
+
+impl Resource {
+    pub fn dup(&self) -> Option<Box<Self>> {
+        match self.ip.dup() {
+            Some(ip) => Some(box Resource {
+                ip: ip,
+                peer_addr: self.peer_addr,
+                identifier: self.identifier,
+                sequence: self.sequence,
+                sent: self.sent,
+            }),
+            None => None
+        }
+    }
+
+    pub fn path(&self) -> Option<String> {
+        Some(format!("icmp://{}", self.peer_addr.to_string()))
+    }
+
+    /// Wait for the reply to the last echo request, reading its round trip time in milliseconds
+    ///
+    /// Returns `None` if no request is outstanding, or if the reply does not arrive in time.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let sent = match self.sent.take() {
+            Some(sent) => sent,
+            None => return None,
+        };
+
+        let deadline = sent + Duration::new(ICMP_TIMEOUT, 0);
+        while Instant::now() < deadline {
+            let mut bytes = [0; 4096];
+            match self.ip.read(&mut bytes) {
+                Some(0) => unsafe { sys_yield() },
+                Some(count) => {
+                    if let Some(message) = ICMP::from_bytes(bytes[.. count].to_vec()) {
+                        if message.header._type == ICMP_ECHO_REPLY &&
+                           message.echo_id() == (self.identifier, self.sequence) &&
+                           message.valid() {
+                            let rtt = Instant::now() - sent;
+                            let micros = rtt.total_nanos() / 1000;
+                            let time = format!("{}.{:03}", micros / 1000, micros % 1000);
+
+                            let mut i = 0;
+                            for b in time.bytes() {
+                                if i >= buf.len() {
+                                    break;
+                                }
+                                buf[i] = b;
+                                i += 1;
+                            }
+                            return Some(i);
+                        }
+                    }
+                }
+                None => return None,
+            }
+        }
+
+        None
+    }
+
+    /// Send an echo request, carrying the data
+    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let icmp = ICMP::echo(ICMP_ECHO_REQUEST, self.identifier, self.sequence, Vec::from(buf));
+
+        self.sent = Some(Instant::now());
+        match self.ip.write(&icmp.to_bytes()) {
+            Some(_) => Some(buf.len()),
+            None => {
+                self.sent = None;
+                None
+            }
+        }
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> Option<usize> {
+        None
+    }
+
+    pub fn sync(&mut self) -> bool {
+        self.ip.sync()
+    }
+}
+
+/// ICMP scheme
 pub struct Scheme;
 
 impl Scheme {
-    pub fn run(&mut self){
+    pub fn new() -> Box<Self> {
+        box Scheme
+    }
+
+    /// Answer echo requests sent to this host
+    pub fn run(&mut self) {
         while let Some(mut ip) = URL::from_str("ip:///1").open() {
             let mut bytes: Vec<u8> = Vec::new();
             match ip.read_to_end(&mut bytes) {
                 Some(_) => {
                     if let Some(message) = ICMP::from_bytes(bytes) {
-                        if message.header._type == 0x08 {
-                            let mut response = ICMP {
-                                header: message.header,
-                                data: message.data,
-                            };
-
-                            response.header._type = 0x00;
-
-                            unsafe {
-                                response.header.checksum.data = 0;
-
-                                let header_ptr: *const ICMPHeader = &response.header;
-                                response.header.checksum.data = Checksum::compile(
-                                    Checksum::sum(header_ptr as usize, mem::size_of::<ICMPHeader>()) +
-                                    Checksum::sum(response.data.as_ptr() as usize, response.data.len())
-                                );
-                            }
-
-                            ip.write(&response.to_bytes().as_slice());
+                        if let Some(reply) = message.reply() {
+                            ip.write(&reply.to_bytes());
                         }
                     }
                 }
-                None => unsafe { context_switch(false) },
+                None => unsafe { sys_yield() },
             }
         }
     }
+
+    /// Open `icmp://host` to ping a host
+    pub fn open(&mut self, url_str: &str) -> Option<Box<Resource>> {
+        let url = URL::from_str(&url_str);
+
+        let peer_addr = match IPv4Addr::parse(&url.host()) {
+            Some(addr) => addr,
+            None => return None,
+        };
+
+        match File::open(&format!("ip://{}/1", peer_addr.to_string())) {
+            Some(ip) => Some(box Resource {
+                ip: ip,
+                peer_addr: peer_addr,
+                identifier: rand() as u16,
+                sequence: 0,
+                sent: None,
+            }),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redox::Vec;
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        // 0x0800 + 0x0001 + 0x0001 in network order, complemented
+        let request = ICMP::echo(ICMP_ECHO_REQUEST, 1, 1, Vec::new());
+        assert_eq!(request.to_bytes(), vec![0x08, 0x00, 0xF7, 0xFD, 0x00, 0x01, 0x00, 0x01]);
+        assert!(request.valid());
+
+        // An odd length of data is padded with a zero byte
+        let request = ICMP::echo(ICMP_ECHO_REQUEST, 0x1234, 7, vec![1, 2, 3]);
+        let bytes = request.to_bytes();
+        assert_eq!(bytes.len(), 11);
+        let received = ICMP::from_bytes(bytes.clone()).unwrap();
+        assert!(received.valid());
+        assert_eq!(received.echo_id(), (0x1234, 7));
+        assert_eq!(received.data, vec![1, 2, 3]);
+
+        let mut corrupt = bytes.clone();
+        corrupt[9] ^= 0x40;
+        assert!(!ICMP::from_bytes(corrupt).unwrap().valid());
+    }
+
+    #[test]
+    fn test_reply() {
+        let request = ICMP::echo(ICMP_ECHO_REQUEST, 0xBEEF, 3, b"ping".to_vec());
+        let reply = request.reply().unwrap();
+        assert_eq!(reply.header._type, ICMP_ECHO_REPLY);
+        assert_eq!(reply.echo_id(), (0xBEEF, 3));
+        assert_eq!(reply.data, b"ping".to_vec());
+        assert!(ICMP::from_bytes(reply.to_bytes()).unwrap().valid());
+
+        // Replies, and requests with a bad checksum, are not answered
+        assert!(reply.reply().is_none());
+        let mut bytes = request.to_bytes();
+        bytes[2] ^= 1;
+        assert!(ICMP::from_bytes(bytes).unwrap().reply().is_none());
+    }
 }
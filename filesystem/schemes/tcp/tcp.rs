@@ -1,11 +1,17 @@
 use redox::boxed::Box;
+use redox::cell::RefCell;
+use redox::cmp::min;
+use redox::collections::{BTreeMap, VecDeque};
 use redox::fs::File;
 use redox::io::{Read, Write, SeekFrom};
 use redox::mem;
 use redox::net::*;
 use redox::rand;
+use redox::rc::Rc;
 use redox::slice;
 use redox::string::{String, ToString};
+use redox::syscall::sys_yield;
+use redox::time::{Duration, Instant};
 use redox::to_num::*;
 use redox::vec::Vec;
 use redox::URL;
@@ -23,6 +29,7 @@ pub struct TCPHeader {
     pub urgent_pointer: n16,
 }
 
+#[derive(Clone)]
 pub struct TCP {
     pub header: TCPHeader,
     pub options: Vec<u8>,
@@ -35,12 +42,28 @@ pub const TCP_RST: u16 = 1 << 2;
 pub const TCP_PSH: u16 = 1 << 3;
 pub const TCP_ACK: u16 = 1 << 4;
 
+pub const TCP_OPT_END: u8 = 0;
+pub const TCP_OPT_NOP: u8 = 1;
+pub const TCP_OPT_MSS: u8 = 2;
+
+/// The maximum segment size we accept, fitting an ethernet frame
+pub const TCP_MSS: u16 = 1460;
+/// The maximum segment size to assume when the peer does not send one
+pub const TCP_DEFAULT_MSS: u16 = 536;
+/// The receive window advertised when no received data is waiting to be read
+pub const TCP_WINDOW: u16 = 65535;
+
 impl FromBytes for TCP {
+    /// Parse a segment, returning `None` if it is shorter than its header or its data offset
+    /// points before the end of the fixed header or past the end of the segment
     fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
         if bytes.len() >= mem::size_of::<TCPHeader>() {
             unsafe {
                 let header = *(bytes.as_ptr() as *const TCPHeader);
                 let header_len = ((header.flags.get() & 0xF000) >> 10) as usize;
+                if header_len < mem::size_of::<TCPHeader>() || header_len > bytes.len() {
+                    return None;
+                }
 
                 return Some(TCP {
                     header: header,
@@ -65,27 +88,405 @@ impl ToBytes for TCP {
     }
 }
 
+impl TCP {
+    /// Get the maximum segment size from the options, if there is one
+    pub fn mss(&self) -> Option<u16> {
+        let mut i = 0;
+        while i < self.options.len() {
+            match self.options[i] {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => i += 1,
+                kind => {
+                    if i + 1 >= self.options.len() {
+                        break;
+                    }
+
+                    let len = self.options[i + 1] as usize;
+                    if len < 2 || i + len > self.options.len() {
+                        break;
+                    }
+
+                    if kind == TCP_OPT_MSS && len == 4 {
+                        return Some(((self.options[i + 2] as u16) << 8) | self.options[i + 3] as u16);
+                    }
+
+                    i += len;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Get the maximum segment size to use for sending to the peer that sent this SYN
+    pub fn negotiate_mss(&self) -> u16 {
+        match self.mss() {
+            Some(mss) if mss > 0 && mss < TCP_MSS => mss,
+            Some(_) => TCP_MSS,
+            None => TCP_DEFAULT_MSS,
+        }
+    }
+
+    /// Get the words of the header that change between retransmissions: the
+    /// acknowledgement number and the window size
+    fn mutable_words(&self) -> [u16; 3] {
+        unsafe {
+            let ack_num = &self.header.ack_num as *const n32 as *const u16;
+            let window_size = &self.header.window_size as *const n16 as *const u16;
+            [*ack_num, *ack_num.offset(1), *window_size]
+        }
+    }
+
+    /// Change the acknowledgement number and window size, updating the checksum
+    /// incrementally instead of summing the whole segment again
+    pub fn update(&mut self, ack_num: u32, window_size: u16) {
+        let old = self.mutable_words();
+        self.header.ack_num = n32::new(ack_num);
+        self.header.window_size = n16::new(window_size);
+        let new = self.mutable_words();
+
+        for i in 0..old.len() {
+            self.header.checksum.data = Checksum::update(self.header.checksum.data, old[i], new[i]);
+        }
+    }
+
+    /// Sum the pseudo header and the segment, for computing or checking the checksum
+    pub unsafe fn sum(&self, src_addr: &IPv4Addr, dst_addr: &IPv4Addr) -> usize {
+        let proto = n16::new(0x06);
+        let segment_len =
+            n16::new((mem::size_of::<TCPHeader>() + self.options.len() + self.data.len()) as u16);
+        Checksum::sum((src_addr as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
+        Checksum::sum((dst_addr as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
+        Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
+        Checksum::sum((&segment_len as *const n16) as usize, mem::size_of::<n16>()) +
+        Checksum::sum((&self.header as *const TCPHeader) as usize, mem::size_of::<TCPHeader>()) +
+        Checksum::sum(self.options.as_ptr() as usize, self.options.len()) +
+        Checksum::sum(self.data.as_ptr() as usize, self.data.len())
+    }
+}
+
+/// Check if a sequence number comes before another, in the circular sequence space (RFC 1982)
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Check if a sequence number comes before or is equal to another
+pub fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// Check if a segment carries data for an established connection, whether or not PSH is set
+pub fn is_data(segment: &TCP) -> bool {
+    (segment.header.flags.get() & (TCP_SYN | TCP_ACK)) == TCP_ACK && segment.data.len() > 0
+}
+
+/// The state of a TCP connection
+#[derive(Copy, Clone, PartialEq)]
+pub enum TCPState {
+    /// There is no connection
+    Closed,
+    /// Sent a SYN, waiting for the SYN-ACK
+    SynSent,
+    /// Received a SYN and sent a SYN-ACK, waiting for the ACK
+    SynReceived,
+    /// The connection is open in both directions
+    Established,
+    /// Sent a FIN, waiting for its ACK
+    FinWait1,
+    /// Our FIN was acknowledged, waiting for the FIN of the peer
+    FinWait2,
+    /// Received the FIN of the peer, we can still send
+    CloseWait,
+    /// Both sides sent a FIN at the same time, waiting for the ACK of ours
+    Closing,
+    /// Sent our FIN after the FIN of the peer, waiting for its ACK
+    LastAck,
+    /// Both FINs were acknowledged, waiting for delayed segments to expire
+    TimeWait,
+}
+
+impl TCPState {
+    /// Check if data can be sent in this state
+    pub fn can_send(&self) -> bool {
+        *self == TCPState::Established || *self == TCPState::CloseWait
+    }
+
+    /// Check if data can be received in this state
+    pub fn can_receive(&self) -> bool {
+        *self == TCPState::Established || *self == TCPState::FinWait1 || *self == TCPState::FinWait2
+    }
+}
+
+/// The number of times an unacknowledged segment is sent again before giving up
+pub const TCP_RETRIES: usize = 5;
+
+/// The number of seconds to wait for a connection to be accepted before giving up
+pub const TCP_CONNECT_TIMEOUT: i64 = 30;
+
+/// The number of milliseconds an ACK of received data is held, waiting for more to acknowledge
+pub const TCP_ACK_DELAY: i64 = 200;
+
+/// The number of random host ports tried before giving up on opening a connection
+pub const TCP_PORT_TRIES: usize = 64;
+/// The number of segments for other connections a receive drops in a row before backing off
+pub const TCP_STRAY_LIMIT: usize = 64;
+/// The number of milliseconds a receive waits after dropping `TCP_STRAY_LIMIT` segments
+pub const TCP_STRAY_BACKOFF: i64 = 10;
+
+/// The host ports in use by connections and listeners
+pub struct Ports {
+    /// The number of resources using each host port
+    used: BTreeMap<u16, usize>,
+    /// The number of resources of each connection, by host port and peer port
+    connections: BTreeMap<(u16, u16), usize>,
+    /// The host ports listening for connections
+    listening: Vec<u16>,
+}
+
+impl Ports {
+    pub fn new() -> Ports {
+        Ports {
+            used: BTreeMap::new(),
+            connections: BTreeMap::new(),
+            listening: Vec::new(),
+        }
+    }
+
+    /// Count a resource using a connection
+    pub fn acquire(&mut self, host_port: u16, peer_port: u16) {
+        *self.used.entry(host_port).or_insert(0) += 1;
+        *self.connections.entry((host_port, peer_port)).or_insert(0) += 1;
+    }
+
+    /// Stop counting a resource using a connection, freeing the ports when no resource is left
+    pub fn release(&mut self, host_port: u16, peer_port: u16) {
+        let unused = match self.used.get_mut(&host_port) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if unused {
+            self.used.remove(&host_port);
+        }
+
+        let closed = match self.connections.get_mut(&(host_port, peer_port)) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if closed {
+            self.connections.remove(&(host_port, peer_port));
+        }
+    }
+
+    /// Start listening on a host port
+    pub fn listen(&mut self, port: u16) {
+        if ! self.is_listening(port) {
+            self.listening.push(port);
+        }
+    }
+
+    /// Check if a host port is listening for connections
+    pub fn is_listening(&self, port: u16) -> bool {
+        self.listening.iter().any(|&listening| listening == port)
+    }
+
+    /// Check if a host port is used by a connection or a listener
+    pub fn in_use(&self, port: u16) -> bool {
+        self.used.contains_key(&port) || self.is_listening(port)
+    }
+
+    /// Check if a connection between a host port and a peer port is open
+    pub fn owns(&self, host_port: u16, peer_port: u16) -> bool {
+        self.connections.contains_key(&(host_port, peer_port))
+    }
+}
+
+/// The ports in use, shared by the scheme and its resources
+pub type PortTable = Rc<RefCell<Ports>>;
+
+/// Counters of the traffic on a connection, for debugging
+#[derive(Copy, Clone)]
+pub struct Stats {
+    /// The number of segments sent, including retransmissions
+    pub segments_sent: usize,
+    /// The number of valid segments received
+    pub segments_received: usize,
+    /// The number of segments sent again because they were not acknowledged in time
+    pub retransmits: usize,
+    /// The number of received segments dropped for a bad checksum
+    pub bad_checksums: usize,
+    /// The number of received segments dropped as they belong to another connection
+    pub strays: usize,
+    /// The number of received segments dropped as their header is malformed
+    pub malformed: usize,
+    /// The number of data bytes delivered to reads
+    pub bytes_in: usize,
+    /// The number of data bytes sent and acknowledged
+    pub bytes_out: usize,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            segments_sent: 0,
+            segments_received: 0,
+            retransmits: 0,
+            bad_checksums: 0,
+            strays: 0,
+            malformed: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+        }
+    }
+}
+
+/// A segment sent without waiting for its ACK, in non-blocking mode
+#[derive(Clone)]
+struct InFlight {
+    /// The sequence number after the data, which the peer acknowledges
+    end: u32,
+    segment: TCP,
+    /// When the segment is sent again if it was not acknowledged
+    deadline: Instant,
+    /// The time to wait for the ACK, doubled on each retry
+    timeout: Duration,
+    /// The number of times the segment was sent again
+    tries: usize,
+}
+
+/// The link that segments of a connection are sent and received over
+pub trait Link {
+    /// Read the next segment, returning `Some(0)` if none has arrived
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize>;
+    /// Send a segment
+    fn write(&mut self, buf: &[u8]) -> Option<usize>;
+    /// Open the link again
+    fn dup(&self) -> Option<Box<Link>>;
+    /// Wait for sent segments to be written
+    fn sync(&mut self) -> bool;
+}
+
+/// An IP resource is the link of connections to the peer it was opened for
+impl Link for File {
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        Read::read(self, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        Write::write(self, buf)
+    }
+
+    fn dup(&self) -> Option<Box<Link>> {
+        File::dup(self).map(|file| box file as Box<Link>)
+    }
+
+    fn sync(&mut self) -> bool {
+        File::sync(self)
+    }
+}
+
 /// A TCP resource
 pub struct Resource {
-    ip: File,
+    ip: Box<Link>,
+    ports: PortTable,
     peer_addr: IPv4Addr,
     peer_port: u16,
     host_port: u16,
     sequence: u32,
     acknowledge: u32,
+    /// The number of retransmissions of an unacknowledged segment
+    retries: usize,
+    /// The time to wait for an ACK before the first retransmission, doubled on each retry
+    timeout: Duration,
+    /// Received data waiting to be delivered, keyed by sequence number
+    reassembly: BTreeMap<u32, Vec<u8>>,
+    /// Received data in order, not yet consumed by a read
+    received: VecDeque<u8>,
+    /// The capacity of the receive buffer
+    receive_buffer: usize,
+    /// The traffic counters
+    stats: Stats,
+    /// The maximum segment size negotiated with the peer
+    mss: u16,
+    /// The receive window last advertised by the peer
+    peer_window: u16,
+    /// The state of the connection
+    state: TCPState,
+    /// The sequence number of the FIN of the peer, once it was received
+    fin: Option<u32>,
+    /// If set, reads and writes return instead of waiting for the peer
+    nonblocking: bool,
+    /// Segments sent in non-blocking mode that were not acknowledged yet, in sequence order
+    in_flight: VecDeque<InFlight>,
+    /// The number of delivered segments we did not acknowledge yet
+    unacked: usize,
+    /// When the ACK of the delivered segments is due
+    ack_deadline: Option<Instant>,
 }
 
 impl Resource {
+    pub fn new(ip: Box<Link>, ports: PortTable, peer_addr: IPv4Addr, peer_port: u16, host_port: u16, sequence: u32, acknowledge: u32) -> Box<Resource> {
+        ports.borrow_mut().acquire(host_port, peer_port);
+
+        box Resource {
+            ip: ip,
+            ports: ports,
+            peer_addr: peer_addr,
+            peer_port: peer_port,
+            host_port: host_port,
+            sequence: sequence,
+            acknowledge: acknowledge,
+            retries: TCP_RETRIES,
+            timeout: Duration::new(1, 0),
+            reassembly: BTreeMap::new(),
+            received: VecDeque::new(),
+            receive_buffer: TCP_WINDOW as usize,
+            stats: Stats::new(),
+            mss: TCP_DEFAULT_MSS,
+            peer_window: TCP_WINDOW,
+            state: TCPState::Closed,
+            fin: None,
+            nonblocking: false,
+            in_flight: VecDeque::new(),
+            unacked: 0,
+            ack_deadline: None,
+        }
+    }
+
     pub fn dup(&self) -> Option<Box<Resource>> {
         match self.ip.dup() {
-            Some(ip) => Some(box Resource {
-                ip: ip,
-                peer_addr: self.peer_addr,
-                peer_port: self.peer_port,
-                host_port: self.host_port,
-                sequence: self.sequence,
-                acknowledge: self.acknowledge,
-            }),
+            Some(ip) => {
+                self.ports.borrow_mut().acquire(self.host_port, self.peer_port);
+
+                Some(box Resource {
+                    ip: ip,
+                    ports: self.ports.clone(),
+                    peer_addr: self.peer_addr,
+                    peer_port: self.peer_port,
+                    host_port: self.host_port,
+                    sequence: self.sequence,
+                    acknowledge: self.acknowledge,
+                    retries: self.retries,
+                    timeout: self.timeout,
+                    reassembly: self.reassembly.clone(),
+                    received: self.received.clone(),
+                    receive_buffer: self.receive_buffer,
+                    stats: self.stats,
+                    mss: self.mss,
+                    peer_window: self.peer_window,
+                    state: self.state,
+                    fin: self.fin,
+                    nonblocking: self.nonblocking,
+                    in_flight: self.in_flight.clone(),
+                    unacked: self.unacked,
+                    ack_deadline: self.ack_deadline,
+                })
+            }
             None => None
         }
     }
@@ -94,70 +495,71 @@ impl Resource {
         Some(format!("tcp://{}:{}/{}", self.peer_addr.to_string(), self.peer_port, self.host_port as usize))
     }
 
-    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
-        loop {
-            let mut bytes: Vec<u8> = Vec::new();
-            match self.ip.read_to_end(&mut bytes) {
-                Some(_) => {
-                    if let Some(segment) = TCP::from_bytes(bytes) {
-                        if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) ==
-                           (TCP_PSH | TCP_ACK) &&
-                           segment.header.dst.get() == self.host_port &&
-                           segment.header.src.get() == self.peer_port {
-                            //Send ACK
-                            self.sequence = segment.header.ack_num.get();
-                            self.acknowledge = segment.header.sequence.get() +
-                                               segment.data.len() as u32;
-                            let mut tcp = TCP {
-                                header: TCPHeader {
-                                    src: n16::new(self.host_port),
-                                    dst: n16::new(self.peer_port),
-                                    sequence: n32::new(self.sequence),
-                                    ack_num: n32::new(self.acknowledge),
-                                    flags: n16::new(((mem::size_of::<TCPHeader>() << 10) & 0xF000) as u16 | TCP_ACK),
-                                    window_size: n16::new(65535),
-                                    checksum: Checksum {
-                                        data: 0
-                                    },
-                                    urgent_pointer: n16::new(0)
-                                },
-                                options: Vec::new(),
-                                data: Vec::new()
-                            };
+    /// Get the address and port of the peer
+    pub fn peer_addr(&self) -> (IPv4Addr, u16) {
+        (self.peer_addr, self.peer_port)
+    }
 
-                            unsafe {
-                                let proto = n16::new(0x06);
-                                let segment_len = n16::new((mem::size_of::<TCPHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-                                tcp.header.checksum.data = Checksum::compile(
-                                    Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
-                                    Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
-                                    Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
-                                    Checksum::sum((&segment_len as *const n16) as usize, mem::size_of::<n16>()) +
-                                    Checksum::sum((&tcp.header as *const TCPHeader) as usize, mem::size_of::<TCPHeader>()) +
-                                    Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                    Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len())
-                                );
-                            }
+    /// Get the local port of the connection
+    pub fn local_port(&self) -> u16 {
+        self.host_port
+    }
 
-                            self.ip.write(&tcp.to_bytes());
+    /// Get the state of the connection
+    pub fn state(&self) -> TCPState {
+        self.state
+    }
 
-                            //TODO: Support broken packets (one packet in two buffers)
-                            let mut i = 0;
-                            while i < buf.len() && i < segment.data.len() {
-                                buf[i] = segment.data[i];
-                                i += 1;
-                            }
-                            return Some(i);
-                        }
-                    }
-                }
-                None => return None,
-            }
+    /// Get the number of received segments that were dropped for a bad checksum
+    pub fn bad_checksums(&self) -> usize {
+        self.stats.bad_checksums
+    }
+
+    /// Get the traffic counters of the connection
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Get the capacity of the receive buffer
+    pub fn receive_buffer(&self) -> usize {
+        self.receive_buffer
+    }
+
+    /// Set the capacity of the receive buffer, which holds received data until it is read
+    ///
+    /// The receive window advertised to the peer is the room left in the buffer.
+    pub fn set_receive_buffer(&mut self, size: usize) {
+        self.receive_buffer = size;
+    }
+
+    /// Check if reads and writes return instead of waiting for the peer
+    pub fn nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
+    /// Set whether reads and writes return instead of waiting for the peer
+    ///
+    /// A non-blocking read returns `Some(0)` if no data has arrived, and a non-blocking write
+    /// sends as much as the window of the peer allows, retransmitting it on later calls until
+    /// it is acknowledged. Switching back to blocking mode waits for the data in flight.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        if ! nonblocking {
+            self.flush();
         }
+        self.nonblocking = nonblocking;
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
-        let tcp_data = Vec::from(buf);
+    /// Create a segment for this connection using the current sequence and acknowledge numbers
+    ///
+    /// A SYN carries our maximum segment size as an option.
+    fn segment(&self, flags: u16, data: Vec<u8>) -> TCP {
+        let mut options = Vec::new();
+        if flags & TCP_SYN == TCP_SYN {
+            options.push(TCP_OPT_MSS);
+            options.push(4);
+            options.push((TCP_MSS >> 8) as u8);
+            options.push(TCP_MSS as u8);
+        }
 
         let mut tcp = TCP {
             header: TCPHeader {
@@ -165,339 +567,1687 @@ impl Resource {
                 dst: n16::new(self.peer_port),
                 sequence: n32::new(self.sequence),
                 ack_num: n32::new(self.acknowledge),
-                flags: n16::new((((mem::size_of::<TCPHeader>()) << 10) & 0xF000) as u16 | TCP_PSH |
-                                TCP_ACK),
-                window_size: n16::new(65535),
+                flags: n16::new((((mem::size_of::<TCPHeader>() + options.len()) << 10) & 0xF000) as u16 | flags),
+                window_size: n16::new(self.window()),
                 checksum: Checksum { data: 0 },
                 urgent_pointer: n16::new(0),
             },
-            options: Vec::new(),
-            data: tcp_data,
+            options: options,
+            data: data,
         };
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len = n16::new((mem::size_of::<TCPHeader>() + tcp.data.len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TCPHeader) as usize,
-                                                mem::size_of::<TCPHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
-        }
-
-        match self.ip.write(&tcp.to_bytes()) {
-            Some(size) => loop { // Wait for ACK
-                let mut bytes: Vec<u8> = Vec::new();
-                match self.ip.read_to_end(&mut bytes) {
-                    Some(_) => {
-                        if let Some(segment) = TCP::from_bytes(bytes) {
-                            if segment.header.dst.get() == self.host_port &&
-                               segment.header.src.get() == self.peer_port {
-                                if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) ==
-                                   TCP_ACK {
-                                    self.sequence = segment.header.ack_num.get();
-                                    self.acknowledge = segment.header.sequence.get();
-                                    return Some(size);
-                                } else {
-                                    return None;
-                                }
-                            }
-                        }
-                    }
-                    None => return None,
-                }
-            },
-            None => return None,
+        tcp.header.checksum.data = unsafe { Checksum::compile(tcp.sum(&IP_ADDR, &self.peer_addr)) };
+
+        tcp
+    }
+
+    /// Write a segment to the IP resource, counting it
+    ///
+    /// A segment with an ACK carries the delayed ACK, if there is one.
+    fn transmit(&mut self, tcp: &TCP) -> Option<usize> {
+        if tcp.header.flags.get() & TCP_ACK == TCP_ACK {
+            self.unacked = 0;
+            self.ack_deadline = None;
         }
+
+        self.stats.segments_sent += 1;
+        self.ip.write(&tcp.to_bytes())
     }
 
-    pub fn seek(&mut self, pos: SeekFrom) -> Option<usize> {
-        return None;
+    /// Acknowledge delivered segments, holding the ACK to acknowledge more at once
+    ///
+    /// The ACK is sent with the second segment, when `TCP_ACK_DELAY` passes, or with our next segment.
+    /// Without a timer, the deadline is checked on every read and while waiting for a segment.
+    fn delay_ack(&mut self, segments: usize) {
+        self.unacked += segments;
+        if self.unacked >= 2 {
+            let tcp = self.segment(TCP_ACK, Vec::new());
+            self.transmit(&tcp);
+        } else if self.ack_deadline.is_none() {
+            self.ack_deadline = Some(Instant::now() + Duration::from_millis(TCP_ACK_DELAY));
+        }
     }
 
-    pub fn sync(&mut self) -> bool {
-        return self.ip.sync();
+    /// Send the delayed ACK if it is due
+    fn flush_ack(&mut self) {
+        if let Some(deadline) = self.ack_deadline {
+            if Instant::now() >= deadline {
+                let tcp = self.segment(TCP_ACK, Vec::new());
+                self.transmit(&tcp);
+            }
+        }
     }
 
-    /// Etablish client
-    pub fn client_establish(&mut self) -> bool {
-        // Send SYN
-        let mut tcp = TCP {
-            header: TCPHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new(((mem::size_of::<TCPHeader>() << 10) & 0xF000) as u16 | TCP_SYN),
-                window_size: n16::new(65535),
-                checksum: Checksum { data: 0 },
-                urgent_pointer: n16::new(0),
-            },
-            options: Vec::new(),
-            data: Vec::new(),
-        };
+    /// Get the receive window to advertise: the room left in the receive buffer, up to `TCP_WINDOW`
+    ///
+    /// Data queued out of order is not counted, as it lies within the window already advertised.
+    fn window(&self) -> u16 {
+        min(self.receive_buffer.saturating_sub(self.received.len()), TCP_WINDOW as usize) as u16
+    }
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len =
-                n16::new((mem::size_of::<TCPHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TCPHeader) as usize,
-                                                mem::size_of::<TCPHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
-        }
-
-        match self.ip.write(&tcp.to_bytes()) {
-            Some(_) => loop { // Wait for SYN-ACK
-                let mut bytes: Vec<u8> = Vec::new();
-                match self.ip.read_to_end(&mut bytes) {
-                    Some(_) => {
-                        if let Some(segment) = TCP::from_bytes(bytes) {
-                            if segment.header.dst.get() == self.host_port &&
-                               segment.header.src.get() == self.peer_port {
-                                if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) ==
-                                   (TCP_SYN | TCP_ACK) {
-                                    self.sequence = segment.header.ack_num.get();
-                                    self.acknowledge = segment.header.sequence.get();
-
-                                    self.acknowledge += 1;
-                                    tcp = TCP {
-                                        header: TCPHeader {
-                                            src: n16::new(self.host_port),
-                                            dst: n16::new(self.peer_port),
-                                            sequence: n32::new(self.sequence),
-                                            ack_num: n32::new(self.acknowledge),
-                                            flags: n16::new(((mem::size_of::<TCPHeader>() << 10) & 0xF000) as u16 | TCP_ACK),
-                                            window_size: n16::new(65535),
-                                            checksum: Checksum {
-                                                data: 0
-                                            },
-                                            urgent_pointer: n16::new(0)
-                                        },
-                                        options: Vec::new(),
-                                        data: Vec::new()
-                                    };
-
-                                    unsafe {
-                                        let proto = n16::new(0x06);
-                                        let segment_len = n16::new((mem::size_of::<TCPHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-                                        tcp.header.checksum.data = Checksum::compile(
-                                            Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
-                                            Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
-                                            Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
-                                            Checksum::sum((&segment_len as *const n16) as usize, mem::size_of::<n16>()) +
-                                            Checksum::sum((&tcp.header as *const TCPHeader) as usize, mem::size_of::<TCPHeader>()) +
-                                            Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                            Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len())
-                                        );
-                                    }
+    /// Wait for the next segment sent to this connection
+    ///
+    /// Segments that are malformed, have a bad checksum, or are for other connections are dropped,
+    /// yielding after each so they cannot keep us busy. After `TCP_STRAY_LIMIT` segments for other
+    /// connections in a row, we back off for `TCP_STRAY_BACKOFF`.
+    /// Those sent to a pair of ports without a connection are answered with a RST, unless they
+    /// request a connection to a listening port.
+    ///
+    /// Returns `None` if the IP resource fails, or if a deadline is given and passes first.
+    /// The link is always read once, so a segment that has already arrived is taken even if
+    /// the deadline is now.
+    fn recv(&mut self, deadline: Option<Instant>) -> Option<TCP> {
+        let mut strays = 0;
+        let mut first = true;
+        while self.state != TCPState::Closed {
+            self.flush_ack();
 
-                                    self.ip.write(&tcp.to_bytes());
+            if let Some(deadline) = deadline {
+                if ! first && Instant::now() > deadline {
+                    return None;
+                }
+            }
+            first = false;
 
-                                    return true;
-                                } else {
-                                    return false;
-                                }
-                            }
-                        }
-                    }
-                    None => return false,
+            let mut bytes = [0; 4096];
+            let count = match self.ip.read(&mut bytes) {
+                Some(0) => {
+                    unsafe { sys_yield() };
+                    continue;
                 }
-            },
-            None => return false,
+                Some(count) => count,
+                None => return None,
+            };
+
+            let dropped = self.stats.strays;
+            if let Some(segment) = self.filter(&bytes[.. count]) {
+                return Some(segment);
+            }
+
+            // Only segments for other connections count toward the limit, the rest are just dropped
+            if self.stats.strays == dropped {
+                unsafe { sys_yield() };
+                continue;
+            }
+
+            strays += 1;
+            if strays >= TCP_STRAY_LIMIT {
+                strays = 0;
+                Duration::from_millis(TCP_STRAY_BACKOFF).sleep();
+            } else {
+                unsafe { sys_yield() };
+            }
         }
+
+        None
     }
 
-    /// Try to establish a server connection
-    pub fn server_establish(&mut self, syn: TCP) -> bool {
-        //Send SYN-ACK
-        self.acknowledge += 1;
-        let mut tcp = TCP {
-            header: TCPHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new(((mem::size_of::<TCPHeader>() << 10) & 0xF000) as u16 | TCP_SYN |
-                                TCP_ACK),
-                window_size: n16::new(65535),
-                checksum: Checksum { data: 0 },
-                urgent_pointer: n16::new(0),
-            },
-            options: Vec::new(),
-            data: Vec::new(),
+    /// Check a received segment, counting it, and return it if it is valid and for this connection
+    ///
+    /// A RST closes the connection. Segments that are malformed, corrupted, or for other
+    /// connections are dropped.
+    fn filter(&mut self, bytes: &[u8]) -> Option<TCP> {
+        let segment = match TCP::from_bytes(bytes.to_vec()) {
+            Some(segment) => segment,
+            None => {
+                self.stats.malformed += 1;
+                return None;
+            }
         };
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len =
-                n16::new((mem::size_of::<TCPHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TCPHeader) as usize,
-                                                mem::size_of::<TCPHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
-        }
-
-        match self.ip.write(&tcp.to_bytes()) {
-            Some(_) => loop { // Wait for ACK
-                let mut bytes: Vec<u8> = Vec::new();
-                match self.ip.read_to_end(&mut bytes) {
-                    Some(_) => {
-                        if let Some(segment) = TCP::from_bytes(bytes) {
-                            if segment.header.dst.get() == self.host_port &&
-                               segment.header.src.get() == self.peer_port {
-                                if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) ==
-                                   TCP_ACK {
-                                    self.sequence = segment.header.ack_num.get();
-                                    self.acknowledge = segment.header.sequence.get();
-                                    return true;
-                                } else {
-                                    return false;
-                                }
-                            }
-                        }
-                    }
-                    None => return false,
+        if segment.header.dst.get() == self.host_port && segment.header.src.get() == self.peer_port {
+            if unsafe { Checksum::compile(segment.sum(&self.peer_addr, &IP_ADDR)) } == 0 {
+                if segment.header.flags.get() & TCP_RST == TCP_RST {
+                    self.state = TCPState::Closed;
+                } else {
+                    self.peer_window = segment.header.window_size.get();
+                    self.stats.segments_received += 1;
+                    return Some(segment);
                 }
-            },
-            None => return false,
-        }
-    }
-}
+            } else {
+                self.stats.bad_checksums += 1;
+            }
+        } else {
+            let dst = segment.header.dst.get();
+            let src = segment.header.src.get();
+            let request = segment.header.flags.get() & (TCP_SYN | TCP_ACK) == TCP_SYN;
+            let (listening, owned) = {
+                let ports = self.ports.borrow();
+                (ports.is_listening(dst), ports.owns(dst, src))
+            };
 
-impl Drop for Resource {
-    fn drop(&mut self) {
-        //Send FIN-ACK
-        let mut tcp = TCP {
-            header: TCPHeader {
-                src: n16::new(self.host_port),
-                dst: n16::new(self.peer_port),
-                sequence: n32::new(self.sequence),
-                ack_num: n32::new(self.acknowledge),
-                flags: n16::new((((mem::size_of::<TCPHeader>()) << 10) & 0xF000) as u16 | TCP_FIN |
-                                TCP_ACK),
-                window_size: n16::new(65535),
-                checksum: Checksum { data: 0 },
-                urgent_pointer: n16::new(0),
-            },
-            options: Vec::new(),
-            data: Vec::new(),
-        };
+            // Connection requests are left for the listener, and segments for
+            // other connections to the peer are left for them
+            if ! (request && listening) && ! owned {
+                reset_reply(&mut *self.ip, &self.peer_addr, &segment);
+            }
 
-        unsafe {
-            let proto = n16::new(0x06);
-            let segment_len =
-                n16::new((mem::size_of::<TCPHeader>() + tcp.options.len() + tcp.data.len()) as u16);
-            tcp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&segment_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&tcp.header as *const TCPHeader) as usize,
-                                                mem::size_of::<TCPHeader>()) +
-                                  Checksum::sum(tcp.options.as_ptr() as usize, tcp.options.len()) +
-                                  Checksum::sum(tcp.data.as_ptr() as usize, tcp.data.len()));
-        }
-
-        self.ip.write(&tcp.to_bytes());
-    }
-}
+            self.stats.strays += 1;
+        }
 
-/// A TCP scheme
-pub struct Scheme;
+        None
+    }
 
-impl Scheme {
-    pub fn new() -> Box<Scheme> {
-        box Scheme
+    /// Move received data into a buffer, keeping what does not fit for the next read
+    fn drain_received(&mut self, buf: &mut [u8]) -> usize {
+        let mut i = 0;
+        while i < buf.len() {
+            match self.received.pop_front() {
+                Some(b) => buf[i] = b,
+                None => break,
+            }
+            i += 1;
+        }
+        self.stats.bytes_in += i;
+        i
     }
 
-    pub fn open(&mut self, url_str: &str) -> Option<Box<Resource>> {
-        let url = URL::from_str(&url_str);
+    /// Abort the connection, telling the peer with a RST
+    pub fn reset(&mut self) {
+        if self.state != TCPState::Closed {
+            let tcp = self.segment(TCP_RST, Vec::new());
+            self.transmit(&tcp);
+            self.state = TCPState::Closed;
+        }
+    }
 
-        if url.host().len() > 0 && url.port().len() > 0 {
-            let peer_addr = IPv4Addr::from_string(&url.host());
-            let peer_port = url.port().to_num() as u16;
-            let host_port = (rand() % 32768 + 32768) as u16;
+    /// Read received data in order, waiting for it unless the connection is non-blocking
+    ///
+    /// Returns `None` if the connection was reset. Unrelated or malformed traffic does not end
+    /// the read, but only slows down how often it is checked.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        loop {
+            self.flush_ack();
 
-            if let Some(ip) = File::open(&("ip://".to_string() + &peer_addr.to_string() + "/6")) {
-                let mut ret = box Resource {
-                    ip: ip,
-                    peer_addr: peer_addr,
-                    peer_port: peer_port,
-                    host_port: host_port,
-                    sequence: rand() as u32,
-                    acknowledge: 0,
+            if ! self.state.can_receive() {
+                return match self.state {
+                    TCPState::Closed => None,
+                    _ => Some(0),
                 };
+            }
 
-                if ret.client_establish() {
-                    return Some(ret);
-                }
+            // Deliver data left over from an earlier read
+            if self.received.len() > 0 {
+                return Some(self.drain_received(buf));
             }
-        } else if url.path().len() > 0 {
-            let host_port = url.path().to_num() as u16;
 
-            while let Some(mut ip) = File::open("ip:///6") {
-                let mut bytes: Vec<u8> = Vec::new();
-                match ip.read_to_end(&mut bytes) {
-                    Some(_) => {
-                        if let Some(segment) = TCP::from_bytes(bytes) {
-                            if segment.header.dst.get() == host_port && (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == TCP_SYN {
-                                if let Some(path) = ip.path() {
-                                    let url = URL::from_string(&path);
-
-                                    let peer_addr = IPv4Addr::from_string(&url.host());
-
-                                    let mut ret = box Resource {
-                                        ip: ip,
-                                        peer_addr: peer_addr,
-                                        peer_port: segment.header.src.get(),
-                                        host_port: host_port,
-                                        sequence: rand() as u32,
-                                        acknowledge: segment.header.sequence.get(),
-                                    };
-
-                                    if ret.server_establish(segment) {
-                                        return Some(ret);
-                                    }
-                                }
-                            }
-                        }
+            // Deliver the queued data that continues the stream, if it has arrived
+            self.discard_delivered();
+            if let Some(data) = self.reassembly.remove(&self.acknowledge) {
+                self.acknowledge = self.acknowledge.wrapping_add(data.len() as u32);
+                self.received.extend(data.into_iter());
+                let mut segments = 1;
+
+                // Buffer following segments that fit completely
+                loop {
+                    let fits = match self.reassembly.get(&self.acknowledge) {
+                        Some(next) => self.received.len() + next.len() <= self.receive_buffer,
+                        None => false,
+                    };
+                    if ! fits {
+                        break;
+                    }
+
+                    if let Some(next) = self.reassembly.remove(&self.acknowledge) {
+                        self.acknowledge = self.acknowledge.wrapping_add(next.len() as u32);
+                        self.received.extend(next.into_iter());
+                        segments += 1;
                     }
-                    None => break,
                 }
+
+                self.delay_ack(segments);
+
+                return Some(self.drain_received(buf));
             }
-        }
 
-        None
+            // All data before the FIN of the peer was delivered
+            if self.fin == Some(self.acknowledge) {
+                self.acknowledge = self.acknowledge.wrapping_add(1);
+                self.state = match self.state {
+                    TCPState::FinWait1 => TCPState::Closing,
+                    TCPState::FinWait2 => TCPState::TimeWait,
+                    _ => TCPState::CloseWait,
+                };
+
+                let tcp = self.segment(TCP_ACK, Vec::new());
+                self.transmit(&tcp);
+
+                return Some(0);
+            }
+
+            if self.nonblocking {
+                self.retransmit();
+            }
+
+            // Without blocking, only take segments that have already arrived
+            let deadline = if self.nonblocking {
+                Some(Instant::now())
+            } else {
+                None
+            };
+
+            match self.recv(deadline) {
+                Some(segment) => {
+                    let flags = segment.header.flags.get();
+                    if flags & TCP_FIN == TCP_FIN {
+                        self.fin = Some(segment.header.sequence.get().wrapping_add(segment.data.len() as u32));
+                    }
+
+                    if flags & TCP_ACK == TCP_ACK {
+                        self.acked(segment.header.ack_num.get());
+                    }
+
+                    // Data is accepted with or without PSH, as it is delivered as soon as it is in order
+                    if is_data(&segment) {
+                        self.queue(segment);
+                    }
+                }
+                None => return if self.nonblocking && self.state != TCPState::Closed {
+                    Some(0)
+                } else {
+                    None
+                },
+            }
+        }
+    }
+
+    /// Queue the data of a segment for delivery in sequence order
+    fn queue(&mut self, segment: TCP) {
+        let mut sequence = segment.header.sequence.get();
+        let mut data = segment.data;
+
+        if seq_le(sequence.wrapping_add(data.len() as u32), self.acknowledge) {
+            // Already delivered, ACK again in case our ACK was lost
+            let tcp = self.segment(TCP_ACK, Vec::new());
+            self.transmit(&tcp);
+            return;
+        }
+
+        if seq_lt(sequence, self.acknowledge) {
+            // Drop the part that was already delivered
+            data = data[self.acknowledge.wrapping_sub(sequence) as usize ..].to_vec();
+            sequence = self.acknowledge;
+        }
+
+        // Drop the part past the window we advertised
+        let limit = self.acknowledge.wrapping_add(self.window() as u32);
+        if seq_le(limit, sequence) {
+            let tcp = self.segment(TCP_ACK, Vec::new());
+            self.transmit(&tcp);
+            return;
+        }
+        if seq_lt(limit, sequence.wrapping_add(data.len() as u32)) {
+            data.truncate(limit.wrapping_sub(sequence) as usize);
+        }
+
+        self.reassemble(sequence, data);
+
+        if sequence != self.acknowledge {
+            // Out of order, ACK what we have so the peer resends the gap
+            let tcp = self.segment(TCP_ACK, Vec::new());
+            self.transmit(&tcp);
+        }
+    }
+
+    /// Queue data for reassembly, replacing the parts of queued data it overlaps
+    fn reassemble(&mut self, sequence: u32, data: Vec<u8>) {
+        let end = sequence.wrapping_add(data.len() as u32);
+
+        let keys: Vec<u32> = self.reassembly.keys().cloned().collect();
+        for key in keys {
+            let queued = match self.reassembly.remove(&key) {
+                Some(queued) => queued,
+                None => continue,
+            };
+            let queued_end = key.wrapping_add(queued.len() as u32);
+
+            if seq_le(queued_end, sequence) || seq_le(end, key) {
+                // No overlap
+                self.reassembly.insert(key, queued);
+            } else if seq_le(key, sequence) && seq_le(end, queued_end) {
+                // Everything was already queued
+                self.reassembly.insert(key, queued);
+                return;
+            } else {
+                // Keep the parts of the queued data before and after the new data
+                if seq_lt(key, sequence) {
+                    self.reassembly.insert(key, queued[.. sequence.wrapping_sub(key) as usize].to_vec());
+                }
+                if seq_lt(end, queued_end) {
+                    self.reassembly.insert(end, queued[end.wrapping_sub(key) as usize ..].to_vec());
+                }
+            }
+        }
+
+        self.reassembly.insert(sequence, data);
+    }
+
+    /// Drop the queued data before the acknowledge number, keeping the rest of an entry it splits
+    fn discard_delivered(&mut self) {
+        let keys: Vec<u32> = self.reassembly.keys().cloned().collect();
+        for key in keys {
+            if seq_lt(key, self.acknowledge) {
+                if let Some(queued) = self.reassembly.remove(&key) {
+                    let end = key.wrapping_add(queued.len() as u32);
+                    if seq_lt(self.acknowledge, end) {
+                        let rest = queued[self.acknowledge.wrapping_sub(key) as usize ..].to_vec();
+                        self.reassembly.insert(self.acknowledge, rest);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write data in segments no larger than the MSS or the window of the peer
+    ///
+    /// Returns a short write if the window of the peer stays closed, or a segment is lost.
+    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        if self.nonblocking {
+            return self.write_nonblocking(buf);
+        }
+
+        let mut sent = 0;
+        while sent < buf.len() {
+            if self.peer_window == 0 && ! self.wait_window() {
+                break;
+            }
+
+            let len = min(buf.len() - sent, min(self.mss, self.peer_window) as usize);
+            if ! self.send(&buf[sent .. sent + len]) {
+                break;
+            }
+            sent += len;
+        }
+
+        if sent > 0 || buf.len() == 0 {
+            Some(sent)
+        } else {
+            None
+        }
+    }
+
+    /// Send as much data as the window of the peer allows, without waiting for ACKs
+    fn write_nonblocking(&mut self, buf: &[u8]) -> Option<usize> {
+        if ! self.state.can_send() {
+            return None;
+        }
+
+        // Take the ACKs that arrived since the last call
+        while let Some(segment) = self.recv(Some(Instant::now())) {
+            if segment.header.flags.get() & TCP_ACK == TCP_ACK {
+                self.acked(segment.header.ack_num.get());
+            }
+            if is_data(&segment) {
+                self.queue(segment);
+            }
+        }
+
+        if ! self.retransmit() {
+            return None;
+        }
+
+        let mut sent = 0;
+        while sent < buf.len() {
+            let in_flight = self.in_flight.iter().fold(0, |sum, f| sum + f.segment.data.len());
+            let room = (self.peer_window as usize).saturating_sub(in_flight);
+            let len = min(buf.len() - sent, min(self.mss as usize, room));
+            if len == 0 {
+                break;
+            }
+
+            let tcp = self.segment(TCP_PSH | TCP_ACK, Vec::from(&buf[sent .. sent + len]));
+            if self.transmit(&tcp).is_none() {
+                break;
+            }
+
+            self.sequence = self.sequence.wrapping_add(len as u32);
+            self.in_flight.push_back(InFlight {
+                end: self.sequence,
+                segment: tcp,
+                deadline: Instant::now() + self.timeout,
+                timeout: self.timeout,
+                tries: 0,
+            });
+            self.stats.bytes_out += len;
+            sent += len;
+        }
+
+        if sent > 0 || self.state.can_send() {
+            Some(sent)
+        } else {
+            None
+        }
+    }
+
+    /// Forget the segments in flight that an ACK acknowledges
+    fn acked(&mut self, ack_num: u32) {
+        while self.in_flight.front().map_or(false, |f| seq_le(f.end, ack_num)) {
+            self.in_flight.pop_front();
+        }
+    }
+
+    /// Send again the segments in flight that were not acknowledged in time
+    ///
+    /// Returns false, resetting the connection, if a segment was sent too many times.
+    fn retransmit(&mut self) -> bool {
+        let now = Instant::now();
+        for i in 0..self.in_flight.len() {
+            if self.in_flight[i].deadline > now {
+                continue;
+            }
+
+            if self.in_flight[i].tries >= self.retries {
+                self.in_flight.clear();
+                self.reset();
+                return false;
+            }
+
+            let mut flight = self.in_flight[i].clone();
+            flight.segment.update(self.acknowledge, self.window());
+            self.transmit(&flight.segment);
+            self.stats.retransmits += 1;
+
+            flight.tries += 1;
+            flight.timeout = flight.timeout * 2;
+            flight.deadline = now + flight.timeout;
+            self.in_flight[i] = flight;
+        }
+
+        true
+    }
+
+    /// Wait until the segments in flight are acknowledged, or the connection fails
+    fn flush(&mut self) {
+        while ! self.in_flight.is_empty() && self.state.can_send() {
+            let deadline = self.in_flight.iter().fold(self.in_flight[0].deadline, |deadline, f| {
+                if f.deadline < deadline { f.deadline } else { deadline }
+            });
+
+            if let Some(segment) = self.recv(Some(deadline)) {
+                if segment.header.flags.get() & TCP_ACK == TCP_ACK {
+                    self.acked(segment.header.ack_num.get());
+                }
+                if is_data(&segment) {
+                    self.queue(segment);
+                }
+            }
+
+            if ! self.retransmit() {
+                break;
+            }
+        }
+    }
+
+    /// Wait for the peer to open its receive window, returning false if it stays closed
+    fn wait_window(&mut self) -> bool {
+        let deadline = Instant::now() + self.timeout * (self.retries as i32 + 1);
+        while self.peer_window == 0 {
+            match self.recv(Some(deadline)) {
+                Some(segment) => {
+                    if is_data(&segment) {
+                        self.queue(segment);
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Send one segment of data and wait for it to be acknowledged
+    fn send(&mut self, buf: &[u8]) -> bool {
+        if ! self.state.can_send() {
+            return false;
+        }
+
+        let mut tcp = self.segment(TCP_PSH | TCP_ACK, Vec::from(buf));
+        let ack_num = self.sequence.wrapping_add(buf.len() as u32);
+
+        let mut timeout = self.timeout;
+        for attempt in 0..self.retries + 1 {
+            if attempt > 0 {
+                self.stats.retransmits += 1;
+            }
+
+            // Data may have arrived while waiting, so acknowledge it in the retransmission
+            tcp.update(self.acknowledge, self.window());
+
+            if self.transmit(&tcp).is_none() {
+                return false;
+            }
+
+            // Wait for ACK, sending the segment again if it does not arrive in time
+            let deadline = Instant::now() + timeout;
+            while let Some(segment) = self.recv(Some(deadline)) {
+                if (segment.header.flags.get() & (TCP_SYN | TCP_ACK)) == TCP_ACK {
+                    let acked = segment.header.ack_num.get() == ack_num;
+
+                    // The peer may send data along with the ACK
+                    if is_data(&segment) {
+                        self.queue(segment);
+                    }
+
+                    if acked {
+                        self.sequence = ack_num;
+                        self.stats.bytes_out += buf.len();
+                        return true;
+                    }
+                } else {
+                    return false;
+                }
+            }
+
+            timeout = timeout * 2;
+        }
+
+        false
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> Option<usize> {
+        return None;
+    }
+
+    pub fn sync(&mut self) -> bool {
+        return self.ip.sync();
+    }
+
+    /// Close the connection, waiting for both sides to acknowledge the FIN of the other
+    ///
+    /// The TIME-WAIT state only lingers for twice the retransmission timeout, rather than
+    /// twice the maximum segment lifetime, so that closing does not block for minutes.
+    pub fn close(&mut self) {
+        self.flush();
+
+        if ! self.state.can_send() {
+            self.state = TCPState::Closed;
+            return;
+        }
+
+        //Send FIN-ACK
+        let fin = self.segment(TCP_FIN | TCP_ACK, Vec::new());
+        self.sequence = self.sequence.wrapping_add(1);
+        self.state = match self.state {
+            TCPState::CloseWait => TCPState::LastAck,
+            _ => TCPState::FinWait1,
+        };
+
+        let mut timeout = self.timeout;
+        let mut tries = 0;
+        loop {
+            match self.state {
+                TCPState::FinWait1 | TCPState::Closing | TCPState::LastAck => {
+                    // Our FIN was not acknowledged yet
+                    if tries > self.retries || self.transmit(&fin).is_none() {
+                        break;
+                    }
+                    if tries > 0 {
+                        self.stats.retransmits += 1;
+                    }
+                    tries += 1;
+
+                    self.await_close(Instant::now() + timeout);
+                    timeout = timeout * 2;
+                }
+                TCPState::FinWait2 => {
+                    self.await_close(Instant::now() + timeout);
+                    if self.state == TCPState::FinWait2 {
+                        break;
+                    }
+                }
+                TCPState::TimeWait => {
+                    // Acknowledge the FIN again if the peer did not get our ACK
+                    self.await_close(Instant::now() + self.timeout * 2);
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        self.state = TCPState::Closed;
+    }
+
+    /// Handle segments while closing, until the state changes or the deadline passes
+    fn await_close(&mut self, deadline: Instant) {
+        let state = self.state;
+        while self.state == state {
+            match self.recv(Some(deadline)) {
+                Some(segment) => {
+                    let flags = segment.header.flags.get();
+
+                    if flags & TCP_ACK == TCP_ACK && segment.header.ack_num.get() == self.sequence {
+                        self.state = match self.state {
+                            TCPState::FinWait1 => TCPState::FinWait2,
+                            TCPState::Closing => TCPState::TimeWait,
+                            TCPState::LastAck => TCPState::Closed,
+                            other => other,
+                        };
+                    }
+
+                    if flags & TCP_FIN == TCP_FIN {
+                        let fin = segment.header.sequence.get().wrapping_add(segment.data.len() as u32);
+                        if self.acknowledge != fin.wrapping_add(1) {
+                            // Data that was not read yet is dropped, as we are closing
+                            self.fin = Some(fin);
+                            self.acknowledge = fin.wrapping_add(1);
+                            self.state = match self.state {
+                                TCPState::FinWait1 => TCPState::Closing,
+                                TCPState::FinWait2 => TCPState::TimeWait,
+                                other => other,
+                            };
+                        }
+
+                        let tcp = self.segment(TCP_ACK, Vec::new());
+                        self.transmit(&tcp);
+                    }
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Etablish client
+    pub fn client_establish(&mut self, connect_timeout: Duration) -> bool {
+        let tcp = self.segment(TCP_SYN, Vec::new());
+        self.state = TCPState::SynSent;
+
+        // Send SYN, sending it again if no SYN-ACK arrives in time, until the connect timeout
+        let connect_deadline = Instant::now() + connect_timeout;
+        let mut timeout = self.timeout;
+        for attempt in 0..self.retries + 1 {
+            if attempt > 0 {
+                self.stats.retransmits += 1;
+            }
+
+            if self.transmit(&tcp).is_none() {
+                break;
+            }
+
+            let mut deadline = Instant::now() + timeout;
+            if deadline > connect_deadline {
+                deadline = connect_deadline;
+            }
+
+            // Wait for SYN-ACK
+            if let Some(segment) = self.recv(Some(deadline)) {
+                if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == (TCP_SYN | TCP_ACK) {
+                    self.sequence = segment.header.ack_num.get();
+                    self.acknowledge = segment.header.sequence.get();
+
+                    self.acknowledge = self.acknowledge.wrapping_add(1);
+                    self.mss = segment.negotiate_mss();
+                    let tcp = self.segment(TCP_ACK, Vec::new());
+                    self.transmit(&tcp);
+
+                    self.state = TCPState::Established;
+                    return true;
+                }
+                break;
+            }
+
+            if Instant::now() > connect_deadline || self.state == TCPState::Closed {
+                break;
+            }
+
+            timeout = timeout * 2;
+        }
+
+        self.state = TCPState::Closed;
+        false
+    }
+
+    /// Try to establish a server connection
+    pub fn server_establish(&mut self, syn: TCP) -> bool {
+        //Send SYN-ACK
+        self.acknowledge = self.acknowledge.wrapping_add(1);
+        self.mss = syn.negotiate_mss();
+        self.peer_window = syn.header.window_size.get();
+        let tcp = self.segment(TCP_SYN | TCP_ACK, Vec::new());
+        if self.transmit(&tcp).is_none() {
+            return false;
+        }
+        self.state = TCPState::SynReceived;
+
+        // Wait for ACK
+        if let Some(segment) = self.recv(None) {
+            if (segment.header.flags.get() & (TCP_SYN | TCP_ACK)) == TCP_ACK {
+                self.sequence = segment.header.ack_num.get();
+                self.acknowledge = segment.header.sequence.get();
+
+                self.state = TCPState::Established;
+
+                // The ACK may already carry data
+                if is_data(&segment) {
+                    self.queue(segment);
+                }
+                return true;
+            }
+        }
+
+        self.state = TCPState::Closed;
+        false
+    }
+}
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        self.close();
+
+        // Release the host port
+        self.ports.borrow_mut().release(self.host_port, self.peer_port);
+    }
+}
+
+/// Send a RST in reply to a segment that does not belong to any connection
+pub fn reset_reply(ip: &mut Link, peer_addr: &IPv4Addr, segment: &TCP) {
+    let flags = segment.header.flags.get();
+    if flags & TCP_RST == TCP_RST {
+        return;
+    }
+
+    let (sequence, ack_num, reply_flags) = if flags & TCP_ACK == TCP_ACK {
+        (segment.header.ack_num.get(), 0, TCP_RST)
+    } else {
+        let mut len = segment.data.len() as u32;
+        if flags & TCP_SYN == TCP_SYN {
+            len += 1;
+        }
+        if flags & TCP_FIN == TCP_FIN {
+            len += 1;
+        }
+        (0, segment.header.sequence.get().wrapping_add(len), TCP_RST | TCP_ACK)
+    };
+
+    let mut tcp = TCP {
+        header: TCPHeader {
+            src: segment.header.dst,
+            dst: segment.header.src,
+            sequence: n32::new(sequence),
+            ack_num: n32::new(ack_num),
+            flags: n16::new(((mem::size_of::<TCPHeader>() << 10) & 0xF000) as u16 | reply_flags),
+            window_size: n16::new(0),
+            checksum: Checksum { data: 0 },
+            urgent_pointer: n16::new(0),
+        },
+        options: Vec::new(),
+        data: Vec::new(),
+    };
+
+    tcp.header.checksum.data = unsafe { Checksum::compile(tcp.sum(&IP_ADDR, peer_addr)) };
+
+    ip.write(&tcp.to_bytes());
+}
+
+/// The maximum number of connection requests queued on a listening port
+pub const TCP_BACKLOG: usize = 16;
+
+/// A connection request waiting to be accepted
+struct Pending {
+    ip: File,
+    peer_addr: IPv4Addr,
+    syn: TCP,
+}
+
+/// A listening port and the connection requests queued on it
+struct Listener {
+    port: u16,
+    backlog: VecDeque<Pending>,
+}
+
+/// A TCP scheme
+pub struct Scheme {
+    listeners: Vec<Listener>,
+    ports: PortTable,
+    /// The time to wait for a connection to be accepted before giving up
+    connect_timeout: Duration,
+}
+
+impl Scheme {
+    pub fn new() -> Box<Scheme> {
+        box Scheme {
+            listeners: Vec::new(),
+            ports: Rc::new(RefCell::new(Ports::new())),
+            connect_timeout: Duration::new(TCP_CONNECT_TIMEOUT, 0),
+        }
+    }
+
+    /// Set the time to wait for a connection to be accepted before giving up
+    pub fn set_connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Pick a random host port that is not in use by a connection or a listener
+    fn allocate_port(&self) -> Option<u16> {
+        for _ in 0..TCP_PORT_TRIES {
+            let port = (rand() % 32768 + 32768) as u16;
+            if ! self.ports.borrow().in_use(port) {
+                return Some(port);
+            }
+        }
+
+        None
+    }
+
+    /// Start listening on a port, if it is not already
+    fn listen(&mut self, port: u16) {
+        if ! self.listeners.iter().any(|listener| listener.port == port) {
+            self.ports.borrow_mut().listen(port);
+            self.listeners.push(Listener {
+                port: port,
+                backlog: VecDeque::new(),
+            });
+        }
+    }
+
+    /// Read the next connection request and queue it on its listening port
+    ///
+    /// Requests for ports that are not listening, or whose backlog is full, are dropped,
+    /// as are other segments, yielding after each.
+    /// Returns false if the IP scheme failed.
+    fn poll_syn(&mut self) -> bool {
+        if let Some(mut ip) = File::open("ip:///6") {
+            let mut bytes = [0; 4096];
+            if let Some(count) = Read::read(&mut ip, &mut bytes) {
+                if let Some(segment) = TCP::from_bytes(bytes[.. count].to_vec()) {
+                    if (segment.header.flags.get() & (TCP_PSH | TCP_SYN | TCP_ACK)) == TCP_SYN {
+                        if let Some(path) = ip.path() {
+                            let url = URL::from_string(&path);
+
+                            let peer_addr = IPv4Addr::from_string(&url.host());
+                            if unsafe { Checksum::compile(segment.sum(&peer_addr, &IP_ADDR)) } == 0 {
+                                let port = segment.header.dst.get();
+                                if let Some(listener) = self.listeners.iter_mut().find(|listener| listener.port == port) {
+                                    if listener.backlog.len() < TCP_BACKLOG {
+                                        listener.backlog.push_back(Pending {
+                                            ip: ip,
+                                            peer_addr: peer_addr,
+                                            syn: segment,
+                                        });
+                                        return true;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // The segment was dropped, let others run before reading the next
+                unsafe { sys_yield() };
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Open a connection, `tcp://host:port` to connect or `tcp:///port` to accept one
+    ///
+    /// The host is an address or a name, which is resolved with DNS.
+    ///
+    /// Adding `?nonblock` opens the connection in non-blocking mode.
+    pub fn open(&mut self, url_str: &str) -> Option<Box<Resource>> {
+        let url = URL::from_str(&url_str);
+        let nonblocking = url.query() == "nonblock";
+
+        if url.host().len() > 0 && url.port().len() > 0 {
+            let peer_addr = match dns::resolve(&url.host()) {
+                Some(addr) => addr,
+                None => return None,
+            };
+            let peer_port = url.port().to_num() as u16;
+            let host_port = match self.allocate_port() {
+                Some(port) => port,
+                None => return None,
+            };
+
+            if let Some(ip) = File::open(&("ip://".to_string() + &peer_addr.to_string() + "/6")) {
+                let mut ret = Resource::new(box ip, self.ports.clone(), peer_addr, peer_port, host_port, rand() as u32, 0);
+
+                if ret.client_establish(self.connect_timeout) {
+                    ret.nonblocking = nonblocking;
+                    return Some(ret);
+                }
+            }
+        } else if url.path().len() > 0 {
+            let host_port = url.path().to_num() as u16;
+            self.listen(host_port);
+
+            loop {
+                let pending = match self.listeners.iter_mut().find(|listener| listener.port == host_port) {
+                    Some(listener) => listener.backlog.pop_front(),
+                    None => None,
+                };
+
+                match pending {
+                    Some(pending) => {
+                        let mut ret = Resource::new(box pending.ip,
+                                                    self.ports.clone(),
+                                                    pending.peer_addr,
+                                                    pending.syn.header.src.get(),
+                                                    host_port,
+                                                    rand() as u32,
+                                                    pending.syn.header.sequence.get());
+
+                        if ret.server_establish(pending.syn) {
+                            ret.nonblocking = nonblocking;
+                            return Some(ret);
+                        }
+                    }
+                    None => if ! self.poll_syn() {
+                        break;
+                    },
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redox::boxed::Box;
+    use redox::cell::RefCell;
+    use redox::cmp::min;
+    use redox::collections::VecDeque;
+    use redox::fs::File;
+    use redox::mem;
+    use redox::net::*;
+    use redox::rc::Rc;
+    use redox::time::{Duration, Instant};
+    use redox::vec::Vec;
+    use super::*;
+
+    /// Create an established connection that writes its segments to `null://`
+    fn resource(acknowledge: u32) -> Box<Resource> {
+        let ip = File::open("null://").unwrap();
+        let mut resource = Resource::new(box ip, Rc::new(RefCell::new(Ports::new())), IP_ADDR, 80, 32768, 1000, acknowledge);
+        resource.state = TCPState::Established;
+        resource
+    }
+
+    /// Compute the checksum of a segment sent by the peer of `resource`
+    fn seal(tcp: &mut TCP) {
+        tcp.header.checksum.data = 0;
+        tcp.header.checksum.data = unsafe { Checksum::compile(tcp.sum(&IP_ADDR, &IP_ADDR)) };
+    }
+
+    /// Create a segment with the given flags sent by the peer of `resource`
+    fn segment(flags: u16, sequence: u32, ack_num: u32, bytes: &[u8]) -> TCP {
+        let mut tcp = TCP {
+            header: TCPHeader {
+                src: n16::new(80),
+                dst: n16::new(32768),
+                sequence: n32::new(sequence),
+                ack_num: n32::new(ack_num),
+                flags: n16::new(((mem::size_of::<TCPHeader>() << 10) & 0xF000) as u16 | flags),
+                window_size: n16::new(TCP_WINDOW),
+                checksum: Checksum { data: 0 },
+                urgent_pointer: n16::new(0),
+            },
+            options: Vec::new(),
+            data: bytes.to_vec(),
+        };
+        seal(&mut tcp);
+        tcp
+    }
+
+    /// Create a data segment sent by the peer of `resource`
+    fn data(sequence: u32, bytes: &[u8]) -> TCP {
+        segment(TCP_PSH | TCP_ACK, sequence, 1000, bytes)
+    }
+
+    /// Create a segment with the given options
+    fn with_options(flags: u16, options: &[u8]) -> TCP {
+        let mut tcp = data(0, b"");
+        tcp.header.flags = n16::new((((mem::size_of::<TCPHeader>() + options.len()) << 10) & 0xF000) as u16 | flags);
+        tcp.options = options.to_vec();
+        tcp
+    }
+
+    /// A peer that answers the segments sent to it, like the other end of a connection would
+    struct Peer {
+        /// Segments waiting to be received, in order
+        inbox: VecDeque<Vec<u8>>,
+        /// The segments sent to the peer
+        sent: Vec<TCP>,
+        /// If false, segments are only collected
+        answer: bool,
+        /// The sequence number of the next segment of the peer
+        sequence: u32,
+        /// The receive window advertised by the peer
+        window: u16,
+        /// The maximum segment size advertised by the peer
+        mss: u16,
+    }
+
+    impl Peer {
+        fn new() -> Rc<RefCell<Peer>> {
+            Rc::new(RefCell::new(Peer {
+                inbox: VecDeque::new(),
+                sent: Vec::new(),
+                answer: true,
+                sequence: 5000,
+                window: TCP_WINDOW,
+                mss: TCP_MSS,
+            }))
+        }
+
+        /// Queue a segment to be received
+        fn push(&mut self, tcp: &TCP) {
+            self.inbox.push_back(tcp.to_bytes());
+        }
+
+        /// Queue a reply, advertising the window of the peer
+        fn reply(&mut self, flags: u16, ack_num: u32) {
+            let mut tcp = segment(flags, self.sequence, ack_num, b"");
+            tcp.header.window_size = n16::new(self.window);
+            if flags & TCP_SYN == TCP_SYN {
+                tcp.options = vec![TCP_OPT_MSS, 4, (self.mss >> 8) as u8, self.mss as u8];
+                tcp.header.flags = n16::new((((mem::size_of::<TCPHeader>() + 4) << 10) & 0xF000) as u16 | flags);
+                self.sequence = self.sequence.wrapping_add(1);
+            }
+            seal(&mut tcp);
+            self.push(&tcp);
+        }
+
+        /// Answer a SYN with a SYN-ACK, and acknowledge a SYN-ACK, data, and a FIN, which is then answered with a FIN
+        fn receive(&mut self, tcp: TCP) {
+            let flags = tcp.header.flags.get();
+            let sequence = tcp.header.sequence.get();
+            self.sent.push(tcp.clone());
+
+            if ! self.answer || flags & TCP_RST == TCP_RST {
+                return;
+            }
+
+            if flags & TCP_SYN == TCP_SYN {
+                if flags & TCP_ACK == TCP_ACK {
+                    self.reply(TCP_ACK, sequence.wrapping_add(1));
+                } else {
+                    self.reply(TCP_SYN | TCP_ACK, sequence.wrapping_add(1));
+                }
+            } else if tcp.data.len() > 0 || flags & TCP_FIN == TCP_FIN {
+                let mut end = sequence.wrapping_add(tcp.data.len() as u32);
+                if flags & TCP_FIN == TCP_FIN {
+                    end = end.wrapping_add(1);
+                }
+                self.reply(TCP_ACK, end);
+                if flags & TCP_FIN == TCP_FIN {
+                    self.reply(TCP_FIN | TCP_ACK, end);
+                    self.sequence = self.sequence.wrapping_add(1);
+                }
+            }
+        }
+
+        /// Get the flags of the segments sent to the peer
+        fn flags(&self) -> Vec<u16> {
+            self.sent.iter().map(|tcp| tcp.header.flags.get() & 0x3F).collect()
+        }
+    }
+
+    /// The link to a `Peer`
+    struct PeerLink {
+        peer: Rc<RefCell<Peer>>,
+    }
+
+    impl Link for PeerLink {
+        fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+            match self.peer.borrow_mut().inbox.pop_front() {
+                Some(bytes) => {
+                    for (b, &byte) in buf.iter_mut().zip(bytes.iter()) {
+                        *b = byte;
+                    }
+                    Some(min(buf.len(), bytes.len()))
+                }
+                None => Some(0),
+            }
+        }
+
+        fn write(&mut self, buf: &[u8]) -> Option<usize> {
+            let tcp = TCP::from_bytes(buf.to_vec()).unwrap();
+            self.peer.borrow_mut().receive(tcp);
+            Some(buf.len())
+        }
+
+        fn dup(&self) -> Option<Box<Link>> {
+            Some(box PeerLink {
+                peer: self.peer.clone(),
+            })
+        }
+
+        fn sync(&mut self) -> bool {
+            true
+        }
+    }
+
+    /// Create a connection to a peer, not yet established, with short timeouts
+    fn connect(peer: &Rc<RefCell<Peer>>, sequence: u32, acknowledge: u32) -> Box<Resource> {
+        let link = box PeerLink {
+            peer: peer.clone(),
+        };
+        let mut resource = Resource::new(link, Rc::new(RefCell::new(Ports::new())), IP_ADDR, 80, 32768, sequence, acknowledge);
+        resource.timeout = Duration::from_millis(10);
+        resource
+    }
+
+    /// Create a connection established with a client handshake
+    fn established(peer: &Rc<RefCell<Peer>>) -> Box<Resource> {
+        let mut resource = connect(peer, 1000, 0);
+        assert!(resource.client_establish(Duration::new(1, 0)));
+        peer.borrow_mut().sent.clear();
+        resource
+    }
+
+    #[test]
+    fn test_mss() {
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_MSS, 4, 0x05, 0xB4]).mss(), Some(1460));
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_NOP, TCP_OPT_NOP, TCP_OPT_MSS, 4, 0x02, 0x18]).mss(), Some(536));
+        assert_eq!(with_options(TCP_SYN, &[3, 3, 7, TCP_OPT_MSS, 4, 0x01, 0x00]).mss(), Some(256));
+        assert_eq!(with_options(TCP_SYN, &[]).mss(), None);
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_END, TCP_OPT_MSS, 4, 0x01, 0x00]).mss(), None);
+
+        // Truncated or malformed options are ignored
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_MSS, 4, 0x05]).mss(), None);
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_MSS]).mss(), None);
+        assert_eq!(with_options(TCP_SYN, &[3, 0, TCP_OPT_MSS, 4, 0x01, 0x00]).mss(), None);
+    }
+
+    #[test]
+    fn test_negotiate_mss() {
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_MSS, 4, 0x02, 0x00]).negotiate_mss(), 512);
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_MSS, 4, 0x23, 0x28]).negotiate_mss(), TCP_MSS);
+        assert_eq!(with_options(TCP_SYN, &[TCP_OPT_MSS, 4, 0x00, 0x00]).negotiate_mss(), TCP_MSS);
+        assert_eq!(with_options(TCP_SYN, &[]).negotiate_mss(), TCP_DEFAULT_MSS);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let bytes = with_options(TCP_SYN, &[TCP_OPT_MSS, 4, 0x05, 0xB4]).to_bytes();
+        let tcp = TCP::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(tcp.options, vec![TCP_OPT_MSS, 4, 0x05, 0xB4]);
+        assert!(tcp.data.is_empty());
+
+        let tcp = TCP::from_bytes(data(0, b"payload").to_bytes()).unwrap();
+        assert!(tcp.options.is_empty());
+        assert_eq!(tcp.data, b"payload".to_vec());
+
+        // Shorter than the fixed header
+        assert!(TCP::from_bytes(bytes[.. mem::size_of::<TCPHeader>() - 1].to_vec()).is_none());
+        // A data offset past the end of the segment
+        assert!(TCP::from_bytes(bytes[.. bytes.len() - 1].to_vec()).is_none());
+    }
+
+    #[test]
+    fn test_from_bytes_bad_offset() {
+        for offset in 0..5 {
+            let mut tcp = data(0, b"payload");
+            tcp.header.flags = n16::new((offset << 12) | TCP_ACK);
+            assert!(TCP::from_bytes(tcp.to_bytes()).is_none());
+        }
+
+        let mut tcp = data(0, b"payload");
+        tcp.header.flags = n16::new((15 << 12) | TCP_ACK);
+        assert!(TCP::from_bytes(tcp.to_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_seq_lt() {
+        assert!(seq_lt(1, 2));
+        assert!(! seq_lt(2, 1));
+        assert!(! seq_lt(5, 5));
+        assert!(seq_le(5, 5));
+
+        // Across the wrap of the sequence space
+        assert!(seq_lt(0xFFFF_FFF0, 0x10));
+        assert!(! seq_lt(0x10, 0xFFFF_FFF0));
+        assert!(seq_lt(0xFFFF_FFFF, 0));
+        assert!(seq_le(0xFFFF_FFFF, 0));
+        assert!(! seq_le(0, 0xFFFF_FFFF));
+
+        // Half of the space ahead is later, the other half earlier
+        assert!(seq_lt(0, 0x7FFF_FFFF));
+        assert!(! seq_lt(0, 0x8000_0001));
+    }
+
+    #[test]
+    fn test_small_reads() {
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+
+        let mut r = resource(5000);
+        r.queue(data(5000, &payload));
+
+        let mut read = Vec::new();
+        let mut buf = [0; 8];
+        while read.len() < payload.len() {
+            let count = r.read(&mut buf).unwrap();
+            assert!(count > 0 && count <= 8);
+            read.extend(buf[.. count].iter().cloned());
+        }
+        assert_eq!(read, payload);
+        assert!(r.received.is_empty());
+        r.reset();
+    }
+
+    #[test]
+    fn test_receive_buffer_window() {
+        let mut r = resource(5000);
+        r.set_receive_buffer(100);
+        assert_eq!(r.window(), 100);
+
+        // Data past the room in the buffer is dropped
+        r.queue(data(5000, &[1; 150]));
+        assert_eq!(r.reassembly.values().fold(0, |sum, data| sum + data.len()), 100);
+
+        let mut buf = [0; 40];
+        assert_eq!(r.read(&mut buf), Some(40));
+        assert_eq!(r.window(), 40);
+        assert_eq!(r.read(&mut buf), Some(40));
+        assert_eq!(r.read(&mut buf), Some(20));
+        assert_eq!(r.window(), 100);
+
+        r.set_receive_buffer(1 << 20);
+        assert_eq!(r.window(), TCP_WINDOW);
+        r.reset();
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut r = resource(5000);
+
+        let mut tcp = data(5000, b"hello");
+        assert!(r.filter(&tcp.to_bytes()).is_some());
+        assert_eq!(r.stats().segments_received, 1);
+
+        tcp.header.checksum.data ^= 1;
+        assert!(r.filter(&tcp.to_bytes()).is_none());
+        assert_eq!(r.stats().bad_checksums, 1);
+
+        assert!(r.filter(&[0; 8]).is_none());
+        assert_eq!(r.stats().malformed, 1);
+
+        let mut other = data(5000, b"hello");
+        other.header.src = n16::new(81);
+        assert!(r.filter(&other.to_bytes()).is_none());
+        assert_eq!(r.stats().strays, 1);
+
+        let mut buf = [0; 16];
+        r.queue(data(5000, b"hello"));
+        assert_eq!(r.read(&mut buf), Some(5));
+        r.queue(data(5005, b" world"));
+        assert_eq!(r.read(&mut buf), Some(6));
+        assert_eq!(r.stats().bytes_in, 11);
+        // The ACK of the two segments
+        assert_eq!(r.stats().segments_sent, 1);
+        assert_eq!(r.stats().segments_received, 1);
+        assert_eq!(r.stats().retransmits, 0);
+        r.reset();
+    }
+
+    #[test]
+    fn test_delayed_ack() {
+        let mut r = resource(5000);
+        r.queue(data(5000, b"hello world"));
+
+        let mut buf = [0; 5];
+        assert_eq!(r.read(&mut buf), Some(5));
+        assert_eq!(r.stats().segments_sent, 0);
+        assert!(r.ack_deadline.is_some());
+
+        // A due ACK is sent by the next read, even if it does not wait for a segment
+        r.ack_deadline = Some(Instant::now());
+        assert_eq!(r.read(&mut buf), Some(5));
+        assert_eq!(r.stats().segments_sent, 1);
+        assert!(r.ack_deadline.is_none());
+        r.reset();
+    }
+
+    #[test]
+    fn test_filter_garbage() {
+        let mut r = resource(5000);
+        let valid = data(5000, b"payload").to_bytes();
+
+        // A simple generator, so that the garbage is the same on every run
+        let mut seed: u32 = 12345;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        };
+
+        for _ in 0..1000 {
+            let len = next() as usize % 64;
+            let bytes: Vec<u8> = (0..len).map(|_| next()).collect();
+            TCP::from_bytes(bytes.clone());
+            r.filter(&bytes);
+
+            // A valid segment with a byte changed, often in the data offset
+            let mut bytes = valid.clone();
+            let i = next() as usize % bytes.len();
+            bytes[i] = next();
+            TCP::from_bytes(bytes.clone());
+            r.filter(&bytes);
+            r.state = TCPState::Established;
+        }
+
+        assert!(r.stats().malformed > 0);
+        assert!(r.stats().bad_checksums > 0);
+        r.reset();
+    }
+
+    #[test]
+    fn test_ports() {
+        let mut ports = Ports::new();
+        ports.acquire(32768, 80);
+        ports.acquire(32768, 80);
+        ports.listen(8080);
+
+        assert!(ports.owns(32768, 80));
+        assert!(! ports.owns(32768, 81));
+        assert!(! ports.owns(8080, 80));
+        assert!(ports.in_use(32768));
+        assert!(ports.in_use(8080));
+        assert!(ports.is_listening(8080));
+
+        ports.release(32768, 80);
+        assert!(ports.owns(32768, 80));
+        ports.release(32768, 80);
+        assert!(! ports.owns(32768, 80));
+        assert!(! ports.in_use(32768));
+    }
+
+    #[test]
+    fn test_reassembly_reverse_order() {
+        let mut r = resource(5000);
+        r.queue(data(5008, b"ijkl"));
+        r.queue(data(5004, b"efgh"));
+        r.queue(data(5000, b"abcd"));
+
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf), Some(12));
+        assert_eq!(&buf[.. 12], b"abcdefghijkl");
+        assert!(r.reassembly.is_empty());
+        r.reset();
+    }
+
+    #[test]
+    fn test_reassembly_overlap() {
+        let mut r = resource(5000);
+        r.queue(data(5004, b"efgh"));
+        r.queue(data(5002, b"cdefghij"));
+        r.queue(data(4998, b"xxabcd"));
+        assert_eq!(r.reassembly.values().fold(0, |sum, data| sum + data.len()), 10);
+
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf), Some(10));
+        assert_eq!(&buf[.. 10], b"abcdefghij");
+        assert!(r.reassembly.is_empty());
+        assert_eq!(r.window(), TCP_WINDOW);
+        r.reset();
+    }
+
+    #[test]
+    fn test_reassembly_window() {
+        let mut r = resource(5000);
+        r.queue(data(5000u32.wrapping_add(TCP_WINDOW as u32), b"late"));
+        assert!(r.reassembly.is_empty());
+
+        r.queue(data(5000u32.wrapping_add(TCP_WINDOW as u32 - 2), b"edge"));
+        assert_eq!(r.reassembly.values().fold(0, |sum, data| sum + data.len()), 2);
+        r.reset();
+    }
+
+    #[test]
+    fn test_handshake() {
+        // As the client: SYN, SYN-ACK, ACK
+        let peer = Peer::new();
+        let mut r = connect(&peer, 1000, 0);
+        assert!(r.state() == TCPState::Closed);
+        assert!(r.client_establish(Duration::new(1, 0)));
+        assert!(r.state() == TCPState::Established);
+        assert_eq!(peer.borrow().flags(), vec![TCP_SYN, TCP_ACK]);
+        assert_eq!(peer.borrow().sent[0].header.sequence.get(), 1000);
+        assert_eq!(peer.borrow().sent[1].header.ack_num.get(), 5001);
+        assert_eq!((r.sequence, r.acknowledge), (1001, 5001));
+
+        // A RST closes the connection
+        let rst = segment(TCP_RST, 5001, 0, b"");
+        peer.borrow_mut().push(&rst);
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf), None);
+        assert!(r.state() == TCPState::Closed);
+
+        // As the server: the SYN was read by the listener, then SYN-ACK, ACK
+        let peer = Peer::new();
+        peer.borrow_mut().sequence = 7001;
+        let syn = segment(TCP_SYN, 7000, 0, b"");
+        let mut r = connect(&peer, 3000, 7000);
+        assert!(r.server_establish(syn));
+        assert!(r.state() == TCPState::Established);
+        assert_eq!(peer.borrow().flags(), vec![TCP_SYN | TCP_ACK]);
+        assert_eq!(peer.borrow().sent[0].header.ack_num.get(), 7001);
+        assert_eq!((r.sequence, r.acknowledge), (3001, 7001));
+        r.reset();
+    }
+
+    #[test]
+    fn test_write_split_by_mss() {
+        let peer = Peer::new();
+        peer.borrow_mut().mss = 1000;
+        let mut r = established(&peer);
+        assert_eq!(r.mss, 1000);
+
+        let payload: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        assert_eq!(r.write(&payload), Some(4096));
+
+        let lens: Vec<usize> = peer.borrow().sent.iter().map(|tcp| tcp.data.len()).collect();
+        assert_eq!(lens, vec![1000, 1000, 1000, 1000, 96]);
+        let sent: Vec<u8> = peer.borrow().sent.iter().flat_map(|tcp| tcp.data.iter().cloned()).collect();
+        assert_eq!(sent, payload);
+        assert_eq!(r.sequence, 1001 + 4096);
+        assert_eq!(r.stats().bytes_out, 4096);
+        r.reset();
+    }
+
+    #[test]
+    fn test_close() {
+        // Closing first: FIN, ACK, then the FIN of the peer and its ACK
+        let peer = Peer::new();
+        let mut r = established(&peer);
+        peer.borrow_mut().answer = false;
+        let ack = segment(TCP_ACK, 5001, 1002, b"");
+        let fin = segment(TCP_FIN | TCP_ACK, 5001, 1002, b"");
+        peer.borrow_mut().push(&ack);
+        peer.borrow_mut().push(&fin);
+        r.close();
+        assert!(r.state() == TCPState::Closed);
+        assert_eq!(peer.borrow().flags(), vec![TCP_FIN | TCP_ACK, TCP_ACK]);
+        assert_eq!(peer.borrow().sent[0].header.sequence.get(), 1001);
+        assert_eq!(peer.borrow().sent[1].header.ack_num.get(), 5002);
+
+        // Closed by the peer: its FIN ends the reads, and ours is acknowledged
+        let peer = Peer::new();
+        let mut r = established(&peer);
+        let fin = segment(TCP_FIN | TCP_ACK, 5001, 1001, b"");
+        peer.borrow_mut().push(&fin);
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf), Some(0));
+        assert!(r.state() == TCPState::CloseWait);
+        assert_eq!(r.acknowledge, 5002);
+
+        peer.borrow_mut().sequence = 5002;
+        r.close();
+        assert!(r.state() == TCPState::Closed);
+        assert_eq!(peer.borrow().flags(), vec![TCP_ACK, TCP_FIN | TCP_ACK]);
+        assert_eq!(r.stats().retransmits, 0);
+    }
+
+    #[test]
+    fn test_allocate_port() {
+        let scheme = Scheme::new();
+        let mut ports = Vec::new();
+        for _ in 0..1000 {
+            let port = scheme.allocate_port().unwrap();
+            assert!(port >= 32768);
+            assert!(! ports.contains(&port));
+            scheme.ports.borrow_mut().acquire(port, 80);
+            ports.push(port);
+        }
+
+        for &port in ports.iter() {
+            scheme.ports.borrow_mut().release(port, 80);
+        }
+        assert!(! ports.iter().any(|&port| scheme.ports.borrow().in_use(port)));
+    }
+
+    #[test]
+    fn test_tiny_peer_window() {
+        let peer = Peer::new();
+        peer.borrow_mut().window = 100;
+        let mut r = established(&peer);
+        assert_eq!(r.peer_window, 100);
+
+        assert_eq!(r.write(&[7; 250]), Some(250));
+        let lens: Vec<usize> = peer.borrow().sent.iter().map(|tcp| tcp.data.len()).collect();
+        assert_eq!(lens, vec![100, 100, 50]);
+        r.reset();
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut r = resource(5000);
+        let (addr, port) = r.peer_addr();
+        assert!(addr.equals(IP_ADDR));
+        assert_eq!(port, 80);
+        assert_eq!(r.local_port(), 32768);
+        assert_eq!(r.path(), Some(format!("tcp://{}:80/32768", IP_ADDR.to_string())));
+
+        // A duplicate is a connection between the same ports
+        let mut dup = r.dup().unwrap();
+        assert!(dup.peer_addr().0.equals(IP_ADDR));
+        assert_eq!(dup.peer_addr().1, 80);
+        assert_eq!(dup.local_port(), 32768);
+        dup.reset();
+        r.reset();
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        // The SYN is sent again until the connect timeout passes
+        let peer = Peer::new();
+        peer.borrow_mut().answer = false;
+        let mut r = connect(&peer, 1000, 0);
+        let start = Instant::now();
+        assert!(! r.client_establish(Duration::from_millis(100)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(r.state() == TCPState::Closed);
+
+        let syns = peer.borrow().flags();
+        assert!(syns.len() >= 2);
+        assert!(syns.iter().all(|&flags| flags == TCP_SYN));
+        assert_eq!(r.stats().retransmits, syns.len() - 1);
+
+        // Or until the retries run out, if that comes first
+        let peer = Peer::new();
+        peer.borrow_mut().answer = false;
+        let mut r = connect(&peer, 1000, 0);
+        r.retries = 2;
+        assert!(! r.client_establish(Duration::from_secs(30)));
+        assert_eq!(peer.borrow().flags(), vec![TCP_SYN, TCP_SYN, TCP_SYN]);
+    }
+
+    #[test]
+    fn test_sequence_wrap() {
+        let peer = Peer::new();
+        peer.borrow_mut().sequence = 0xFFFF_FFFE;
+        let mut r = connect(&peer, 0xFFFF_FFF0, 0);
+        assert!(r.client_establish(Duration::new(1, 0)));
+        assert_eq!((r.sequence, r.acknowledge), (0xFFFF_FFF1, 0xFFFF_FFFF));
+        peer.borrow_mut().sent.clear();
+
+        // Sent data is acknowledged past the wrap
+        assert_eq!(r.write(&[7; 100]), Some(100));
+        assert_eq!(r.sequence, 0x55);
+        assert_eq!(r.stats().retransmits, 0);
+
+        // Received data is delivered and acknowledged past the wrap
+        peer.borrow_mut().sent.clear();
+        peer.borrow_mut().push(&segment(TCP_ACK, 0xFFFF_FFFF, 0x55, b"abcd"));
+        peer.borrow_mut().push(&segment(TCP_ACK, 3, 0x55, b"efgh"));
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf), Some(4));
+        assert_eq!(&buf[.. 4], b"abcd");
+        assert_eq!(r.read(&mut buf), Some(4));
+        assert_eq!(&buf[.. 4], b"efgh");
+        assert_eq!(r.acknowledge, 7);
+        assert_eq!(peer.borrow().flags(), vec![TCP_ACK]);
+        assert_eq!(peer.borrow().sent[0].header.ack_num.get(), 7);
+        r.reset();
+    }
+
+    #[test]
+    fn test_data_without_psh() {
+        let peer = Peer::new();
+        let mut r = established(&peer);
+        peer.borrow_mut().push(&segment(TCP_ACK, 5001, 1001, b"hello"));
+        peer.borrow_mut().push(&segment(TCP_ACK, 5006, 1001, b" world"));
+
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf), Some(5));
+        assert_eq!(&buf[.. 5], b"hello");
+        assert_eq!(r.read(&mut buf), Some(6));
+        assert_eq!(&buf[.. 6], b" world");
+
+        // Both segments are acknowledged together
+        assert_eq!(peer.borrow().flags(), vec![TCP_ACK]);
+        assert_eq!(peer.borrow().sent[0].header.ack_num.get(), 5012);
+        r.reset();
+    }
+
+    #[test]
+    fn test_nonblocking_read() {
+        let peer = Peer::new();
+        let mut r = established(&peer);
+        r.set_nonblocking(true);
+        assert!(r.nonblocking());
+
+        // Nothing has arrived, so the read returns at once
+        let mut buf = [0; 16];
+        let start = Instant::now();
+        assert_eq!(r.read(&mut buf), Some(0));
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert!(r.state() == TCPState::Established);
+
+        peer.borrow_mut().push(&data(5001, b"hello"));
+        assert_eq!(r.read(&mut buf), Some(5));
+        assert_eq!(&buf[.. 5], b"hello");
+        assert_eq!(r.read(&mut buf), Some(0));
+        r.reset();
+    }
+
+    #[test]
+    fn test_unrelated_traffic() {
+        let peer = Peer::new();
+        let mut r = established(&peer);
+
+        // Segments for a connection from another port of the peer, then one for us
+        let count = TCP_STRAY_LIMIT * 3;
+        for _ in 0..count {
+            let mut stray = data(5001, b"other");
+            stray.header.src = n16::new(81);
+            seal(&mut stray);
+            peer.borrow_mut().push(&stray);
+        }
+        peer.borrow_mut().push(&data(5001, b"hello"));
+
+        let mut buf = [0; 16];
+        let start = Instant::now();
+        assert_eq!(r.read(&mut buf), Some(5));
+        assert_eq!(&buf[.. 5], b"hello");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(r.stats().strays, count);
+
+        // There is no connection from that port, so each was answered with a RST
+        assert_eq!(peer.borrow().flags().iter().filter(|&&flags| flags & TCP_RST == TCP_RST).count(), count);
+        r.reset();
     }
 }
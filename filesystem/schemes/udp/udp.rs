@@ -50,6 +50,24 @@ impl ToBytes for UDP {
     }
 }
 
+impl UDP {
+    pub unsafe fn sum(&self, src_addr: &IPv4Addr, dst_addr: &IPv4Addr) -> usize {
+        let proto = n16::new(0x11);
+        let datagram_len = n16::new((mem::size_of::<UDPHeader>() + self.data.len()) as u16);
+        Checksum::sum((src_addr as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
+        Checksum::sum((dst_addr as *const IPv4Addr) as usize, mem::size_of::<IPv4Addr>()) +
+        Checksum::sum((&proto as *const n16) as usize, mem::size_of::<n16>()) +
+        Checksum::sum((&datagram_len as *const n16) as usize, mem::size_of::<n16>()) +
+        Checksum::sum((&self.header as *const UDPHeader) as usize, mem::size_of::<UDPHeader>()) +
+        Checksum::sum(self.data.as_ptr() as usize, self.data.len())
+    }
+
+    /// Check the checksum of a received datagram. A zero checksum means none was computed.
+    pub fn valid(&self, src_addr: &IPv4Addr, dst_addr: &IPv4Addr) -> bool {
+        self.header.checksum.data == 0 || unsafe { Checksum::compile(self.sum(src_addr, dst_addr)) } == 0
+    }
+}
+
 /// UDP resource
 pub struct Resource {
     ip: File,
@@ -82,10 +100,11 @@ impl Resource {
             let mut bytes: Vec<u8> = Vec::new();
             mem::swap(&mut self.data, &mut bytes);
 
-            //TODO: Allow splitting
-            let i = 0;
+            // Datagrams are not split, what does not fit in the buffer is dropped
+            let mut i = 0;
             while i < buf.len() && i < bytes.len() {
-                 buf[i] = bytes[i];
+                buf[i] = bytes[i];
+                i += 1;
             }
             return Some(i);
         }
@@ -95,12 +114,11 @@ impl Resource {
             match self.ip.read_to_end(&mut bytes) {
                 Some(_) => {
                     if let Some(datagram) = UDP::from_bytes(bytes) {
-                        if datagram.header.dst.get() == self.host_port &&
-                           datagram.header.src.get() == self.peer_port {
-                            //TODO: Allow splitting
-                            let i = 0;
+                        if self.accepts(&datagram) {
+                            let mut i = 0;
                             while i < buf.len() && i < datagram.data.len() {
                                 buf[i] = datagram.data[i];
+                                i += 1;
                             }
                             return Some(i);
                         }
@@ -113,7 +131,15 @@ impl Resource {
         None
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
+    /// Check if a received datagram is from the peer to our port, with a valid checksum
+    fn accepts(&self, datagram: &UDP) -> bool {
+        datagram.header.dst.get() == self.host_port &&
+        datagram.header.src.get() == self.peer_port &&
+        datagram.valid(&self.peer_addr, &IP_ADDR)
+    }
+
+    /// Create a datagram to the peer
+    fn datagram(&self, buf: &[u8]) -> UDP {
         let udp_data = Vec::from(buf);
 
         let mut udp = UDP {
@@ -127,22 +153,15 @@ impl Resource {
         };
 
         unsafe {
-            let proto = n16::new(0x11);
-            let datagram_len = n16::new((mem::size_of::<UDPHeader>() + udp.data.len()) as u16);
-            udp.header.checksum.data =
-                Checksum::compile(Checksum::sum((&IP_ADDR as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&self.peer_addr as *const IPv4Addr) as usize,
-                                                mem::size_of::<IPv4Addr>()) +
-                                  Checksum::sum((&proto as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&datagram_len as *const n16) as usize,
-                                                mem::size_of::<n16>()) +
-                                  Checksum::sum((&udp.header as *const UDPHeader) as usize,
-                                                mem::size_of::<UDPHeader>()) +
-                                  Checksum::sum(udp.data.as_ptr() as usize, udp.data.len()));
+            udp.header.checksum.data = Checksum::compile(udp.sum(&IP_ADDR, &self.peer_addr));
         }
 
+        udp
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        let udp = self.datagram(buf);
+
         match self.ip.write(&udp.to_bytes()) {
             Some(_) => return Some(buf.len()),
             None => return None,
@@ -171,20 +190,25 @@ impl Scheme {
 
         //Check host and port vs path
         if url.path().len() > 0 {
-            let host_port = url.port().to_num();
+            let host_port = url.path().to_num();
             if host_port > 0 && host_port < 65536 {
-                if let Some(mut ip) = File::open("ip:///11") {
+                // Wait for a datagram to the port, dropping others and those with a bad checksum
+                while let Some(mut ip) = File::open("ip:///11") {
                     let mut bytes: Vec<u8> = Vec::new();
-                    if ip.read_to_end(&mut bytes).is_some() {
-                        if let Some(datagram) = UDP::from_bytes(bytes) {
-                            if datagram.header.dst.get() as usize == host_port {
-                                if let Some(path) = ip.path() {
-                                    let url = URL::from_string(&path);
+                    if ip.read_to_end(&mut bytes).is_none() {
+                        break;
+                    }
 
+                    if let Some(datagram) = UDP::from_bytes(bytes) {
+                        if datagram.header.dst.get() as usize == host_port {
+                            if let Some(path) = ip.path() {
+                                let url = URL::from_string(&path);
+                                let peer_addr = IPv4Addr::from_string(&url.host());
+                                if datagram.valid(&peer_addr, &IP_ADDR) {
                                     return Some(box Resource {
                                         ip: ip,
                                         data: datagram.data,
-                                        peer_addr: IPv4Addr::from_string(&url.host()),
+                                        peer_addr: peer_addr,
                                         peer_port: datagram.header.src.get(),
                                         host_port: host_port as u16,
                                     });
@@ -198,12 +222,16 @@ impl Scheme {
             let peer_port = url.port().to_num();
             if peer_port > 0 && peer_port < 65536 {
                 let host_port = (rand() % 32768 + 32768) as u16;
+                let peer_addr = match IPv4Addr::parse(&url.host()) {
+                    Some(addr) => addr,
+                    None => return None,
+                };
 
-                if let Some(ip) = File::open(&format!("ip://{}/11", url.host())) {
+                if let Some(ip) = File::open(&format!("ip://{}/11", peer_addr.to_string())) {
                     return Some(box Resource {
                         ip: ip,
                         data: Vec::new(),
-                        peer_addr: IPv4Addr::from_string(&url.host()),
+                        peer_addr: peer_addr,
                         peer_port: peer_port as u16,
                         host_port: host_port,
                     });
@@ -214,3 +242,51 @@ impl Scheme {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use redox::fs::File;
+    use redox::net::*;
+    use redox::Vec;
+    use super::*;
+
+    /// Create a resource for a pair of ports on this host, writing to `null://`
+    fn resource(host_port: u16, peer_port: u16) -> Resource {
+        Resource {
+            ip: File::open("null://").unwrap(),
+            data: Vec::new(),
+            peer_addr: IP_ADDR,
+            peer_port: peer_port,
+            host_port: host_port,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sender = resource(40000, 53);
+        let receiver = resource(53, 40000);
+
+        let bytes = sender.datagram(b"hello").to_bytes();
+        assert_eq!(bytes.len(), 8 + 5);
+        let datagram = UDP::from_bytes(bytes.clone()).unwrap();
+        assert!(datagram.valid(&IP_ADDR, &IP_ADDR));
+        assert!(receiver.accepts(&datagram));
+        assert_eq!(datagram.data, b"hello".to_vec());
+
+        // Corrupted data fails the checksum
+        let mut corrupted = bytes.clone();
+        corrupted[9] ^= 1;
+        let datagram = UDP::from_bytes(corrupted).unwrap();
+        assert!(! datagram.valid(&IP_ADDR, &IP_ADDR));
+        assert!(! receiver.accepts(&datagram));
+
+        // A zero checksum was not computed, so it is not checked
+        let mut unchecked = UDP::from_bytes(bytes.clone()).unwrap();
+        unchecked.header.checksum.data = 0;
+        unchecked.data[0] = b'j';
+        assert!(unchecked.valid(&IP_ADDR, &IP_ADDR));
+
+        // Datagrams to other ports are not ours
+        assert!(! sender.accepts(&UDP::from_bytes(bytes).unwrap()));
+    }
+}
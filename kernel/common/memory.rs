@@ -374,3 +374,22 @@ pub fn memory_free() -> usize {
     }
     ret
 }
+
+/// Count the live allocations, each of which starts at the cluster it is owned by
+pub fn memory_allocations() -> usize {
+    let mut ret = 0;
+    unsafe {
+        //Memory allocation must be atomic
+        let reenable = scheduler::start_no_ints();
+
+        for i in 0..CLUSTER_COUNT {
+            if cluster(i) == cluster_to_address(i) {
+                ret += 1;
+            }
+        }
+
+        //Memory allocation must be atomic
+        scheduler::end_no_ints(reenable);
+    }
+    ret
+}
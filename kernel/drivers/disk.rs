@@ -1,6 +1,8 @@
 use alloc::arc::Arc;
 
-use core::ptr;
+use collections::vec::Vec;
+
+use core::{cmp, ptr};
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use common::debug;
@@ -11,6 +13,7 @@ use common::scheduler;
 use drivers::pio::*;
 
 /// An disk extent
+/// In a file node, an extent with a length but no block is a hole, read as zeros without sectors on the disk
 #[derive(Copy, Clone)]
 #[repr(packed)]
 pub struct Extent {
@@ -173,6 +176,10 @@ pub struct Disk {
     sts: PIO8,
     prdt: Option<PRDT>,
     pub irq: u8,
+    /// The number of sectors, found by identify
+    pub sectors: u64,
+    /// The sectors of a disk held in memory, used instead of the controller
+    memory: Option<Vec<u8>>,
 }
 
 impl Disk {
@@ -188,6 +195,8 @@ impl Disk {
             sts: PIO8::new(base + 2),
             prdt: PRDT::new(base + 4),
             irq: 0xE,
+            sectors: 0,
+            memory: None,
         }
     }
 
@@ -203,6 +212,8 @@ impl Disk {
             sts: PIO8::new(base + 2),
             prdt: PRDT::new(base + 4),
             irq: 0xE,
+            sectors: 0,
+            memory: None,
         }
     }
 
@@ -218,6 +229,8 @@ impl Disk {
             sts: PIO8::new(base + 0xA),
             prdt: PRDT::new(base + 0xC),
             irq: 0xF,
+            sectors: 0,
+            memory: None,
         }
     }
 
@@ -233,6 +246,25 @@ impl Disk {
             sts: PIO8::new(base + 0xA),
             prdt: PRDT::new(base + 0xC),
             irq: 0xF,
+            sectors: 0,
+            memory: None,
+        }
+    }
+
+    /// Get a disk held in memory, with a number of zeroed sectors
+    pub fn memory(sectors: u64) -> Self {
+        Disk {
+            base: 0,
+            ctrl: 0,
+            master: true,
+            request: None,
+            requests: Queue::new(),
+            cmd: PIO8::new(0),
+            sts: PIO8::new(0),
+            prdt: None,
+            irq: 0,
+            sectors: sectors,
+            memory: Some(vec![0; sectors as usize * 512]),
         }
     }
 
@@ -287,7 +319,12 @@ impl Disk {
     }
 
     /// Identify
-    pub unsafe fn identify(&self) -> bool {
+    pub unsafe fn identify(&mut self) -> bool {
+        // A disk in memory without sectors is taken as no disk
+        if self.memory.is_some() {
+            return self.sectors > 0;
+        }
+
         if self.ide_read(ATA_REG_STATUS) == 0xFF {
             debug::d(" Floating Bus");
 
@@ -340,6 +377,8 @@ impl Disk {
         debug::dd((sectors / 2048) as usize);
         debug::d(" MB");
 
+        self.sectors = sectors;
+
         true
     }
 
@@ -431,6 +470,21 @@ impl Disk {
 
     /// Send request
     pub fn request(&mut self, request: Request) {
+        // A disk in memory completes the request at once
+        if let Some(ref mut memory) = self.memory {
+            let start = cmp::min(request.extent.block as usize * 512, memory.len());
+            let end = cmp::min(start + (request.extent.length as usize + 511) / 512 * 512, memory.len());
+            unsafe {
+                if request.read {
+                    ptr::copy(memory.as_ptr().offset(start as isize), request.mem as *mut u8, end - start);
+                } else {
+                    ptr::copy(request.mem as *const u8, memory.as_mut_ptr().offset(start as isize), end - start);
+                }
+            }
+            request.complete.store(true, Ordering::SeqCst);
+            return;
+        }
+
         unsafe {
             let reenable = scheduler::start_no_ints();
 
@@ -445,6 +499,10 @@ impl Disk {
     }
 
     pub unsafe fn on_poll(&mut self) {
+        if self.memory.is_some() {
+            return;
+        }
+
         let sts = self.sts.read();
         if sts & STS_INT == STS_INT {
             self.sts.write(sts);
@@ -5,6 +5,8 @@ use core::simd::*;
 
 use common::{memory, scheduler};
 
+use drivers::pio::{inw, outw};
+
 use super::color::Color;
 use super::point::Point;
 use super::size::Size;
@@ -21,13 +23,13 @@ pub struct VBEModeInfo {
     segment_a: u16,
     segment_b: u16,
     winfuncptr: u32,
-    bytesperscanline: u16,
-    xresolution: u16,
-    yresolution: u16,
+    pub bytesperscanline: u16,
+    pub xresolution: u16,
+    pub yresolution: u16,
     xcharsize: u8,
     ycharsize: u8,
     numberofplanes: u8,
-    bitsperpixel: u8,
+    pub bitsperpixel: u8,
     numberofbanks: u8,
     memorymodel: u8,
     banksize: u8,
@@ -47,10 +49,105 @@ pub struct VBEModeInfo {
     offscreenmemsize: u16,
 }
 
-const VBEMODEINFO: *const VBEModeInfo = 0x5200 as *const VBEModeInfo;
+impl VBEModeInfo {
+    /// Record the resolution and depth of a mode that was set
+    pub fn set_resolution(&mut self, width: usize, height: usize, depth: usize) {
+        self.xresolution = width as u16;
+        self.yresolution = height as u16;
+        self.bytesperscanline = (width * depth / 8) as u16;
+        self.bitsperpixel = depth as u8;
+    }
+}
+
+/// The mode info left by the loader, which tests point at a mode in memory
+pub static mut VBEMODEINFO: *const VBEModeInfo = 0x5200 as *const VBEModeInfo;
 
 pub const FONTS: *mut usize = 0x200008 as *mut usize;
 
+/// The index and data ports of the Bochs VBE extensions, on the Bochs and QEMU display adapters
+const BGA_INDEX: u16 = 0x1CE;
+const BGA_DATA: u16 = 0x1CF;
+
+/// The registers of the Bochs VBE extensions
+const BGA_ID: u16 = 0;
+const BGA_XRES: u16 = 1;
+const BGA_YRES: u16 = 2;
+const BGA_BPP: u16 = 3;
+const BGA_ENABLE: u16 = 4;
+
+/// The flags of the enable register
+const BGA_ENABLED: u16 = 0x01;
+const BGA_GETCAPS: u16 = 0x02;
+const BGA_LFB_ENABLED: u16 = 0x40;
+
+/// Read a register of the Bochs VBE extensions
+unsafe fn bga_read(index: u16) -> u16 {
+    outw(BGA_INDEX, index);
+    inw(BGA_DATA)
+}
+
+/// Write a register of the Bochs VBE extensions
+unsafe fn bga_write(index: u16, value: u16) {
+    outw(BGA_INDEX, index);
+    outw(BGA_DATA, value);
+}
+
+/// Program a mode, with the linear framebuffer enabled
+unsafe fn bga_program(width: u16, height: u16, depth: u16) {
+    bga_write(BGA_ENABLE, 0);
+    bga_write(BGA_XRES, width);
+    bga_write(BGA_YRES, height);
+    bga_write(BGA_BPP, depth);
+    bga_write(BGA_ENABLE, BGA_ENABLED | BGA_LFB_ENABLED);
+}
+
+/// Check if a mode could be set: only 32 bit modes are supported, and the width must be a multiple of 8
+pub fn mode_supported(width: usize, height: usize, depth: usize) -> bool {
+    depth == 32 && width > 0 && height > 0 && width % 8 == 0 && width <= 0xFFFF && height <= 0xFFFF
+}
+
+/// Change the video mode of the screen, using the Bochs VBE extensions
+/// Returns false if the mode is not supported or could not be set, leaving the current mode.
+/// Root displays follow the new mode with `update_mode`
+pub unsafe fn set_mode(width: usize, height: usize, depth: usize) -> bool {
+    if ! mode_supported(width, height, depth) {
+        return false;
+    }
+
+    let id = bga_read(BGA_ID);
+    if id < 0xB0C2 || id > 0xB0CF {
+        return false;
+    }
+
+    let reenable = scheduler::start_no_ints();
+
+    // Ask for the largest mode
+    let enable = bga_read(BGA_ENABLE);
+    bga_write(BGA_ENABLE, BGA_GETCAPS);
+    let max_width = bga_read(BGA_XRES) as usize;
+    let max_height = bga_read(BGA_YRES) as usize;
+    bga_write(BGA_ENABLE, enable);
+
+    let mut ok = false;
+    if width <= max_width && height <= max_height {
+        let old = (bga_read(BGA_XRES), bga_read(BGA_YRES), bga_read(BGA_BPP));
+
+        bga_program(width as u16, height as u16, depth as u16);
+        ok = bga_read(BGA_XRES) as usize == width && bga_read(BGA_YRES) as usize == height &&
+             bga_read(BGA_BPP) as usize == depth;
+
+        if ok {
+            (*(VBEMODEINFO as *mut VBEModeInfo)).set_resolution(width, height, depth);
+        } else {
+            bga_program(old.0, old.1, old.2);
+        }
+    }
+
+    scheduler::end_no_ints(reenable);
+
+    ok
+}
+
 /// A display
 pub struct Display {
     pub offscreen: usize,
@@ -83,6 +180,36 @@ impl Display {
         ret
     }
 
+    /// Follow a change of the video mode, reallocating the offscreen buffer of a root display
+    /// The display is cleared. Returns true if the mode changed
+    pub unsafe fn update_mode(&mut self) -> bool {
+        if ! self.root {
+            return false;
+        }
+
+        let mode_info = &*VBEMODEINFO;
+        let bytesperrow = mode_info.bytesperscanline as usize;
+        let width = mode_info.xresolution as usize;
+        let height = mode_info.yresolution as usize;
+        if bytesperrow == self.bytesperrow && width == self.width && height == self.height {
+            return false;
+        }
+
+        if self.offscreen > 0 {
+            memory::unalloc(self.offscreen);
+        }
+        self.offscreen = memory::alloc(bytesperrow * height);
+        self.size = bytesperrow * height;
+        self.bytesperrow = bytesperrow;
+        self.width = width;
+        self.height = height;
+
+        self.set(Color::new(0, 0, 0));
+        self.flip();
+
+        true
+    }
+
     /// Create a new display
     pub fn new(width: usize, height: usize) -> Box<Self> {
         unsafe {
@@ -187,6 +314,75 @@ impl Display {
         }
     }
 
+    /// Flip a rectangle of the display
+    /// Displays that swap buffers are flipped completely
+    pub fn flip_rect(&self, point: Point, size: Size) {
+        if self.root {
+            let start_x = cmp::max(0, cmp::min(self.width as isize, point.x)) as usize;
+            let end_x = cmp::max(0, cmp::min(self.width as isize, point.x + size.width as isize)) as usize;
+            let start_y = cmp::max(0, cmp::min(self.height as isize, point.y)) as usize;
+            let end_y = cmp::max(0, cmp::min(self.height as isize, point.y + size.height as isize)) as usize;
+
+            if start_x < end_x {
+                unsafe {
+                    let reenable = scheduler::start_no_ints();
+                    for y in start_y..end_y {
+                        let offset = y * self.bytesperrow + start_x * 4;
+                        Display::copy_run(self.offscreen + offset,
+                                          self.onscreen + offset,
+                                          (end_x - start_x) * 4);
+                    }
+                    scheduler::end_no_ints(reenable);
+                }
+            }
+        } else {
+            self.flip();
+        }
+    }
+
+    /// Draw a mask straight to the screen, inverting the offscreen pixels under its set bits
+    /// Each byte of the mask is a row of 8 pixels, with the leftmost pixel in the high bit.
+    /// The offscreen pixels are left untouched, so the next flip of the area erases the mask.
+    /// Displays that swap buffers have no screen to draw on, and are left alone
+    pub fn overlay(&self, point: Point, mask: &[u8]) {
+        if self.root {
+            unsafe {
+                let reenable = scheduler::start_no_ints();
+                for row in 0..mask.len() {
+                    let y = point.y + row as isize;
+                    for col in 0..8 {
+                        let x = point.x + col;
+                        if mask[row] & (0x80 >> col) != 0 && x >= 0 && x < self.width as isize &&
+                           y >= 0 && y < self.height as isize {
+                            let offset = y as usize * self.bytesperrow + x as usize * 4;
+                            let pixel = *((self.offscreen + offset) as *const u32);
+                            *((self.onscreen + offset) as *mut u32) = pixel ^ 0xFFFFFF;
+                        }
+                    }
+                }
+                scheduler::end_no_ints(reenable);
+            }
+        }
+    }
+
+    /// Fill a rectangle with an opaque color, ignoring alpha
+    pub fn fill_rect(&self, point: Point, size: Size, color: Color) {
+        let start_x = cmp::max(0, cmp::min(self.width as isize, point.x)) as usize;
+        let end_x = cmp::max(0, cmp::min(self.width as isize, point.x + size.width as isize)) as usize;
+        let start_y = cmp::max(0, cmp::min(self.height as isize, point.y)) as usize;
+        let end_y = cmp::max(0, cmp::min(self.height as isize, point.y + size.height as isize)) as usize;
+
+        if start_x < end_x {
+            for y in start_y..end_y {
+                unsafe {
+                    Display::set_run(color.data,
+                                     self.offscreen + y * self.bytesperrow + start_x * 4,
+                                     (end_x - start_x) * 4);
+                }
+            }
+        }
+    }
+
     /// Draw a rectangle
     pub fn rect(&self, point: Point, size: Size, color: Color) {
         let data = color.data;
@@ -62,6 +62,8 @@ use schemes::debug::*;
 use schemes::ethernet::*;
 use schemes::ip::*;
 use schemes::memory::*;
+use schemes::null::*;
+use schemes::pipe::*;
 use schemes::random::*;
 use schemes::time::*;
 use schemes::window::*;
@@ -318,7 +320,13 @@ unsafe fn init(font_data: usize) {
     session.items.push(box ContextScheme);
     session.items.push(box DebugScheme);
     session.items.push(box MemoryScheme);
+    session.items.push(box NullScheme);
+    session.items.push(box ZeroScheme);
+    session.items.push(box PipeScheme {
+        pipes: Vec::new(),
+    });
     session.items.push(box RandomScheme);
+    session.items.push(box UrandomScheme);
     session.items.push(box TimeScheme);
 
     session.items.push(box EthernetScheme);
@@ -326,7 +334,10 @@ unsafe fn init(font_data: usize) {
     session.items.push(box IPScheme {
         arp: Vec::new()
     });
-    session.items.push(box DisplayScheme);
+    session.items.push(box DisplayScheme {
+        cursor: None,
+        fills: Vec::new(),
+    });
     session.items.push(box WindowScheme);
 
     Context::spawn(box move || {
@@ -165,10 +165,15 @@ fs_header:
 .signature:
     db "REDOXFS",0
 .version:
-    dd 0xFFFFFFFF
+    ; Nodes have flags and times after 14 extents
+    dd 0xFFFFFFFE
 .name:
     db "Root Filesystem",0
-align 256, db 0
+    times 240-($-fs_header) db 0
+.free:
+    ; The free block bitmap is created by the kernel on first use
+    dq 0
+    dq 0
 .extents:
     dq (fs_root_node_list - boot)/512
     dq (fs_root_node_list.end - fs_root_node_list)
@@ -284,6 +289,9 @@ fs_root_node_list:
 %include "filesystem.gen"
 
 %unmacro file 2+
+
+; Unused nodes, used when creating files
+    times 64 * 512 db 0
 fs_root_node_list.end:
 
 %macro file 2+
@@ -296,3 +304,7 @@ fs_data.%1:
 %include "filesystem.gen"
 
 %unmacro file 2+
+
+; Unused space, used when files grow
+fs_free:
+    times 1024 * 1024 db 0
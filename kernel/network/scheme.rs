@@ -5,7 +5,6 @@ use collections::vec::Vec;
 use core::ops::DerefMut;
 
 use common::context::context_switch;
-use common::debug;
 use common::queue::Queue;
 use common::scheduler;
 
@@ -65,9 +64,26 @@ impl Resource for NetworkResource {
         URL::from_str("network://")
     }
 
+    /// Read a single packet if one is queued, without blocking
     fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
-        debug::d("TODO: Implement read for RTL8139\n");
-        None
+        unsafe {
+            (*self.nic).sync();
+
+            let reenable = scheduler::start_no_ints();
+            let option = (*self.ptr).inbound.pop();
+            scheduler::end_no_ints(reenable);
+
+            if let Some(bytes) = option {
+                let mut i = 0;
+                while i < buf.len() && i < bytes.len() {
+                    buf[i] = bytes[i];
+                    i += 1;
+                }
+                return Some(i);
+            }
+        }
+
+        Some(0)
     }
 
     fn read_to_end(&mut self, vec: &mut Vec<u8>) -> Option<usize> {
@@ -144,6 +144,24 @@ impl Session {
         }
     }
 
+    pub fn create(&mut self, url: &URL) -> Option<Box<Resource>> {
+        for mut item in self.items.iter_mut() {
+            if item.scheme() == url.scheme() {
+                return item.create(url);
+            }
+        }
+        None
+    }
+
+    pub fn unlink(&mut self, url: &URL) -> bool {
+        for mut item in self.items.iter_mut() {
+            if item.scheme() == url.scheme() {
+                return item.unlink(url);
+            }
+        }
+        false
+    }
+
     fn on_key(&mut self, key_event: KeyEvent) {
         if self.windows.len() > 0 {
             match self.windows.get(self.windows.len() - 1) {
@@ -233,6 +251,11 @@ impl Session {
     }
 
     pub unsafe fn redraw(&mut self) {
+        // The video mode was changed through display://mode
+        if self.display.update_mode() {
+            self.redraw = true;
+        }
+
         if self.redraw {
             self.display.set(Color::new(75, 163, 253));
             if self.background.data.len() > 0 {
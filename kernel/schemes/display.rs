@@ -1,19 +1,81 @@
 use alloc::boxed::Box;
 
-use collections::string::ToString;
+use collections::string::{String, ToString};
+use collections::vec::Vec;
 
-use core::cmp;
+use core::{cmp, mem};
 
-use graphics::display::Display;
+use common::to_num::ToNum;
 
-use schemes::{KScheme, Resource, ResourceSeek, URL};
+use graphics::color::Color;
+use graphics::display::{self, Display, VBEModeInfo, VBEMODEINFO};
+use graphics::point::Point;
+use graphics::size::Size;
 
-pub struct DisplayScheme;
+use schemes::{KScheme, Resource, ResourceSeek, URL, VecResource};
+
+/// The size of the cursor sprite
+pub const CURSOR_WIDTH: usize = 8;
+pub const CURSOR_HEIGHT: usize = 16;
+
+/// The cursor sprite, a bar as tall as a character, one byte per row
+pub const CURSOR_SPRITE: [u8; CURSOR_HEIGHT] = [0xC0; CURSOR_HEIGHT];
+
+/// The display scheme
+/// `display://cursor?x,y` moves the text cursor to a pixel, and `display://cursor` hides it
+/// `display://fill?x,y,w,h,RRGGBB` fills a rectangle of the display with a color on its next sync
+pub struct DisplayScheme {
+    /// The position of the text cursor, drawn over the display on every sync
+    pub cursor: Option<Point>,
+    /// The rectangles to fill on the next sync, as x, y, width, height, and color
+    pub fills: Vec<(usize, usize, usize, usize, Color)>,
+}
 
 // Should there only be one display per session?
 pub struct DisplayResource {
+    pub scheme: *mut DisplayScheme,
     pub display: Box<Display>,
     pub seek: usize,
+    /// The left, top, right, and bottom pixels written since the last sync
+    pub damage: Option<(usize, usize, usize, usize)>,
+    /// Blend written pixels over the display by their alpha, instead of copying them
+    pub blend: bool,
+    /// Where the cursor was last drawn on the screen
+    pub cursor: Option<Point>,
+}
+
+impl DisplayResource {
+    /// Follow a change of the video mode, which clears the display
+    fn follow_mode(&mut self) {
+        if unsafe { self.display.update_mode() } {
+            self.seek = cmp::min(self.seek, self.display.size);
+            self.damage = None;
+            self.cursor = None;
+        }
+    }
+
+    /// Add a rectangle to the pixels to flip on the next sync
+    fn add_damage(&mut self, start_x: usize, start_y: usize, end_x: usize, end_y: usize) {
+        self.damage = match self.damage {
+            Some((left, top, right, bottom)) => Some((cmp::min(left, start_x),
+                                                      cmp::min(top, start_y),
+                                                      cmp::max(right, end_x),
+                                                      cmp::max(bottom, end_y))),
+            None => Some((start_x, start_y, end_x, end_y)),
+        };
+    }
+
+    /// Fill a rectangle with a color, without going through a buffer of pixels
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: Color) {
+        let end_x = cmp::min(self.display.width, x + w);
+        let end_y = cmp::min(self.display.height, y + h);
+        if x < end_x && y < end_y {
+            self.display.fill_rect(Point::new(x as isize, y as isize),
+                                   Size::new(end_x - x, end_y - y),
+                                   color);
+            self.add_damage(x, y, end_x, end_y);
+        }
+    }
 }
 
 impl Resource for DisplayResource {
@@ -27,26 +89,59 @@ impl Resource for DisplayResource {
         return URL::from_string(&("display://".to_string()));
     }
 
-    // not sure what to return here
+    /// Read pixels back from the framebuffer, for example to take a screenshot
     fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
-        None
+        self.follow_mode();
+        let display = &mut self.display;
+
+        let size = cmp::min(display.size - self.seek, buf.len());
+        unsafe {
+            Display::copy_run(display.offscreen + self.seek,
+                              buf.as_mut_ptr() as usize,
+                              size);
+        }
+        self.seek += size;
+        return Some(size);
     }
 
 
     fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        self.follow_mode();
         let display = &mut self.display;
 
         let size = cmp::min(display.size - self.seek, buf.len());
         unsafe {
-            Display::copy_run(buf.as_ptr() as usize,
-                              display.offscreen + self.seek,
-                              size);
+            if self.blend {
+                Display::copy_run_alpha(buf.as_ptr() as usize,
+                                        display.offscreen + self.seek,
+                                        size);
+            } else {
+                Display::copy_run(buf.as_ptr() as usize,
+                                  display.offscreen + self.seek,
+                                  size);
+            }
+        }
+
+        // Mark the written pixels as damaged, so that sync only flips them
+        if size > 0 {
+            let start_y = self.seek / display.bytesperrow;
+            let end_y = (self.seek + size - 1) / display.bytesperrow + 1;
+            let (start_x, end_x) = if end_y - start_y == 1 {
+                (self.seek % display.bytesperrow / 4,
+                 ((self.seek + size - 1) % display.bytesperrow) / 4 + 1)
+            } else {
+                (0, display.width)
+            };
+
+            self.add_damage(start_x, start_y, end_x, end_y);
         }
+
         self.seek += size;
         return Some(size);
     }
 
     fn seek(&mut self, pos: ResourceSeek) -> Option<usize> {
+        self.follow_mode();
         let end = self.display.size;
 
         self.seek = match pos {
@@ -58,28 +153,556 @@ impl Resource for DisplayResource {
         return Some(self.seek);
     }
 
+    /// Fill the rectangles queued on the scheme, then flip the pixels written since the last sync,
+    /// or the whole display if none were written
+    /// The cursor is then drawn over the screen, after erasing it where it was last drawn
     fn sync(&mut self) -> bool {
-        self.display.flip();
+        self.follow_mode();
+        let fills = unsafe { mem::replace(&mut (*self.scheme).fills, Vec::new()) };
+        for (x, y, w, h, color) in fills {
+            self.fill_rect(x, y, w, h, color);
+        }
+
+        match self.damage {
+            Some((left, top, right, bottom)) => {
+                self.display.flip_rect(Point::new(left as isize, top as isize),
+                                       Size::new(right - left, bottom - top))
+            }
+            None => self.display.flip(),
+        }
+        self.damage = None;
+
+        if let Some(point) = self.cursor {
+            self.display.flip_rect(point, Size::new(CURSOR_WIDTH, CURSOR_HEIGHT));
+        }
+        self.cursor = unsafe { (*self.scheme).cursor };
+        if let Some(point) = self.cursor {
+            self.display.overlay(point, &CURSOR_SPRITE);
+        }
+
         return true;
     }
 }
 
+/// The video mode command resource, `display://mode`
+/// Reading gives the mode as `WIDTHxHEIGHTxDEPTH`, and writing `mode WIDTHxHEIGHTxDEPTH` sets it.
+pub struct ModeResource {
+    pub seek: usize,
+}
+
+impl ModeResource {
+    /// Get the current mode
+    fn mode() -> String {
+        ModeResource::describe(unsafe { &*VBEMODEINFO })
+    }
+
+    /// Describe a mode as `WIDTHxHEIGHTxDEPTH`
+    fn describe(mode_info: &VBEModeInfo) -> String {
+        format!("{}x{}x{}", mode_info.xresolution, mode_info.yresolution, mode_info.bitsperpixel)
+    }
+
+    /// Parse a `mode WIDTHxHEIGHTxDEPTH` command
+    fn parse(command: &str) -> Option<(usize, usize, usize)> {
+        let mut args = command.trim().split(' ');
+        if args.next() != Some("mode") {
+            return None;
+        }
+
+        let parts: Vec<String> = match args.next() {
+            Some(mode) => mode.split('x').map(|part| part.to_string()).collect(),
+            None => return None,
+        };
+        if args.next().is_some() || parts.len() != 3 ||
+           ! parts.iter().all(|part| part.len() > 0 && part.chars().all(|c| c.is_digit(10))) {
+            return None;
+        }
+
+        Some((parts[0].to_num(), parts[1].to_num(), parts[2].to_num()))
+    }
+}
+
+impl Resource for ModeResource {
+    fn dup(&self) -> Option<Box<Resource>> {
+        Some(box ModeResource { seek: self.seek })
+    }
+
+    fn url(&self) -> URL {
+        URL::from_str("display://mode")
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let mode = ModeResource::mode();
+        let mut i = 0;
+        while i < buf.len() && self.seek < mode.len() {
+            buf[i] = mode.as_bytes()[self.seek];
+            self.seek += 1;
+            i += 1;
+        }
+        Some(i)
+    }
+
+    /// Set the mode, failing if it is not supported
+    fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        let command = match String::from_utf8(buf.to_vec()) {
+            Ok(command) => command,
+            Err(_) => return None,
+        };
+        let (width, height, depth) = match ModeResource::parse(&command) {
+            Some(mode) => mode,
+            None => return None,
+        };
+
+        if unsafe { display::set_mode(width, height, depth) } {
+            self.seek = 0;
+            Some(buf.len())
+        } else {
+            None
+        }
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Option<usize> {
+        let end = ModeResource::mode().len();
+        self.seek = match pos {
+            ResourceSeek::Start(offset) => cmp::min(end, offset),
+            ResourceSeek::Current(offset) => cmp::min(end, cmp::max(0, self.seek as isize + offset) as usize),
+            ResourceSeek::End(offset) => cmp::min(end, cmp::max(0, end as isize + offset) as usize),
+        };
+        Some(self.seek)
+    }
+
+    fn sync(&mut self) -> bool {
+        true
+    }
+}
+
+impl DisplayScheme {
+    /// Parse the index of a display from the host of a URL, which is 0 if it is empty
+    fn index(host: &String) -> Option<usize> {
+        if host.len() == 0 {
+            Some(0)
+        } else if host.chars().all(|c| c.is_digit(10)) {
+            Some(host.to_num())
+        } else {
+            None
+        }
+    }
+}
+
 impl KScheme for DisplayScheme {
     fn scheme(&self) -> &str {
         "display"
     }
 
     fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
-        // TODO: ponder these things:
-        // - should display:// be the only only valid url
-        //      for this scheme?
-        // - maybe "read" should support displays at some other location
+        // TODO: maybe "read" should support displays at some other location
         //      like built in screen sharing capability or something
+
+        // display://info describes the root display, one "key=value" per line:
+        // width and height in pixels, bytesperrow, bitsperpixel, and the byte order
+        // of a pixel, which is BGRA for 32 bit modes
+        if url.host() == "info" {
+            let mode_info = unsafe { &*VBEMODEINFO };
+            let info = format!("width={}\nheight={}\nbytesperrow={}\nbitsperpixel={}\nformat=BGRA",
+                               mode_info.xresolution,
+                               mode_info.yresolution,
+                               mode_info.bytesperscanline,
+                               mode_info.bitsperpixel);
+            return Some(box VecResource::new(url.clone(), info.into_bytes()));
+        }
+
+        // display://mode reads and sets the video mode
+        if url.host() == "mode" {
+            return Some(box ModeResource { seek: 0 });
+        }
+
+        // display://cursor?x,y moves the cursor, which is drawn on the next sync of a display.
+        // The cursor is never written to the display's pixels, so moving it leaves them alone.
+        // Reading gives the position of the cursor, or nothing if it is hidden
+        if url.host() == "cursor" {
+            let query = url.query();
+            if query.len() == 0 {
+                self.cursor = None;
+            } else {
+                let mut parts = query.split(',');
+                match (parts.next(), parts.next()) {
+                    (Some(x), Some(y)) => {
+                        self.cursor = Some(Point::new(x.to_string().to_num_signed(),
+                                                      y.to_string().to_num_signed()));
+                    }
+                    _ => return None,
+                }
+            }
+
+            let position = match self.cursor {
+                Some(point) => format!("{},{}", point.x, point.y),
+                None => String::new(),
+            };
+            return Some(box VecResource::new(url.clone(), position.into_bytes()));
+        }
+
+        // display://fill?x,y,w,h,RRGGBB queues a rectangle of an opaque color,
+        // which is filled on the next sync of a display
+        if url.host() == "fill" {
+            let parts: Vec<String> = url.query().split(',').map(|part| part.to_string()).collect();
+            if parts.len() != 5 {
+                return None;
+            }
+            let color = Color { data: 0xFF000000 | parts[4].to_num_radix(16) as u32 };
+            self.fills.push((parts[0].to_num(), parts[1].to_num(),
+                             parts[2].to_num(), parts[3].to_num(),
+                             color));
+            return Some(box VecResource::new(url.clone(), Vec::new()));
+        }
+
+        // display://<index> opens an attached display, display:// is the root display.
+        // Adding ?blend makes writes blend pixels over the display by their alpha
+        // TODO: Find other attached displays
+        match DisplayScheme::index(&url.host()) {
+            Some(0) => unsafe {
+                Some(box DisplayResource {
+                    scheme: self,
+                    display: Display::root(),
+                    seek: 0,
+                    damage: None,
+                    blend: url.query() == "blend",
+                    cursor: None,
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use collections::string::{String, ToString};
+    use collections::vec::Vec;
+
+    use common::memory;
+    use common::to_num::ToNum;
+
+    use graphics::color::Color;
+    use graphics::display::{self, Display, VBEModeInfo};
+    use graphics::point::Point;
+    use graphics::size::Size;
+
+    use schemes::{KScheme, Resource, ResourceSeek, URL};
+
+    use super::*;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 16;
+
+    /// Point the mode info at a mode in memory, the same for every test
+    fn mode() {
+        static mut MODE: [u8; 64] = [0; 64];
         unsafe {
-            return Some(box DisplayResource {
-                        display: Display::root(),
-                       seek: 0,
-            });
+            let mode_info = &mut *(MODE.as_mut_ptr() as *mut VBEModeInfo);
+            mode_info.xresolution = WIDTH as u16;
+            mode_info.yresolution = HEIGHT as u16;
+            mode_info.bytesperscanline = (WIDTH * 4) as u16;
+            mode_info.bitsperpixel = 32;
+            display::VBEMODEINFO = mode_info;
         }
     }
+
+    /// A root display with its screen in memory, cleared to black
+    fn root(width: usize, height: usize) -> Box<Display> {
+        let size = width * 4 * height;
+        let display = box Display {
+            offscreen: unsafe { memory::alloc(size) },
+            onscreen: unsafe { memory::alloc(size) },
+            size: size,
+            bytesperrow: width * 4,
+            width: width,
+            height: height,
+            root: true,
+        };
+        display.set(Color::new(0, 0, 0));
+        display.flip();
+        display
+    }
+
+    fn resource(scheme: &mut DisplayScheme, blend: bool) -> DisplayResource {
+        mode();
+        DisplayResource {
+            scheme: scheme,
+            display: root(WIDTH, HEIGHT),
+            seek: 0,
+            damage: None,
+            blend: blend,
+            cursor: None,
+        }
+    }
+
+    /// Get a pixel of a buffer of a display of the test mode
+    fn pixel(buffer: usize, x: usize, y: usize) -> u32 {
+        unsafe { *((buffer + (y * WIDTH + x) * 4) as *const u32) }
+    }
+
+    fn read_string(resource: &mut Box<Resource>) -> String {
+        let mut bytes = Vec::new();
+        resource.read_to_end(&mut bytes);
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_read_back() {
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut resource = resource(&mut scheme, false);
+
+        let mut pattern = Vec::new();
+        for i in 0..WIDTH * HEIGHT * 4 {
+            pattern.push((i * 7) as u8);
+        }
+        assert_eq!(resource.write(&pattern), Some(pattern.len()));
+        assert_eq!(resource.write(&pattern), Some(0));
+
+        assert_eq!(resource.seek(ResourceSeek::Start(0)), Some(0));
+        let mut buf = vec![0; pattern.len() + 4];
+        assert_eq!(resource.read(&mut buf), Some(pattern.len()));
+        assert_eq!(&buf[.. pattern.len()], &pattern[..]);
+        assert_eq!(resource.read(&mut buf), Some(0));
+
+        // Reading from the middle gives the pixels from there
+        assert_eq!(resource.seek(ResourceSeek::End(-8)), Some(pattern.len() - 8));
+        assert_eq!(resource.read(&mut buf), Some(8));
+        assert_eq!(&buf[.. 8], &pattern[pattern.len() - 8 ..]);
+    }
+
+    #[test]
+    fn test_info() {
+        mode();
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let info = read_string(&mut scheme.open(&URL::from_str("display://info")).unwrap());
+
+        let mut width = 0;
+        let mut height = 0;
+        for line in info.lines() {
+            let mut parts = line.split('=');
+            match (parts.next(), parts.next()) {
+                (Some("width"), Some(value)) => width = value.to_string().to_num(),
+                (Some("height"), Some(value)) => height = value.to_string().to_num(),
+                _ => (),
+            }
+        }
+        assert_eq!(width, WIDTH);
+        assert_eq!(height, HEIGHT);
+        assert!(info.lines().any(|line| line == "bytesperrow=128"));
+        assert!(info.lines().any(|line| line == "bitsperpixel=32"));
+        assert!(info.lines().any(|line| line == "format=BGRA"));
+    }
+
+    #[test]
+    fn test_flip_rect() {
+        mode();
+        let display = root(WIDTH, HEIGHT);
+        display.set(Color::new(255, 255, 255));
+        display.flip_rect(Point::new(2, 3), Size::new(4, 5));
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let inside = x >= 2 && x < 6 && y >= 3 && y < 8;
+                assert_eq!(pixel(display.onscreen, x, y) == 0xFFFFFFFF, inside);
+            }
+        }
+    }
+
+    #[test]
+    fn test_damage() {
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut resource = resource(&mut scheme, false);
+
+        // Two white pixels in the fourth row
+        resource.seek(ResourceSeek::Start((3 * WIDTH + 4) * 4));
+        resource.write(&[0xFF; 8]);
+        assert_eq!(resource.damage, Some((4, 3, 6, 4)));
+
+        // Changes to the offscreen pixels outside of the damage are not flipped
+        unsafe { *((resource.display.offscreen + 4) as *mut u32) = 0xFFFFFFFF };
+        assert!(resource.sync());
+        assert_eq!(resource.damage, None);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let inside = y == 3 && (x == 4 || x == 5);
+                assert_eq!(pixel(resource.display.onscreen, x, y) == 0xFFFFFFFF, inside);
+            }
+        }
+
+        // Writes over several rows damage the whole width of the rows
+        resource.seek(ResourceSeek::Start((5 * WIDTH + 30) * 4));
+        resource.write(&[0xFF; 16]);
+        assert_eq!(resource.damage, Some((0, 5, WIDTH, 7)));
+    }
+
+    #[test]
+    fn test_index() {
+        assert_eq!(DisplayScheme::index(&String::new()), Some(0));
+        assert_eq!(DisplayScheme::index(&"0".to_string()), Some(0));
+        assert_eq!(DisplayScheme::index(&"12".to_string()), Some(12));
+        assert_eq!(DisplayScheme::index(&"1a".to_string()), None);
+        assert_eq!(DisplayScheme::index(&"-1".to_string()), None);
+
+        // Only the root display is attached
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        assert!(scheme.open(&URL::from_str("display://1")).is_none());
+        assert!(scheme.open(&URL::from_str("display://one")).is_none());
+    }
+
+    #[test]
+    fn test_fill_rect() {
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut resource = resource(&mut scheme, false);
+
+        // The rectangle is clamped to the right edge of the display
+        resource.fill_rect(28, 2, 8, 3, Color::new(255, 0, 0));
+        assert_eq!(resource.damage, Some((28, 2, WIDTH, 5)));
+
+        let mut buf = vec![0; WIDTH * HEIGHT * 4];
+        assert_eq!(resource.read(&mut buf), Some(buf.len()));
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let offset = (y * WIDTH + x) * 4;
+                let inside = x >= 28 && y >= 2 && y < 5;
+                assert_eq!(&buf[offset .. offset + 4] == &[0, 0, 0xFF, 0xFF], inside);
+            }
+        }
+
+        // A fill through the scheme is drawn by the next sync
+        scheme.open(&URL::from_str("display://fill?1,8,2,2,00FF00")).unwrap();
+        assert!(resource.sync());
+        assert!(scheme.fills.is_empty());
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let inside = x >= 1 && x < 3 && y >= 8 && y < 10;
+                assert_eq!(pixel(resource.display.onscreen, x, y) == 0xFF00FF00, inside);
+            }
+        }
+
+        assert!(scheme.open(&URL::from_str("display://fill?1,2,3")).is_none());
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut blended = resource(&mut scheme, true);
+        blended.display.set(Color::new(0, 0, 200));
+
+        // Half red over blue, then an opaque green and a transparent white pixel
+        let pixels = [0x00, 0x00, 0xFF, 0x80,
+                      0x00, 0xFF, 0x00, 0xFF,
+                      0xFF, 0xFF, 0xFF, 0x00];
+        assert_eq!(blended.write(&pixels), Some(pixels.len()));
+
+        let offscreen = blended.display.offscreen;
+        assert_eq!(pixel(offscreen, 0, 0), (((255 * 128) >> 8) << 16) | ((200 * 127) >> 8));
+        assert_eq!(pixel(offscreen, 1, 0), 0xFF00FF00);
+        assert_eq!(pixel(offscreen, 2, 0), 0xFF0000C8);
+
+        // Without blending, the pixels are copied as they are
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut copied = resource(&mut scheme, false);
+        copied.display.set(Color::new(0, 0, 200));
+        assert_eq!(copied.write(&pixels), Some(pixels.len()));
+
+        let offscreen = copied.display.offscreen;
+        assert_eq!(pixel(offscreen, 0, 0), 0x80FF0000);
+        assert_eq!(pixel(offscreen, 2, 0), 0x00FFFFFF);
+    }
+
+    /// Check that the screen shows the offscreen pixels, inverted under a cursor at a point,
+    /// and that the offscreen pixels are still the ones saved
+    fn check_cursor(resource: &DisplayResource, saved: &[u32], cursor: Option<(usize, usize)>) {
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let offscreen = pixel(resource.display.offscreen, x, y);
+                assert_eq!(offscreen, saved[y * WIDTH + x]);
+
+                let inverted = match cursor {
+                    Some((cursor_x, cursor_y)) => x >= cursor_x && x < cursor_x + 2 && y >= cursor_y,
+                    None => false,
+                };
+                let expected = if inverted {
+                    offscreen ^ 0xFFFFFF
+                } else {
+                    offscreen
+                };
+                assert_eq!(pixel(resource.display.onscreen, x, y), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut resource = resource(&mut scheme, false);
+
+        let mut pattern = Vec::new();
+        for i in 0..WIDTH * HEIGHT * 4 {
+            pattern.push((i * 7) as u8);
+        }
+        resource.write(&pattern);
+        let mut saved = Vec::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                saved.push(pixel(resource.display.offscreen, x, y));
+            }
+        }
+
+        let mut position = scheme.open(&URL::from_str("display://cursor?4,2")).unwrap();
+        assert_eq!(read_string(&mut position), "4,2");
+        assert!(resource.sync());
+        check_cursor(&resource, &saved, Some((4, 2)));
+
+        // Moving the cursor erases it where it was, even outside of the damage
+        resource.seek(ResourceSeek::Start(0));
+        resource.write(&pattern[.. 4]);
+        scheme.open(&URL::from_str("display://cursor?10,0")).unwrap();
+        assert!(resource.sync());
+        assert!(match resource.cursor {
+            Some(point) => point.x == 10 && point.y == 0,
+            None => false,
+        });
+        check_cursor(&resource, &saved, Some((10, 0)));
+
+        let mut position = scheme.open(&URL::from_str("display://cursor")).unwrap();
+        assert_eq!(read_string(&mut position), "");
+        assert!(resource.sync());
+        check_cursor(&resource, &saved, None);
+
+        assert!(scheme.open(&URL::from_str("display://cursor?1")).is_none());
+    }
+
+    #[test]
+    fn test_mode() {
+        mode();
+        let mut scheme = DisplayScheme { cursor: None, fills: Vec::new() };
+        let mut resource = scheme.open(&URL::from_str("display://mode")).unwrap();
+        assert_eq!(read_string(&mut resource), "32x16x32");
+
+        assert_eq!(ModeResource::parse("mode 640x480x32\n"), Some((640, 480, 32)));
+        for command in ["", "mode", "mode 640x480", "mode 640x480x", "mode 640xax32", "mode 640x480x32 1",
+                        "size 640x480x32"].iter() {
+            assert_eq!(ModeResource::parse(command), None);
+        }
+
+        // Unsupported modes are rejected before the hardware is asked, leaving the mode
+        for command in ["mode 640x480x24", "mode 636x480x32", "mode 0x480x32", "mode 640x0x32",
+                        "mode 640x480", "hello"].iter() {
+            assert_eq!(resource.write(command.as_bytes()), None);
+        }
+        resource.seek(ResourceSeek::Start(0));
+        assert_eq!(read_string(&mut resource), "32x16x32");
+
+        // A mode that was set is reported with its new dimensions
+        let mut buffer = [0u8; 64];
+        let mode_info = unsafe { &mut *(buffer.as_mut_ptr() as *mut VBEModeInfo) };
+        mode_info.set_resolution(640, 480, 32);
+        assert_eq!(ModeResource::describe(mode_info), "640x480x32");
+        let bytesperrow = mode_info.bytesperscanline;
+        assert_eq!(bytesperrow, 640 * 4);
+        assert!(display::mode_supported(640, 480, 32));
+    }
 }
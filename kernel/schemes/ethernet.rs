@@ -42,9 +42,43 @@ impl Resource for EthernetResource {
         URL::from_string(&format!("ethernet://{}/{:X}", self.peer_addr.to_string(), self.ethertype))
     }
 
+    /// Read the data of a single frame if one is queued, without blocking
     fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
-        debug::d("TODO: Implement read for ethernet://\n");
-        return None;
+        if self.data.len() > 0 {
+            let mut bytes: Vec<u8> = Vec::new();
+            mem::swap(&mut self.data, &mut bytes);
+
+            let mut i = 0;
+            while i < buf.len() && i < bytes.len() {
+                buf[i] = bytes[i];
+                i += 1;
+            }
+            return Some(i);
+        }
+
+        loop {
+            let mut bytes = [0; 2048];
+            match self.network.read(&mut bytes) {
+                Some(0) => return Some(0),
+                Some(count) => {
+                    if let Some(frame) = EthernetII::from_bytes(bytes[.. count].to_vec()) {
+                        if frame.header.ethertype.get() == self.ethertype &&
+                           (unsafe { frame.header.dst.equals(MAC_ADDR) } ||
+                            frame.header.dst.equals(BROADCAST_MAC_ADDR)) &&
+                           (frame.header.src.equals(self.peer_addr) ||
+                            self.peer_addr.equals(BROADCAST_MAC_ADDR)) {
+                            let mut i = 0;
+                            while i < buf.len() && i < frame.data.len() {
+                                buf[i] = frame.data[i];
+                                i += 1;
+                            }
+                            return Some(i);
+                        }
+                    }
+                }
+                None => return None,
+            }
+        }
     }
 
     fn read_to_end(&mut self, vec: &mut Vec<u8>) -> Option<usize> {
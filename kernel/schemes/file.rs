@@ -1,11 +1,12 @@
 use alloc::arc::Arc;
 use alloc::boxed::Box;
 
+use collections::BTreeMap;
 use collections::slice;
 use collections::string::{String, ToString};
 use collections::vec::Vec;
 
-use core::{cmp, mem};
+use core::{cmp, mem, ptr};
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use drivers::disk::{Disk, Extent, Request};
@@ -14,35 +15,89 @@ use drivers::pciconfig::PCIConfig;
 use common::context::context_switch;
 use common::debug;
 use common::memory::Memory;
+use common::time::Duration;
+use common::to_num::ToNum;
 
 use schemes::{KScheme, Resource, ResourceSeek, URL, VecResource};
 
 /// The header of the fs
+#[derive(Copy)]
 #[repr(packed)]
 pub struct Header {
     pub signature: [u8; 8],
     pub version: u32,
-    pub name: [u8; 244],
+    pub name: [u8; 228],
+    /// The free block bitmap, created on first use if zero
+    pub free: Extent,
     pub extents: [Extent; 16],
 }
 
+impl Clone for Header {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+/// The first version, where nodes have 16 extents and no flags or times
+pub const VERSION_OLD: u32 = 0xFFFFFFFF;
+/// The current version, where nodes have flags and times in the place of their last two extents
+pub const VERSION: u32 = 0xFFFFFFFE;
+
+/// Number of extents stored directly in a node
+pub const NODE_EXTENTS: usize = 14;
+
+/// Get the number of extents stored directly in a node, for a version of the file system
+pub fn node_extents(version: u32) -> usize {
+    if version == VERSION_OLD {
+        NODE_EXTENTS + 2
+    } else {
+        NODE_EXTENTS
+    }
+}
+
+/// Maximum length of a node name, leaving room for the terminating zero
+pub const NODE_NAME_LEN: usize = 255;
+
+/// Flag for a directory node
+pub const NODE_DIRECTORY: u64 = 1;
+
 /// Data for a node
+///
+/// Old disks leave the flags and times zeroed, unless they are the last two of 16 extents,
+/// which is the case on file systems of `VERSION_OLD`.
 #[repr(packed)]
 pub struct NodeData {
     pub name: [u8; 256],
-    pub extents: [Extent; 16],
+    pub extents: [Extent; NODE_EXTENTS],
+    pub flags: u64,
+    pub created_secs: i64,
+    pub created_nanos: i32,
+    pub modified_secs: i64,
+    pub modified_nanos: i32,
 }
 
+/// Flag in the length of an extent that points to an extent table
+pub const EXTENT_TABLE: u64 = 1 << 63;
+/// Number of extents in an extent table, the last of which may link to the next table
+pub const EXTENT_TABLE_LEN: usize = 512 / 16;
+
 /// A file node
 pub struct Node {
     pub block: u64,
     pub name: String,
-    pub extents: [Extent; 16],
+    pub extents: Vec<Extent>,
+    /// Blocks of the extent tables holding the extents that do not fit in the node
+    pub tables: Vec<u64>,
+    pub flags: u64,
+    /// Creation time since the epoch, zero if unknown
+    pub created: Duration,
+    /// Modification time since the epoch, zero if unknown
+    pub modified: Duration,
 }
 
 impl Node {
-    /// Create a new file node from an address and some data
-    pub fn new(block: u64, data: &NodeData) -> Self {
+    /// Create a new file node from an address and some data, in the layout of a version
+    pub fn new(block: u64, data: &NodeData, version: u32) -> Self {
         let mut bytes = Vec::new();
         for b in data.name.iter() {
             if *b > 0 {
@@ -51,15 +106,101 @@ impl Node {
                 break;
             }
         }
+        let name = unsafe { String::from_utf8_unchecked(bytes) };
 
-        Node {
-            block: block,
-            name: unsafe { String::from_utf8_unchecked(bytes) },
-            extents: data.extents,
+        let mut all = Vec::new();
+        all.push_all(&data.extents);
+        if version == VERSION_OLD {
+            all.push_all(&unsafe { ptr::read(&data.flags as *const u64 as *const [Extent; 2]) });
+        }
+
+        let mut extents = Vec::new();
+        let mut tables = Vec::new();
+        for extent in all.iter() {
+            if extent.length & EXTENT_TABLE == EXTENT_TABLE {
+                tables.push(extent.block);
+            } else if extent.length > 0 {
+                extents.push(*extent);
+            }
+        }
+
+        if version == VERSION_OLD {
+            // Directories are only known by the slash at the end of their name
+            let flags = if name.ends_with('/') {
+                NODE_DIRECTORY
+            } else {
+                0
+            };
+
+            Node {
+                block: block,
+                name: name,
+                extents: extents,
+                tables: tables,
+                flags: flags,
+                created: Duration::new(0, 0),
+                modified: Duration::new(0, 0),
+            }
+        } else {
+            Node {
+                block: block,
+                name: name,
+                extents: extents,
+                tables: tables,
+                flags: data.flags,
+                created: Duration::new(data.created_secs, data.created_nanos),
+                modified: Duration::new(data.modified_secs, data.modified_nanos),
+            }
+        }
+    }
+
+    /// Get the modification time, zero if unknown
+    pub fn modified(&self) -> Duration {
+        self.modified
+    }
+
+    /// Is this a directory node
+    pub fn is_dir(&self) -> bool {
+        self.flags & NODE_DIRECTORY == NODE_DIRECTORY
+    }
+
+    /// Follow the chain of extent tables, adding their extents to the node
+    pub unsafe fn read_tables(&mut self, disk: &mut Disk) {
+        let mut i = 0;
+        while i < self.tables.len() {
+            if let Some(table) = Memory::<Extent>::new(EXTENT_TABLE_LEN) {
+                read_poll(disk,
+                          &Extent {
+                              block: self.tables[i],
+                              length: 512,
+                          },
+                          table.address());
+
+                for j in 0..EXTENT_TABLE_LEN {
+                    let extent = table.read(j);
+                    if extent.length & EXTENT_TABLE == EXTENT_TABLE {
+                        self.tables.push(extent.block);
+                    } else if extent.length > 0 {
+                        self.extents.push(extent);
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// The number of extent tables needed to hold the extents, for a version of the file system
+    pub fn tables_needed(&self, version: u32) -> usize {
+        let direct = node_extents(version);
+        if self.extents.len() > direct {
+            (self.extents.len() - (direct - 1) + EXTENT_TABLE_LEN - 2) / (EXTENT_TABLE_LEN - 1)
+        } else {
+            0
         }
     }
 
-    pub fn data(&self) -> NodeData {
+    /// Get the data of the node, in the layout of a version
+    pub fn data(&self, version: u32) -> NodeData {
         let mut name: [u8; 256] = [0; 256];
         let mut i = 0;
         for b in self.name.as_bytes().iter() {
@@ -70,10 +211,49 @@ impl Node {
             }
             i += 1;
         }
-        NodeData {
+
+        let direct = node_extents(version);
+        let mut extents = [Extent {
+            block: 0,
+            length: 0,
+        }; NODE_EXTENTS + 2];
+        if self.extents.len() > direct && self.tables.len() > 0 {
+            for i in 0..direct - 1 {
+                extents[i] = self.extents[i];
+            }
+            extents[direct - 1] = Extent {
+                block: self.tables[0],
+                length: 512 | EXTENT_TABLE,
+            };
+        } else {
+            for i in 0..cmp::min(self.extents.len(), direct) {
+                extents[i] = self.extents[i];
+            }
+        }
+
+        let mut data = NodeData {
             name: name,
-            extents: self.extents
+            extents: [Extent {
+                block: 0,
+                length: 0,
+            }; NODE_EXTENTS],
+            flags: self.flags,
+            created_secs: self.created.secs,
+            created_nanos: self.created.nanos,
+            modified_secs: self.modified.secs,
+            modified_nanos: self.modified.nanos,
+        };
+        for i in 0..NODE_EXTENTS {
+            data.extents[i] = extents[i];
+        }
+        if version == VERSION_OLD {
+            unsafe {
+                ptr::write(&mut data.flags as *mut u64 as *mut [Extent; 2],
+                           [extents[NODE_EXTENTS], extents[NODE_EXTENTS + 1]]);
+            }
         }
+
+        data
     }
 }
 
@@ -82,8 +262,40 @@ impl Clone for Node {
         Node {
             block: self.block,
             name: self.name.clone(),
-            extents: self.extents,
+            extents: self.extents.clone(),
+            tables: self.tables.clone(),
+            flags: self.flags,
+            created: self.created,
+            modified: self.modified,
+        }
+    }
+}
+
+/// Read the sectors of an extent into memory, polling the disk until complete
+/// Used before the disk interrupt is handled by a scheme
+unsafe fn read_poll(disk: &mut Disk, extent: &Extent, mem: usize) {
+    let sectors = (extent.length as usize + 511) / 512;
+    let mut sector: usize = 0;
+    while sector < sectors {
+        let count = cmp::min(sectors - sector, 65535);
+
+        let request = Request {
+            extent: Extent {
+                block: extent.block + sector as u64,
+                length: count as u64 * 512,
+            },
+            mem: mem + sector * 512,
+            read: true,
+            complete: Arc::new(AtomicBool::new(false)),
+        };
+
+        disk.request(request.clone());
+
+        while request.complete.load(Ordering::SeqCst) == false {
+            disk.on_poll();
         }
+
+        sector += count;
     }
 }
 
@@ -92,152 +304,932 @@ pub struct FileSystem {
     pub disk: Disk,
     pub header: Header,
     pub nodes: Vec<Node>,
+    /// The index in `nodes` of each node name
+    pub index: BTreeMap<String, usize>,
+    /// Blocks of node slots that are not in use
+    pub free_nodes: Vec<u64>,
+    /// One bit per sector, set if the sector is in use
+    pub bitmap: Vec<u8>,
+    /// The number of open resources of each node, by the block of the node
+    pub open: Vec<(u64, usize)>,
+    /// Nodes that were removed or replaced while open, freed when the last resource is closed
+    pub retired: Vec<Node>,
+}
+
+/// The reasons a file system could not be read from a disk
+#[derive(Copy, Clone, PartialEq)]
+pub enum FsError {
+    /// No disk responded
+    NoDisk,
+    /// The header does not start with the RedoxFS signature
+    BadSignature,
+    /// The header has the signature, but a version that is not supported
+    UnsupportedVersion(u32),
+}
+
+impl FsError {
+    /// Get a description of the error
+    pub fn message(&self) -> &'static str {
+        match *self {
+            FsError::NoDisk => "Disk Not Found",
+            FsError::BadSignature => "Unknown Filesystem",
+            FsError::UnsupportedVersion(_) => "Unsupported Filesystem Version",
+        }
+    }
+}
+
+/// A problem found by checking a file system
+#[derive(Copy, Clone)]
+pub enum Problem {
+    /// An extent of a node reaches past the end of the disk
+    ExtentOutOfBounds {
+        node: u64,
+        extent: Extent,
+    },
+    /// Extents of two nodes use the same sectors, where node 0 is the file system itself
+    Overlap {
+        node: u64,
+        other: u64,
+    },
+    /// A node has an empty name
+    EmptyName {
+        node: u64,
+    },
+    /// Two nodes have the same name
+    DuplicateName {
+        node: u64,
+        other: u64,
+    },
 }
 
 impl FileSystem {
     /// Create a file system from a disk
     pub fn from_disk(disk: Disk) -> Option<Self> {
+        match FileSystem::open_disk(disk) {
+            Ok(fs) => Some(fs),
+            Err(err) => {
+                debug::d(" ");
+                debug::d(err.message());
+                if let FsError::UnsupportedVersion(version) = err {
+                    debug::d(" ");
+                    debug::dh(version as usize);
+                }
+                debug::dl();
+                None
+            }
+        }
+    }
+
+    /// Create a file system from a disk, returning why it failed if it did
+    pub fn open_disk(mut disk: Disk) -> Result<Self, FsError> {
         unsafe {
             if disk.identify() {
                 debug::d(" Disk Found");
 
                 let header_ptr = Memory::<Header>::new(1).unwrap();
-                disk.read(1, 1, header_ptr.address());
+                read_poll(&mut disk,
+                          &Extent {
+                              block: 1,
+                              length: mem::size_of::<Header>() as u64,
+                          },
+                          header_ptr.address());
                 let header = header_ptr.read(0);
                 drop(header_ptr);
 
-                if header.signature[0] == 'R' as u8 &&
-                   header.signature[1] == 'E' as u8 &&
-                   header.signature[2] == 'D' as u8 &&
-                   header.signature[3] == 'O' as u8 &&
-                   header.signature[4] == 'X' as u8 &&
-                   header.signature[5] == 'F' as u8 &&
-                   header.signature[6] == 'S' as u8 &&
-                   header.signature[7] == '\0' as u8 &&
-                   header.version == 0xFFFFFFFF {
-
-                    debug::d(" Redox Filesystem\n");
-
-                    let mut nodes = Vec::new();
-                    for extent in &header.extents {
-                        if extent.block > 0 && extent.length > 0 {
-                            if let Some(data) = Memory::<NodeData>::new(extent.length as usize /
-                                                           mem::size_of::<NodeData>()) {
-                                let sectors = (extent.length as usize + 511) / 512;
-                                let mut sector: usize = 0;
-                                while sectors - sector >= 65536 {
-                                    disk.read(extent.block + sector as u64,
-                                              0,
-                                              data.address() + sector * 512);
-
-                                    /*
-                                    let request = Request {
-                                        extent: Extent {
-                                            block: extent.block + sector as u64,
-                                            length: 65536 * 512,
-                                        },
-                                        mem: data.address() + sector * 512,
-                                        read: true,
-                                        complete: Arc::new(AtomicBool::new(false)),
-                                    };
-
-                                    disk.request(request.clone());
-
-                                    while request.complete.load(Ordering::SeqCst) == false {
-                                        disk.on_poll();
-                                    }
-                                    */
-
-                                    sector += 65535;
-                                }
-                                if sector < sectors {
-                                    disk.read(extent.block + sector as u64,
-                                              (sectors - sector) as u16,
-                                              data.address() + sector * 512);
-                                    /*
-                                    let request = Request {
-                                        extent: Extent {
-                                            block: extent.block + sector as u64,
-                                            length: (sectors - sector) as u64 * 512,
-                                        },
-                                        mem: data.address() + sector * 512,
-                                        read: true,
-                                        complete: Arc::new(AtomicBool::new(false)),
-                                    };
-
-                                    disk.request(request.clone());
-
-                                    while request.complete.load(Ordering::SeqCst) == false {
-                                        disk.on_poll();
-                                    }
-                                    */
-                                }
+                if header.signature != *b"REDOXFS\0" {
+                    return Err(FsError::BadSignature);
+                }
+
+                if header.version != VERSION && header.version != VERSION_OLD {
+                    return Err(FsError::UnsupportedVersion(header.version));
+                }
 
-                                for i in 0..extent.length as usize / mem::size_of::<NodeData>() {
-                                    nodes.push(Node::new(extent.block + i as u64, &data[i]));
+                debug::d(" Redox Filesystem\n");
+
+                let mut nodes = Vec::new();
+                let mut free_nodes = Vec::new();
+                for extent in &header.extents {
+                    if extent.block > 0 && extent.length > 0 {
+                        if let Some(data) = Memory::<NodeData>::new(extent.length as usize /
+                                                       mem::size_of::<NodeData>()) {
+                            read_poll(&mut disk, extent, data.address());
+
+                            for i in 0..extent.length as usize / mem::size_of::<NodeData>() {
+                                let mut node = Node::new(extent.block + i as u64, &data[i], header.version);
+                                if node.name.len() > 0 {
+                                    node.read_tables(&mut disk);
+                                    nodes.push(node);
+                                } else {
+                                    free_nodes.push(node.block);
                                 }
                             }
                         }
                     }
+                }
 
-                    return Some(FileSystem {
-                        disk: disk,
-                        header: header,
-                        nodes: nodes,
-                    });
+                let mut fs = FileSystem {
+                    disk: disk,
+                    header: header,
+                    nodes: nodes,
+                    index: BTreeMap::new(),
+                    free_nodes: free_nodes,
+                    bitmap: Vec::new(),
+                    open: Vec::new(),
+                    retired: Vec::new(),
+                };
+
+                fs.reindex();
+
+                let free = fs.header.free;
+                if free.block > 0 && free.length > 0 {
+                    let sectors = (free.length as usize + 511) / 512;
+                    if let Some(data) = Memory::<u8>::new(sectors * 512) {
+                        read_poll(&mut fs.disk, &free, data.address());
+                        fs.bitmap.push_all(&slice::from_raw_parts(data.ptr, sectors * 512));
+                    }
                 } else {
-                    debug::d(" Unknown Filesystem\n");
+                    // Without a bitmap, everything up to the last used sector is in use
+                    let mut end = 2;
+                    for extent in fs.header.extents.iter() {
+                        end = cmp::max(end, extent.block + (extent.length + 511) / 512);
+                    }
+                    for node in fs.nodes.iter() {
+                        for extent in node.extents.iter().filter(|extent| extent.block > 0) {
+                            end = cmp::max(end, extent.block + (extent.length + 511) / 512);
+                        }
+                        for table in node.tables.iter() {
+                            end = cmp::max(end, table + 1);
+                        }
+                    }
+
+                    let sectors = fs.disk.sectors;
+                    let bitmap_len = ((sectors + 7) / 8 + 511) / 512 * 512;
+                    for _ in 0..bitmap_len {
+                        fs.bitmap.push(0);
+                    }
+                    fs.set_bits(0, end, true);
+                    fs.set_bits(sectors, bitmap_len * 8 - sectors, true);
                 }
-            } else {
-                debug::d(" Disk Not Found\n");
+
+                return Ok(fs);
             }
         }
 
-        None
+        Err(FsError::NoDisk)
     }
 
     /// Get node with a given filename
     pub fn node(&self, filename: &String) -> Option<Node> {
+        self.index.get(filename).map(|&i| self.nodes[i].clone())
+    }
+
+    /// Rebuild the index of node names, after nodes were removed or renamed
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, node) in self.nodes.iter().enumerate() {
+            self.index.insert(node.name.clone(), i);
+        }
+    }
+
+    /// Get the node in the slot at a given block
+    pub fn node_at(&self, block: u64) -> Option<Node> {
+        self.nodes.iter().find(|node| node.block == block).map(|node| node.clone())
+    }
+
+    /// Write a node to its slot on the disk, along with any extent tables it needs
+    pub fn write_node(&mut self, node: &mut Node) -> bool {
+        let version = self.header.version;
+        let tables = node.tables_needed(version);
+        while node.tables.len() < tables {
+            match self.alloc_extent(512) {
+                Some(extent) => node.tables.push(extent.block),
+                None => return false,
+            }
+        }
+        while node.tables.len() > tables {
+            if let Some(block) = node.tables.pop() {
+                self.free_extent(Extent {
+                    block: block,
+                    length: 512,
+                });
+            }
+        }
+
+        unsafe {
+            for i in 0..node.tables.len() {
+                if let Some(mut table) = Memory::<Extent>::new(EXTENT_TABLE_LEN) {
+                    for j in 0..EXTENT_TABLE_LEN {
+                        table.write(j, Extent {
+                            block: 0,
+                            length: 0,
+                        });
+                    }
+
+                    let start = node_extents(version) - 1 + i * (EXTENT_TABLE_LEN - 1);
+                    let end = cmp::min(start + EXTENT_TABLE_LEN - 1, node.extents.len());
+                    for j in start..end {
+                        table.write(j - start, node.extents[j]);
+                    }
+
+                    if let Some(next) = node.tables.get(i + 1) {
+                        table.write(EXTENT_TABLE_LEN - 1, Extent {
+                            block: *next,
+                            length: 512 | EXTENT_TABLE,
+                        });
+                    }
+
+                    self.request(&Extent {
+                                     block: node.tables[i],
+                                     length: 512,
+                                 },
+                                 table.address(),
+                                 false);
+                } else {
+                    return false;
+                }
+            }
+
+            if let Some(mut node_data) = Memory::<NodeData>::new(1) {
+                node_data.write(0, node.data(version));
+
+                self.request(&Extent {
+                                 block: node.block,
+                                 length: mem::size_of::<NodeData>() as u64,
+                             },
+                             node_data.address(),
+                             false);
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Transfer the sectors of an extent between the disk and memory, waiting for completion
+    pub unsafe fn request(&mut self, extent: &Extent, mem: usize, read: bool) {
+        let sectors = (extent.length as usize + 511) / 512;
+        let mut sector: usize = 0;
+        while sector < sectors {
+            let count = cmp::min(sectors - sector, 65535);
+
+            let request = Request {
+                extent: Extent {
+                    block: extent.block + sector as u64,
+                    length: count as u64 * 512,
+                },
+                mem: mem + sector * 512,
+                read: read,
+                complete: Arc::new(AtomicBool::new(false)),
+            };
+
+            self.disk.request(request.clone());
+
+            while request.complete.load(Ordering::SeqCst) == false {
+                context_switch(false);
+            }
+
+            sector += count;
+        }
+    }
+
+    /// Create an empty node with a given filename in a free node slot
+    pub fn create(&mut self, filename: &String) -> Option<Node> {
+        if self.node(filename).is_some() {
+            return None;
+        }
+
+        self.create_node(filename, 0)
+    }
+
+    /// Create an empty directory node, its name ending with a slash
+    pub fn mkdir(&mut self, dirname: &String) -> Option<Node> {
+        let mut name = dirname.clone();
+        if !name.ends_with('/') {
+            name.push('/');
+        }
+
         for node in self.nodes.iter() {
-            if node.name == *filename {
-                return Some(node.clone());
+            if node.name.starts_with(&name) {
+                return None;
+            }
+        }
+
+        self.create_node(&name, NODE_DIRECTORY)
+    }
+
+    fn create_node(&mut self, name: &String, flags: u64) -> Option<Node> {
+        if name.len() > NODE_NAME_LEN {
+            debug::d("Name too long\n");
+            return None;
+        }
+
+        match self.free_nodes.pop() {
+            Some(block) => {
+                let time = Duration::realtime();
+                let mut node = Node {
+                    block: block,
+                    name: name.clone(),
+                    extents: Vec::new(),
+                    tables: Vec::new(),
+                    flags: flags,
+                    created: time,
+                    modified: time,
+                };
+
+                if self.write_node(&mut node) {
+                    self.index.insert(node.name.clone(), self.nodes.len());
+                    self.nodes.push(node.clone());
+                    Some(node)
+                } else {
+                    self.free_nodes.push(block);
+                    None
+                }
+            }
+            None => {
+                debug::d("No free nodes\n");
+                None
+            }
+        }
+    }
+
+    /// Remove the node with a given filename, freeing its extents
+    /// A directory is only removed if it is empty
+    pub fn unlink(&mut self, filename: &String) -> bool {
+        let mut i = 0;
+        while i < self.nodes.len() {
+            if self.nodes[i].name == *filename {
+                // Directories must be empty
+                if self.nodes[i].is_dir() {
+                    for node in self.nodes.iter() {
+                        if node.name.len() > filename.len() && node.name.starts_with(filename) {
+                            return false;
+                        }
+                    }
+                }
+
+                let node = self.nodes.remove(i);
+                self.reindex();
+                self.retire(node);
+
+                return true;
+            }
+            i += 1;
+        }
+
+        false
+    }
+
+    /// Rename a node, keeping its block and extents
+    ///
+    /// A file replaces an existing file of the new name, which is freed once it is no longer open,
+    /// so that files can be replaced atomically by writing a temporary file and renaming it.
+    /// A directory is renamed with the nodes inside it, and never replaces anything.
+    pub fn rename(&mut self, from: &String, to: &String) -> bool {
+        if to.len() > NODE_NAME_LEN {
+            debug::d("Name too long\n");
+            return false;
+        }
+
+        let i = match self.index.get(from) {
+            Some(&i) => i,
+            None => return false,
+        };
+
+        if from == to {
+            return true;
+        }
+
+        if self.nodes[i].is_dir() {
+            return self.rename_dir(from, to);
+        }
+
+        let target = self.index.get(to).map(|&j| j);
+        if let Some(j) = target {
+            if self.nodes[i].is_dir() || self.nodes[j].is_dir() {
+                return false;
+            }
+        }
+
+        let mut node = self.nodes[i].clone();
+        node.name = to.clone();
+        if ! self.write_node(&mut node) {
+            return false;
+        }
+
+        match target {
+            Some(j) => {
+                // The new node takes the place of the old one, so the name is never missing
+                let old = mem::replace(&mut self.nodes[j], node);
+                self.nodes.remove(i);
+                self.reindex();
+                self.retire(old);
+            }
+            None => {
+                self.nodes[i] = node;
+                self.index.remove(from);
+                self.index.insert(to.clone(), i);
+            }
+        }
+
+        true
+    }
+
+    /// Rename a directory node and every node inside it
+    fn rename_dir(&mut self, from: &String, to: &String) -> bool {
+        let mut to = to.clone();
+        if !to.ends_with('/') {
+            to.push('/');
+        }
+
+        // A directory can not be moved inside itself
+        if to.starts_with(&from[..]) {
+            return false;
+        }
+
+        let mut renames = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.name.starts_with(&from[..]) {
+                let name = to.clone() + &node.name[from.len() ..];
+                if name.len() > NODE_NAME_LEN {
+                    debug::d("Name too long\n");
+                    return false;
+                }
+                if self.index.contains_key(&name) {
+                    return false;
+                }
+                renames.push((i, name));
+            }
+        }
+
+        let mut ret = true;
+        for (i, name) in renames {
+            let mut node = self.nodes[i].clone();
+            node.name = name;
+            if self.write_node(&mut node) {
+                self.nodes[i] = node;
+            } else {
+                ret = false;
+                break;
+            }
+        }
+
+        self.reindex();
+        ret
+    }
+
+    /// Clear the slot of a node that was removed from `nodes`, and free its extents and slot
+    /// once no resource has it open
+    fn retire(&mut self, mut node: Node) {
+        let mut cleared = node.clone();
+        cleared.name = String::new();
+        cleared.extents.clear();
+        self.write_node(&mut cleared);
+        node.tables.clear();
+
+        if self.open.iter().any(|&(block, _)| block == node.block) {
+            self.retired.push(node);
+        } else {
+            self.free_node(node);
+        }
+    }
+
+    /// Free the extents and slot of a retired node
+    fn free_node(&mut self, node: Node) {
+        for extent in &node.extents {
+            if extent.block > 0 && extent.length > 0 {
+                self.free_extent(*extent);
+            }
+        }
+        self.free_nodes.push(node.block);
+    }
+
+    /// Check if a node was removed or replaced while open
+    pub fn is_retired(&self, block: u64) -> bool {
+        self.retired.iter().any(|node| node.block == block)
+    }
+
+    /// Count a resource opening a node
+    pub fn retain(&mut self, block: u64) {
+        match self.open.iter().position(|&(open, _)| open == block) {
+            Some(i) => self.open[i].1 += 1,
+            None => self.open.push((block, 1)),
+        }
+    }
+
+    /// Count a resource closing a node, freeing the node if it was retired and this was the last
+    pub fn release(&mut self, block: u64) {
+        if let Some(i) = self.open.iter().position(|&(open, _)| open == block) {
+            self.open[i].1 -= 1;
+            if self.open[i].1 > 0 {
+                return;
+            }
+            self.open.remove(i);
+        }
+
+        if let Some(i) = self.retired.iter().position(|node| node.block == block) {
+            let node = self.retired.remove(i);
+            self.free_node(node);
+        }
+    }
+
+    /// Mark sectors as used or free in the bitmap
+    fn set_bits(&mut self, block: u64, sectors: u64, used: bool) {
+        for sector in block..block + sectors {
+            if let Some(byte) = self.bitmap.get_mut(sector as usize / 8) {
+                if used {
+                    *byte |= 1 << (sector % 8);
+                } else {
+                    *byte &= !(1 << (sector % 8));
+                }
+            }
+        }
+    }
+
+    /// Find the first run of free sectors of a given length
+    fn find_free(&self, sectors: u64) -> Option<u64> {
+        let mut run = 0;
+        for sector in 0..self.bitmap.len() as u64 * 8 {
+            if self.bitmap[sector as usize / 8] & (1 << (sector % 8)) == 0 {
+                run += 1;
+                if run == sectors {
+                    return Some(sector + 1 - sectors);
+                }
+            } else {
+                run = 0;
             }
         }
 
         None
     }
 
-    /// List nodes in a given directory
+    /// Write the part of the bitmap covering some sectors, creating the bitmap on the disk if needed
+    fn write_bitmap(&mut self, block: u64, sectors: u64) {
+        unsafe {
+            if self.header.free.block == 0 {
+                let bitmap_sectors = self.bitmap.len() as u64 / 512;
+                match self.find_free(bitmap_sectors) {
+                    Some(bitmap_block) => {
+                        self.set_bits(bitmap_block, bitmap_sectors, true);
+                        self.header.free = Extent {
+                            block: bitmap_block,
+                            length: bitmap_sectors * 512,
+                        };
+
+                        let free = self.header.free;
+                        let bitmap = self.bitmap.as_ptr() as usize;
+                        self.request(&free, bitmap, false);
+
+                        if let Some(mut header) = Memory::<Header>::new(1) {
+                            header.write(0, self.header);
+                            self.request(&Extent {
+                                             block: 1,
+                                             length: mem::size_of::<Header>() as u64,
+                                         },
+                                         header.address(),
+                                         false);
+                        }
+                    }
+                    None => debug::d("No space for free block bitmap\n"),
+                }
+            } else if sectors > 0 {
+                // Each sector of the bitmap covers 4096 sectors
+                let start = block / 4096;
+                let end = cmp::min((block + sectors - 1) / 4096 + 1,
+                                   self.bitmap.len() as u64 / 512);
+                if start >= end {
+                    return;
+                }
+
+                let bitmap = self.bitmap.as_ptr() as usize + start as usize * 512;
+                let free = self.header.free;
+                self.request(&Extent {
+                                 block: free.block + start,
+                                 length: (end - start) * 512,
+                             },
+                             bitmap,
+                             false);
+            }
+        }
+    }
+
+    /// Allocate an extent of a given length in bytes
+    pub fn alloc_extent(&mut self, length: u64) -> Option<Extent> {
+        let sectors = (length + 511) / 512;
+        if sectors == 0 {
+            return None;
+        }
+
+        match self.find_free(sectors) {
+            Some(block) => {
+                self.set_bits(block, sectors, true);
+                self.write_bitmap(block, sectors);
+
+                Some(Extent {
+                    block: block,
+                    length: length,
+                })
+            }
+            None => None,
+        }
+    }
+
+    /// Free the sectors of an extent
+    pub fn free_extent(&mut self, extent: Extent) {
+        let sectors = (extent.length + 511) / 512;
+        self.set_bits(extent.block, sectors, false);
+        self.write_bitmap(extent.block, sectors);
+    }
+
+    /// List the children of a given directory, with subdirectories ending in a slash
+    /// Subdirectories without a directory node are found from the names of the nodes inside them
     pub fn list(&self, directory: &String) -> Vec<String> {
         let mut ret = Vec::<String>::new();
 
-        for node in self.nodes.iter() {
-            if node.name.starts_with(directory) {
-                ret.push(node.name[directory.len() ..].to_string());
+        // The names inside the directory are next to each other in the index
+        let mut children: Vec<usize> = self.index.iter()
+                                                 .skip_while(|&(name, _)| name < directory)
+                                                 .take_while(|&(name, _)| name.starts_with(&directory[..]))
+                                                 .map(|(_, &i)| i)
+                                                 .collect();
+        children.sort();
+
+        for node in children.iter().map(|&i| &self.nodes[i]) {
+            if node.name.len() > directory.len() {
+                let child = &node.name[directory.len() ..];
+                let entry = match child.find('/') {
+                    Some(index) => child[.. index + 1].to_string(),
+                    None => child.to_string(),
+                };
+
+                if !ret.contains(&entry) {
+                    ret.push(entry);
+                }
             }
         }
 
         ret
     }
+
+    /// Check the nodes for problems, without repairing them
+    ///
+    /// Nodes are identified by the block of their slot.
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        // The sectors used by the file system itself belong to node 0
+        let mut used: Vec<(u64, u64, u64)> = Vec::new();
+        used.push((1, 2, 0));
+        for extent in self.header.extents.iter().chain(Some(self.header.free).iter()) {
+            if extent.block > 0 && extent.length > 0 {
+                used.push((extent.block, extent.block + (extent.length + 511) / 512, 0));
+            }
+        }
+
+        // Retired nodes keep their extents until they are closed. Holes have no sectors
+        for node in self.retired.iter() {
+            for extent in node.extents.iter().filter(|extent| extent.block > 0) {
+                used.push((extent.block, extent.block + (extent.length + 511) / 512, node.block));
+            }
+        }
+
+        for node in self.nodes.iter() {
+            if node.name.len() == 0 {
+                problems.push(Problem::EmptyName { node: node.block });
+            }
+
+            for table in node.tables.iter() {
+                used.push((*table, table + 1, node.block));
+            }
+            for extent in node.extents.iter().filter(|extent| extent.block > 0) {
+                let end = extent.block + (extent.length + 511) / 512;
+                if end > self.disk.sectors {
+                    problems.push(Problem::ExtentOutOfBounds {
+                        node: node.block,
+                        extent: *extent,
+                    });
+                }
+                used.push((extent.block, end, node.block));
+            }
+        }
+
+        for i in 0..self.nodes.len() {
+            for j in i + 1..self.nodes.len() {
+                if self.nodes[i].name == self.nodes[j].name {
+                    problems.push(Problem::DuplicateName {
+                        node: self.nodes[i].block,
+                        other: self.nodes[j].block,
+                    });
+                }
+            }
+        }
+
+        // Each pair of nodes is reported once, however many of their extents overlap
+        let mut overlaps: Vec<(u64, u64)> = Vec::new();
+        for i in 0..used.len() {
+            for j in i + 1..used.len() {
+                let (start, end, node) = used[i];
+                let (other_start, other_end, other) = used[j];
+                if node != other && start < other_end && other_start < end {
+                    let pair = (cmp::max(node, other), cmp::min(node, other));
+                    if !overlaps.contains(&pair) {
+                        overlaps.push(pair);
+                        problems.push(Problem::Overlap {
+                            node: pair.0,
+                            other: pair.1,
+                        });
+                    }
+                }
+            }
+        }
+
+        problems
+    }
 }
 
+/// Files larger than this are read on demand, a page at a time, until they are written
+pub const FILE_LAZY_SIZE: usize = 65536;
+/// The size of a page of a file read on demand
+pub const FILE_PAGE_SIZE: usize = 4096;
+/// The number of pages kept for a file read on demand
+pub const FILE_CACHE_PAGES: usize = 16;
+/// The number of zero sectors in a row that are left as a hole when a file is written
+pub const FILE_HOLE_SECTORS: usize = 8;
+
 /// A file resource
 pub struct FileResource {
     pub scheme: *mut FileScheme,
     pub node: Node,
-    pub vec: Vec<u8>,
+    /// The pages of the file, by their offset, if it is loaded
+    /// Pages that were never written or read from data on the disk are holes, read as zeros
+    pub pages: BTreeMap<usize, Vec<u8>>,
     pub seek: usize,
     pub dirty: bool,
+    /// False if the file was opened read-only
+    pub writable: bool,
+    /// True if `pages` holds the whole file, false if it is read on demand
+    pub loaded: bool,
+    /// The size of the file
+    pub size: usize,
+    /// Recently read pages, by their offset in the file, if it is not loaded
+    pub cache: Vec<(usize, Vec<u8>)>,
+}
+
+impl FileResource {
+    /// Create a resource for a node, reading small files at once and large ones on demand
+    pub fn new(scheme: *mut FileScheme, node: Node, writable: bool) -> FileResource {
+        unsafe { (*scheme).fs.retain(node.block) };
+
+        let size = node.extents.iter().fold(0, |size, extent| size + extent.length as usize);
+        let mut resource = FileResource {
+            scheme: scheme,
+            node: node,
+            pages: BTreeMap::new(),
+            seek: 0,
+            dirty: false,
+            writable: writable,
+            loaded: false,
+            size: size,
+            cache: Vec::new(),
+        };
+
+        if size <= FILE_LAZY_SIZE {
+            resource.load();
+        }
+
+        resource
+    }
+
+    /// Read the whole file into memory, before it is changed
+    ///
+    /// Only the pages holding data on the disk are read, so holes stay out of memory.
+    fn load(&mut self) {
+        if ! self.loaded {
+            let mut starts = Vec::new();
+            let mut pos = 0;
+            for extent in self.node.extents.iter() {
+                let len = extent.length as usize;
+                if extent.block > 0 {
+                    let mut start = pos / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+                    while start < pos + len {
+                        if starts.last() != Some(&start) {
+                            starts.push(start);
+                        }
+                        start += FILE_PAGE_SIZE;
+                    }
+                }
+                pos += len;
+            }
+
+            for start in starts {
+                let i = unsafe { self.page(start) };
+                let (_, mut page) = self.cache.remove(i);
+                while page.len() < FILE_PAGE_SIZE {
+                    page.push(0);
+                }
+                self.pages.insert(start, page);
+            }
+
+            self.loaded = true;
+            self.cache.clear();
+        }
+    }
+
+    /// Get the index in the cache of the page starting at an offset, reading it if needed
+    unsafe fn page(&mut self, start: usize) -> usize {
+        if let Some(i) = self.cache.iter().position(|&(offset, _)| offset == start) {
+            return i;
+        }
+
+        let end = start + FILE_PAGE_SIZE;
+        let mut page = Vec::new();
+
+        // Read the sectors of each extent that overlap the page, and zeros for holes
+        let mut pos = 0;
+        for extent in self.node.extents.iter() {
+            let len = extent.length as usize;
+            if start < pos + len && end > pos {
+                let from = cmp::max(start, pos) - pos;
+                let to = cmp::min(end, pos + len) - pos;
+                if extent.block == 0 {
+                    for _ in from..to {
+                        page.push(0);
+                    }
+                    pos += len;
+                    continue;
+                }
+
+                let first = from / 512;
+                let sectors = (to + 511) / 512 - first;
+                if let Some(data) = Memory::<u8>::new(sectors * 512) {
+                    (*self.scheme).fs.request(&Extent {
+                                                  block: extent.block + first as u64,
+                                                  length: (sectors * 512) as u64,
+                                              },
+                                              data.address(),
+                                              true);
+                    page.push_all(&slice::from_raw_parts(data.ptr.offset((from - first * 512) as isize),
+                                                         to - from));
+                }
+            }
+            pos += len;
+        }
+
+        if self.cache.len() >= FILE_CACHE_PAGES {
+            self.cache.remove(0);
+        }
+        self.cache.push((start, page));
+        self.cache.len() - 1
+    }
+
+    /// Check if a sector of the file holds only zeros
+    fn zero_sector(&self, sector: usize) -> bool {
+        let pos = sector * 512;
+        let start = pos / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+        match self.pages.get(&start) {
+            Some(page) => page[pos - start .. pos - start + 512].iter().all(|&b| b == 0),
+            None => true,
+        }
+    }
+
+    /// Write the data at a position to an extent, from a zeroed buffer of whole sectors
+    unsafe fn write_extent(&self, extent: &Extent, pos: usize) {
+        let len = cmp::min(extent.length as usize, self.size - pos);
+        let size = (extent.length as usize + 511) / 512 * 512;
+        if let Some(data) = Memory::<u8>::new(size) {
+            ptr::write_bytes(data.ptr, 0, size);
+
+            // Copy the pages, leaving zeros for holes
+            let mut i = 0;
+            while i < len {
+                let start = (pos + i) / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+                let offset = pos + i - start;
+                let count = cmp::min(len - i, FILE_PAGE_SIZE - offset);
+                if let Some(page) = self.pages.get(&start) {
+                    ptr::copy(page.as_ptr().offset(offset as isize), data.ptr.offset(i as isize), count);
+                }
+                i += count;
+            }
+
+            (*self.scheme).fs.request(extent, data.address(), false);
+        }
+    }
 }
 
 impl Resource for FileResource {
     fn dup(&self) -> Option<Box<Resource>> {
+        unsafe { (*self.scheme).fs.retain(self.node.block) };
+
         Some(box FileResource {
             scheme: self.scheme,
             node: self.node.clone(),
-            vec: self.vec.clone(),
+            pages: self.pages.clone(),
             seek: self.seek,
             dirty: self.dirty,
+            writable: self.writable,
+            loaded: self.loaded,
+            size: self.size,
+            cache: self.cache.clone(),
         })
     }
 
@@ -247,30 +1239,61 @@ impl Resource for FileResource {
 
     fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
         let mut i = 0;
-        while i < buf.len() && self.seek < self.vec.len() {
-            match self.vec.get(self.seek) {
-                Some(b) => buf[i] = *b,
-                None => (),
+
+        if ! self.loaded {
+            while i < buf.len() && self.seek < self.size {
+                let start = self.seek / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+                let page = unsafe { self.page(start) };
+                if self.seek - start >= self.cache[page].1.len() {
+                    break;
+                }
+                while i < buf.len() && self.seek - start < self.cache[page].1.len() {
+                    buf[i] = self.cache[page].1[self.seek - start];
+                    self.seek += 1;
+                    i += 1;
+                }
             }
-            self.seek += 1;
-            i += 1;
+            return Some(i);
+        }
+
+        while i < buf.len() && self.seek < self.size {
+            let start = self.seek / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+            let offset = self.seek - start;
+            let count = cmp::min(buf.len() - i, cmp::min(FILE_PAGE_SIZE - offset, self.size - self.seek));
+            match self.pages.get(&start) {
+                Some(page) => unsafe {
+                    ptr::copy(page.as_ptr().offset(offset as isize), buf.as_mut_ptr().offset(i as isize), count);
+                },
+                None => for b in buf[i .. i + count].iter_mut() {
+                    *b = 0;
+                },
+            }
+            self.seek += count;
+            i += count;
         }
         Some(i)
     }
 
     fn write(&mut self, buf: &[u8]) -> Option<usize> {
-        let mut i = 0;
-        while i < buf.len() && self.seek < self.vec.len() {
-            self.vec[self.seek] = buf[i];
-            self.seek += 1;
-            i += 1;
+        if ! self.writable {
+            return None;
         }
+
+        self.load();
+
+        // Only the pages written to are created, so a gap left by seeking past the end is a hole
+        let mut i = 0;
         while i < buf.len() {
-            self.vec.push(buf[i]);
-            self.seek += 1;
-            i += 1;
+            let start = self.seek / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+            let offset = self.seek - start;
+            let count = cmp::min(buf.len() - i, FILE_PAGE_SIZE - offset);
+            let page = self.pages.entry(start).or_insert_with(|| vec![0; FILE_PAGE_SIZE]);
+            unsafe { ptr::copy(buf.as_ptr().offset(i as isize), page.as_mut_ptr().offset(offset as isize), count) };
+            self.seek += count;
+            i += count;
         }
         if i > 0 {
+            self.size = cmp::max(self.size, self.seek);
             self.dirty = true;
         }
         Some(i)
@@ -282,118 +1305,169 @@ impl Resource for FileResource {
             ResourceSeek::Current(offset) =>
                 self.seek = cmp::max(0, self.seek as isize + offset) as usize,
             ResourceSeek::End(offset) =>
-                self.seek = cmp::max(0, self.vec.len() as isize + offset) as usize,
-        }
-        while self.vec.len() < self.seek {
-            self.vec.push(0);
+                self.seek = cmp::max(0, self.size as isize + offset) as usize,
         }
         Some(self.seek)
     }
 
-    // TODO: Rename to sync
+    /// Write the changes to the disk, leaving runs of zeros as holes that use no sectors
     // TODO: Check to make sure proper amount of bytes written. See Disk::write
-    // TODO: Allow reallocation
     fn sync(&mut self) -> bool {
+        // The file was replaced or removed, so there is nothing to write the changes to
+        if self.dirty && unsafe { (*self.scheme).fs.is_retired(self.node.block) } {
+            self.dirty = false;
+            return false;
+        }
+
         if self.dirty {
-            let block_size: usize = 512;
-
-            let mut node_dirty = false;
-            let mut pos: isize = 0;
-            let mut remaining = self.vec.len() as isize;
-            for ref mut extent in &mut self.node.extents {
-                //Make sure it is a valid extent
-                if extent.block > 0 && extent.length > 0 {
-                    let current_sectors = (extent.length as usize + block_size - 1) / block_size;
-                    let max_size = current_sectors * 512;
-
-                    let size = cmp::min(remaining as usize, max_size);
-
-                    if size as u64 != extent.length {
-                        extent.length = size as u64;
-                        node_dirty = true;
-                    }
+            self.node.modified = Duration::realtime();
 
-                    unsafe {
-                        let data = self.vec.as_ptr().offset(pos) as usize;
-                        //TODO: Make sure data is copied safely into an zeroed area of the right size!
-
-                        let sectors = (extent.length as usize + 511) / 512;
-                        let mut sector: usize = 0;
-                        while sectors - sector >= 65536 {
-                            let request = Request {
-                                extent: Extent {
-                                    block: extent.block + sector as u64,
-                                    length: 65536 * 512,
-                                },
-                                mem: data + sector * 512,
-                                read: false,
-                                complete: Arc::new(AtomicBool::new(false)),
-                            };
-
-                            (*self.scheme).fs.disk.request(request.clone());
-
-                            while request.complete.load(Ordering::SeqCst) == false {
-                                context_switch(false);
-                            }
+            let sectors = (self.size + 511) / 512;
 
-                            sector += 65535;
-                        }
-                        if sector < sectors {
-                            let request = Request {
-                                extent: Extent {
-                                    block: extent.block + sector as u64,
-                                    length: (sectors - sector) as u64 * 512,
-                                },
-                                mem: data + sector * 512,
-                                read: false,
-                                complete: Arc::new(AtomicBool::new(false)),
-                            };
-
-                            (*self.scheme).fs.disk.request(request.clone());
-
-                            while request.complete.load(Ordering::SeqCst) == false {
-                                context_switch(false);
-                            }
-                        }
+            // The disk sector of each sector of the file, zero in holes
+            let mut blocks: Vec<u64> = Vec::new();
+            for extent in self.node.extents.iter() {
+                for sector in 0..(extent.length + 511) / 512 {
+                    blocks.push(if extent.block > 0 {
+                        extent.block + sector
+                    } else {
+                        0
+                    });
+                }
+            }
+
+            // Zero sectors in a hole stay there, and long runs of them become holes
+            let mut data: Vec<bool> = Vec::new();
+            let mut sector = 0;
+            while sector < sectors {
+                if self.zero_sector(sector) {
+                    let mut end = sector + 1;
+                    while end < sectors && self.zero_sector(end) {
+                        end += 1;
                     }
+                    for i in sector..end {
+                        data.push(end - sector < FILE_HOLE_SECTORS && blocks.get(i) != Some(&0));
+                    }
+                    sector = end;
+                } else {
+                    data.push(true);
+                    sector += 1;
+                }
+            }
 
-                    pos += size as isize;
-                    remaining -= size as isize;
+            // Free sectors that are past the end of the file or in a hole, a run at a time
+            let mut free: Option<Extent> = None;
+            for i in 0..blocks.len() {
+                let unused = blocks[i] > 0 && (i >= sectors || ! data[i]);
+                let joined = match free {
+                    Some(ref mut extent) => if unused && extent.block + extent.length / 512 == blocks[i] {
+                        extent.length += 512;
+                        true
+                    } else {
+                        false
+                    },
+                    None => false,
+                };
+                if ! joined {
+                    if let Some(extent) = free.take() {
+                        unsafe { (*self.scheme).fs.free_extent(extent) };
+                    }
+                    if unused {
+                        free = Some(Extent {
+                            block: blocks[i],
+                            length: 512,
+                        });
+                    }
+                }
+                if unused {
+                    blocks[i] = 0;
                 }
             }
+            if let Some(extent) = free.take() {
+                unsafe { (*self.scheme).fs.free_extent(extent) };
+            }
+
+            blocks.truncate(sectors);
+            while blocks.len() < sectors {
+                blocks.push(0);
+            }
 
-            if node_dirty {
-                debug::d("Node dirty, rewrite\n");
+            // Allocate sectors for the data outside of the old extents, splitting the allocation if needed
+            let mut remaining = 0;
+            let mut i = 0;
+            while i < sectors {
+                if ! data[i] || blocks[i] > 0 {
+                    i += 1;
+                    continue;
+                }
 
-                unsafe {
-                    if let Some(mut node_data) = Memory::<NodeData>::new(1) {
-                        node_data.write(0, self.node.data());
+                let mut end = i + 1;
+                while end < sectors && data[end] && blocks[end] == 0 {
+                    end += 1;
+                }
 
-                        let request = Request {
-                            extent: Extent {
-                                block: self.node.block,
-                                length: 1,
-                            },
-                            mem: node_data.address(),
-                            read: false,
-                            complete: Arc::new(AtomicBool::new(false)),
-                        };
+                let mut count = end - i;
+                while i < end && count > 0 {
+                    match unsafe { (*self.scheme).fs.alloc_extent(count as u64 * 512) } {
+                        Some(extent) => {
+                            for j in 0..count {
+                                blocks[i + j] = extent.block + j as u64;
+                            }
+                            i += count;
+                            count = end - i;
+                        }
+                        None => count /= 2,
+                    }
+                }
 
-                        debug::d("Disk request\n");
+                // Without space, the rest of the run is lost
+                remaining += (end - i) * 512;
+                i = end;
+            }
 
-                        (*self.scheme).fs.disk.request(request.clone());
+            // Join the sectors into extents, the last one holding the partial sector
+            let mut extents: Vec<Extent> = Vec::new();
+            for i in 0..sectors {
+                let block = blocks[i];
+                let length = cmp::min(512, self.size - i * 512) as u64;
+                let joined = match extents.last_mut() {
+                    Some(extent) => if (block == 0 && extent.block == 0) ||
+                                       (block > 0 && extent.block > 0 && extent.block + extent.length / 512 == block) {
+                        extent.length += length;
+                        true
+                    } else {
+                        false
+                    },
+                    None => false,
+                };
+                if ! joined {
+                    extents.push(Extent {
+                        block: block,
+                        length: length,
+                    });
+                }
+            }
 
-                        debug::d("Wait request\n");
-                        while request.complete.load(Ordering::SeqCst) == false {
-                            context_switch(false);
-                        }
+            let mut pos = 0;
+            for extent in extents.iter() {
+                if extent.block > 0 {
+                    unsafe { self.write_extent(extent, pos) };
+                }
+                pos += extent.length as usize;
+            }
+            self.node.extents = extents;
 
-                        debug::d("Renode\n");
+            // The node always changes, as the modification time is updated
+            unsafe {
+                // The node may have been renamed since it was opened
+                if let Some(node) = (*self.scheme).fs.node_at(self.node.block) {
+                    self.node.name = node.name;
+                }
 
-                        for mut node in (*self.scheme).fs.nodes.iter_mut() {
-                            if node.block == self.node.block {
-                                *node = self.node.clone();
-                            }
+                if (*self.scheme).fs.write_node(&mut self.node) {
+                    for mut node in (*self.scheme).fs.nodes.iter_mut() {
+                        if node.block == self.node.block {
+                            *node = self.node.clone();
                         }
                     }
                 }
@@ -402,19 +1476,45 @@ impl Resource for FileResource {
             self.dirty = false;
 
             if remaining > 0 {
-                debug::d("Need to reallocate file, extra: ");
-                debug::ds(remaining);
+                debug::d("No space to grow file, extra: ");
+                debug::dd(remaining);
                 debug::dl();
                 return false;
             }
         }
         true
     }
+
+    fn truncate(&mut self, len: usize) -> bool {
+        if ! self.writable {
+            return false;
+        }
+
+        self.load();
+
+        // Drop the pages past the end, and zero the rest of the last page in case the file grows again
+        if len < self.size {
+            let start = len / FILE_PAGE_SIZE * FILE_PAGE_SIZE;
+            let keys: Vec<usize> = self.pages.keys().cloned().filter(|&key| key >= len).collect();
+            for key in keys {
+                self.pages.remove(&key);
+            }
+            if let Some(page) = self.pages.get_mut(&start) {
+                for b in page[len - start ..].iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+        self.size = len;
+        self.dirty = true;
+        true
+    }
 }
 
 impl Drop for FileResource {
     fn drop(&mut self) {
         self.sync();
+        unsafe { (*self.scheme).fs.release(self.node.block) };
     }
 }
 
@@ -470,6 +1570,11 @@ impl FileScheme {
 
         None
     }
+
+    /// Rename a file
+    pub fn rename(&mut self, from: &String, to: &String) -> bool {
+        self.fs.rename(from, to)
+    }
 }
 
 impl KScheme for FileScheme {
@@ -491,37 +1596,31 @@ impl KScheme for FileScheme {
 
     fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
         let path = url.path();
+
+        // Opening "file://#block" gives the node at a block, whatever its name
+        let fragment = url.fragment();
+        if path.len() == 0 && fragment.len() > 0 {
+            return match self.fs.node_at(fragment.to_num() as u64) {
+                Some(node) => {
+                    let writable = url.query() != "ro";
+                    Some(box FileResource::new(self, node, writable))
+                }
+                None => None,
+            };
+        }
+
         if path.len() == 0 || path.ends_with('/') {
+            if path.len() > 0 && !self.fs.nodes.iter().any(|node| node.name.starts_with(&path)) {
+                return None;
+            }
+
             let mut list = String::new();
-            let mut dirs: Vec<String> = Vec::new();
 
             for file in self.fs.list(&path).iter() {
-                let line;
-                match file.find('/') {
-                    Some(index) => {
-                        let dirname = file[.. index + 1].to_string();
-                        let mut found = false;
-                        for dir in dirs.iter() {
-                            if dirname == *dir {
-                                found = true;
-                                break;
-                            }
-                        }
-                        if found {
-                            line = String::new();
-                        } else {
-                            line = dirname.clone();
-                            dirs.push(dirname);
-                        }
-                    }
-                    None => line = file.clone(),
-                }
-                if line.len() > 0 {
-                    if list.len() > 0 {
-                        list = list + "\n" + &line;
-                    } else {
-                        list = line;
-                    }
+                if list.len() > 0 {
+                    list = list + "\n" + &file;
+                } else {
+                    list = file.clone();
                 }
             }
 
@@ -529,65 +1628,827 @@ impl KScheme for FileScheme {
         } else {
             match self.fs.node(&path) {
                 Some(node) => {
-                    let mut vec: Vec<u8> = Vec::new();
-                    //TODO: Handle more extents
-                    for extent in &node.extents {
-                        if extent.block > 0 && extent.length > 0 {
-                            if let Some(mut data) = Memory::<u8>::new(extent.length as usize) {
-                                let sectors = (extent.length as usize + 511) / 512;
-                                let mut sector: usize = 0;
-                                while sectors - sector >= 65536 {
-                                    let request = Request {
-                                        extent: Extent {
-                                            block: extent.block + sector as u64,
-                                            length: 65536 * 512,
-                                        },
-                                        mem: unsafe { data.address() } + sector * 512,
-                                        read: true,
-                                        complete: Arc::new(AtomicBool::new(false)),
-                                    };
-
-                                    self.fs.disk.request(request.clone());
-
-                                    while !request.complete.load(Ordering::SeqCst) {
-                                        unsafe { context_switch(false) };
-                                    }
-
-                                    sector += 65535;
-                                }
-                                if sector < sectors {
-                                    let request = Request {
-                                        extent: Extent {
-                                            block: extent.block + sector as u64,
-                                            length: (sectors - sector) as u64 * 512,
-                                        },
-                                        mem: unsafe { data.address() } + sector * 512,
-                                        read: true,
-                                        complete: Arc::new(AtomicBool::new(false)),
-                                    };
-
-                                    self.fs.disk.request(request.clone());
-
-                                    while !request.complete.load(Ordering::SeqCst) {
-                                        unsafe { context_switch(false) };
-                                    }
-                                }
+                    // Opening "file:///path?ro" gives a read-only resource
+                    let writable = url.query() != "ro";
+                    return Some(box FileResource::new(self, node, writable));
+                }
+                None => return None
+            }
+        }
+    }
 
-                                vec.push_all(& unsafe { slice::from_raw_parts(data.ptr, extent.length as usize) });
-                            }
-                        }
-                    }
+    fn create(&mut self, url: &URL) -> Option<Box<Resource>> {
+        let path = url.path();
+        if path.ends_with('/') {
+            if self.fs.mkdir(&path).is_some() {
+                return self.open(url);
+            }
+        } else if path.len() > 0 {
+            if let Some(node) = self.fs.create(&path) {
+                return Some(box FileResource::new(self, node, true));
+            }
+        }
 
-                    return Some(box FileResource {
-                        scheme: self,
-                        node: node,
-                        vec: vec,
-                        seek: 0,
-                        dirty: false,
-                    });
+        None
+    }
+
+    fn unlink(&mut self, url: &URL) -> bool {
+        self.fs.unlink(&url.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::arc::Arc;
+    use alloc::boxed::Box;
+
+    use collections::string::{String, ToString};
+    use collections::vec::Vec;
+
+    use core::cmp;
+    use core::sync::atomic::AtomicBool;
+
+    use common::time::Duration;
+
+    use drivers::disk::{Disk, Extent, Request};
+    use drivers::pciconfig::PCIConfig;
+
+    use schemes::{KScheme, Resource, ResourceSeek, URL};
+
+    use super::*;
+
+    /// Write a sector of a disk in memory
+    fn write_sector(disk: &mut Disk, block: u64, mem: usize) {
+        disk.request(Request {
+            extent: Extent {
+                block: block,
+                length: 512,
+            },
+            mem: mem,
+            read: false,
+            complete: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    /// Format a disk in memory, with empty node slots after the header
+    fn format(sectors: u64, slots: u64) -> Disk {
+        format_version(sectors, slots, VERSION)
+    }
+
+    fn format_version(sectors: u64, slots: u64, version: u32) -> Disk {
+        let mut disk = Disk::memory(sectors);
+
+        let mut header = Header {
+            signature: *b"REDOXFS\0",
+            version: version,
+            name: [0; 228],
+            free: Extent {
+                block: 0,
+                length: 0,
+            },
+            extents: [Extent {
+                block: 0,
+                length: 0,
+            }; 16],
+        };
+        header.extents[0] = Extent {
+            block: 2,
+            length: slots * 512,
+        };
+        write_sector(&mut disk, 1, &header as *const Header as usize);
+
+        disk
+    }
+
+    fn fs(disk: Disk) -> FileSystem {
+        match FileSystem::open_disk(disk) {
+            Ok(fs) => fs,
+            Err(err) => panic!("{}", err.message()),
+        }
+    }
+
+    fn mount(disk: Disk) -> Box<FileScheme> {
+        box FileScheme {
+            pci: PCIConfig::new(0, 0, 0),
+            fs: fs(disk),
+        }
+    }
+
+    /// A scheme on a disk of 1024 sectors
+    fn scheme(slots: u64) -> Box<FileScheme> {
+        mount(format(1024, slots))
+    }
+
+    /// Open the file system on the disk of a scheme again, after its resources are closed
+    fn remount(scheme: Box<FileScheme>) -> Box<FileScheme> {
+        let scheme = *scheme;
+        mount(scheme.fs.disk)
+    }
+
+    /// Check if a sector is marked as used in the bitmap
+    fn used(fs: &FileSystem, sector: u64) -> bool {
+        fs.bitmap[sector as usize / 8] & (1 << (sector % 8)) != 0
+    }
+
+    fn read_all(resource: &mut Box<Resource>) -> Vec<u8> {
+        let mut vec = Vec::new();
+        resource.read_to_end(&mut vec);
+        vec
+    }
+
+    #[test]
+    fn test_create_then_open() {
+        let mut scheme = scheme(2);
+        assert!(scheme.open(&URL::from_str("file:///test")).is_none());
+
+        {
+            let mut file = scheme.create(&URL::from_str("file:///test")).unwrap();
+            assert_eq!(file.write(b"hello"), Some(5));
+        }
+
+        let mut file = scheme.open(&URL::from_str("file:///test")).unwrap();
+        assert_eq!(read_all(&mut file), b"hello".to_vec());
+
+        // Creating an existing file fails, as does creating a file without a free slot
+        assert!(scheme.create(&URL::from_str("file:///test")).is_none());
+        assert!(scheme.create(&URL::from_str("file:///other")).is_some());
+        assert!(scheme.create(&URL::from_str("file:///full")).is_none());
+
+        drop(file);
+        let mut scheme = remount(scheme);
+        let mut file = scheme.open(&URL::from_str("file:///test")).unwrap();
+        assert_eq!(read_all(&mut file), b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_unlink() {
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///a")).unwrap();
+            file.write(&[1; 1024]);
+        }
+        {
+            let mut file = scheme.create(&URL::from_str("file:///b")).unwrap();
+            file.write(&[2; 512]);
+        }
+        {
+            // Growing the first file after the second was written needs a second extent
+            let mut file = scheme.open(&URL::from_str("file:///a")).unwrap();
+            file.seek(ResourceSeek::End(0));
+            file.write(&[3; 1024]);
+        }
+
+        let node = scheme.fs.node(&"a".to_string()).unwrap();
+        assert_eq!(node.extents.len(), 2);
+
+        assert!(scheme.unlink(&URL::from_str("file:///a")));
+        assert!(scheme.open(&URL::from_str("file:///a")).is_none());
+        assert_eq!(scheme.fs.list(&String::new()), vec!["b".to_string()]);
+        for extent in node.extents.iter() {
+            for sector in extent.block..extent.block + extent.length / 512 {
+                assert!(!used(&scheme.fs, sector));
+            }
+        }
+
+        assert!(!scheme.unlink(&URL::from_str("file:///a")));
+    }
+
+    #[test]
+    fn test_grow() {
+        let data: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///grow")).unwrap();
+            file.write(&data[.. 512]);
+            assert!(file.sync());
+            file.write(&data[512 ..]);
+            assert!(file.sync());
+        }
+
+        let mut file = scheme.open(&URL::from_str("file:///grow")).unwrap();
+        assert_eq!(read_all(&mut file), data);
+    }
+
+    #[test]
+    fn test_grow_without_space() {
+        let mut scheme = mount(format(16, 4));
+        let mut file = scheme.create(&URL::from_str("file:///grow")).unwrap();
+        file.write(&[1; 16 * 512]);
+        assert!(!file.sync());
+    }
+
+    #[test]
+    fn test_extent_tables() {
+        // One table, and a chain of two
+        for &count in [20, 50].iter() {
+            let mut scheme = scheme(4);
+            let mut node = scheme.fs.create(&"chain".to_string()).unwrap();
+            for i in 0..count {
+                node.extents.push(Extent {
+                    block: 200 + 2 * i,
+                    length: 512,
+                });
+            }
+            assert!(scheme.fs.write_node(&mut node));
+            assert_eq!(node.tables.len(), node.tables_needed(VERSION));
+
+            let scheme = remount(scheme);
+            let read = scheme.fs.node_at(node.block).unwrap();
+            assert_eq!(read.name, "chain");
+            assert_eq!(read.tables, node.tables);
+            assert_eq!(read.extents.len(), count as usize);
+            for (a, b) in read.extents.iter().zip(node.extents.iter()) {
+                assert_eq!(a.block, b.block);
+                assert_eq!(a.length, b.length);
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///a")).unwrap();
+            file.write(&[1; 1024]);
+        }
+        {
+            let mut file = scheme.create(&URL::from_str("file:///b")).unwrap();
+            file.write(&[2; 512]);
+        }
+        {
+            let mut file = scheme.open(&URL::from_str("file:///a")).unwrap();
+            file.seek(ResourceSeek::End(0));
+            file.write(&[3; 1024]);
+        }
+
+        let before = scheme.fs.node(&"a".to_string()).unwrap();
+        assert_eq!(before.extents.len(), 2);
+
+        {
+            let mut file = scheme.open(&URL::from_str("file:///a")).unwrap();
+            assert!(file.truncate(700));
+            assert!(file.sync());
+        }
+
+        let after = scheme.fs.node(&"a".to_string()).unwrap();
+        assert_eq!(after.extents.len(), 1);
+        assert_eq!(after.extents[0].length, 700);
+        let second = before.extents[1];
+        for sector in second.block..second.block + second.length / 512 {
+            assert!(!used(&scheme.fs, sector));
+        }
+
+        let mut file = scheme.open(&URL::from_str("file:///a")).unwrap();
+        assert_eq!(read_all(&mut file), vec![1; 700]);
+
+        // A read-only resource can not truncate
+        let mut file = scheme.open(&URL::from_str("file:///a?ro")).unwrap();
+        assert!(!file.truncate(0));
+    }
+
+    #[test]
+    fn test_multi_sector_read() {
+        let mut disk = format(256, 4);
+        let mut sector = [0; 512];
+        for block in 10..110 {
+            for b in sector.iter_mut() {
+                *b = block as u8;
+            }
+            write_sector(&mut disk, block, sector.as_ptr() as usize);
+        }
+
+        let extent = Extent {
+            block: 10,
+            length: 100 * 512,
+        };
+
+        let mut polled = vec![0; 100 * 512];
+        unsafe { super::read_poll(&mut disk, &extent, polled.as_mut_ptr() as usize) };
+
+        let mut fs = fs(disk);
+        let mut requested = vec![0; 100 * 512];
+        unsafe { fs.request(&extent, requested.as_mut_ptr() as usize, true) };
+
+        for i in 0..100 * 512 {
+            assert_eq!(polled[i], (10 + i / 512) as u8);
+            assert_eq!(requested[i], (10 + i / 512) as u8);
+        }
+    }
+
+    #[test]
+    fn test_alloc_free() {
+        let mut scheme = scheme(4);
+
+        let a = scheme.fs.alloc_extent(1000).unwrap();
+        assert_eq!(a.length, 1000);
+        assert!(used(&scheme.fs, a.block) && used(&scheme.fs, a.block + 1));
+
+        let b = scheme.fs.alloc_extent(512).unwrap();
+        assert!(b.block >= a.block + 2);
+        assert!(used(&scheme.fs, b.block));
+
+        scheme.fs.free_extent(a);
+        assert!(!used(&scheme.fs, a.block) && !used(&scheme.fs, a.block + 1));
+
+        let c = scheme.fs.alloc_extent(1024).unwrap();
+        assert_eq!(c.block, a.block);
+
+        // The bitmap was created on the disk by the first allocation, and kept up to date
+        let scheme = remount(scheme);
+        assert!(scheme.fs.header.free.block > 0);
+        assert!(used(&scheme.fs, scheme.fs.header.free.block));
+        assert!(used(&scheme.fs, c.block) && used(&scheme.fs, c.block + 1));
+        assert!(used(&scheme.fs, b.block));
+        assert!(!used(&scheme.fs, b.block + 1));
+    }
+
+    #[test]
+    fn test_mkdir_then_list() {
+        let mut scheme = scheme(4);
+        assert!(scheme.create(&URL::from_str("file:///dir/")).is_some());
+
+        let mut list = scheme.open(&URL::from_str("file:///dir/")).unwrap();
+        assert_eq!(read_all(&mut list), Vec::new());
+        let mut list = scheme.open(&URL::from_str("file:///")).unwrap();
+        assert_eq!(read_all(&mut list), b"dir/".to_vec());
+
+        let mut scheme = remount(scheme);
+        assert!(scheme.fs.node(&"dir/".to_string()).unwrap().is_dir());
+        let mut list = scheme.open(&URL::from_str("file:///dir/")).unwrap();
+        assert_eq!(read_all(&mut list), Vec::new());
+
+        // A directory is only removed once it is empty
+        assert!(scheme.create(&URL::from_str("file:///dir/file")).is_some());
+        assert!(!scheme.unlink(&URL::from_str("file:///dir/")));
+        assert!(scheme.unlink(&URL::from_str("file:///dir/file")));
+        assert!(scheme.unlink(&URL::from_str("file:///dir/")));
+    }
+
+    #[test]
+    fn test_old_layout() {
+        let mut disk = format_version(1024, 4, VERSION_OLD);
+
+        // A node with 16 extents, as the first version wrote them
+        let mut data = [0; 512];
+        for (i, b) in b"old/file".iter().enumerate() {
+            data[i] = *b;
+        }
+        for i in 0..16 {
+            let block = 100 + 2 * i as u64;
+            for j in 0..8 {
+                data[256 + i * 16 + j] = (block >> (8 * j)) as u8;
+                data[256 + i * 16 + 8 + j] = (512u64 >> (8 * j)) as u8;
+            }
+        }
+        write_sector(&mut disk, 2, data.as_ptr() as usize);
+
+        let mut scheme = mount(disk);
+        let node = scheme.fs.node(&"old/file".to_string()).unwrap();
+        assert_eq!(node.extents.len(), 16);
+        for i in 0..16 {
+            assert_eq!(node.extents[i].block, 100 + 2 * i as u64);
+            assert_eq!(node.extents[i].length, 512);
+        }
+        assert!(!node.is_dir());
+        assert!(node.modified() == Duration::new(0, 0));
+
+        // New nodes are written in the old layout, with directories known by their name
+        assert!(scheme.fs.mkdir(&"new".to_string()).is_some());
+        let scheme = remount(scheme);
+        assert_eq!(scheme.fs.header.version, VERSION_OLD);
+        assert!(scheme.fs.node(&"new/".to_string()).unwrap().is_dir());
+        assert_eq!(scheme.fs.node(&"old/file".to_string()).unwrap().extents.len(), 16);
+    }
+
+    #[test]
+    fn test_modified_time() {
+        let mut scheme = scheme(4);
+
+        unsafe { ::clock_realtime = Duration::new(100, 0) };
+        let mut file = scheme.create(&URL::from_str("file:///time")).unwrap();
+        let node = scheme.fs.node(&"time".to_string()).unwrap();
+        assert!(node.created == Duration::new(100, 0));
+        assert!(node.modified() == Duration::new(100, 0));
+
+        unsafe { ::clock_realtime = Duration::new(200, 0) };
+        file.write(b"hello");
+        assert!(file.sync());
+        drop(file);
+
+        let scheme = remount(scheme);
+        let node = scheme.fs.node(&"time".to_string()).unwrap();
+        assert!(node.created == Duration::new(100, 0));
+        assert!(node.modified() == Duration::new(200, 0));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///a")).unwrap();
+            file.write(b"hello");
+        }
+
+        // A resource that is open during the rename keeps writing to the renamed node
+        let mut open = scheme.open(&URL::from_str("file:///a")).unwrap();
+        assert!(scheme.rename(&"a".to_string(), &"b".to_string()));
+        open.seek(ResourceSeek::End(0));
+        open.write(b" world");
+        assert!(open.sync());
+        drop(open);
+
+        assert!(scheme.open(&URL::from_str("file:///a")).is_none());
+        let mut file = scheme.open(&URL::from_str("file:///b")).unwrap();
+        assert_eq!(read_all(&mut file), b"hello world".to_vec());
+        drop(file);
+
+        let mut scheme = remount(scheme);
+        assert!(scheme.fs.node(&"a".to_string()).is_none());
+        let mut file = scheme.open(&URL::from_str("file:///b")).unwrap();
+        assert_eq!(read_all(&mut file), b"hello world".to_vec());
+    }
+
+    #[test]
+    fn test_rename_dir() {
+        let mut scheme = scheme(4);
+        assert!(scheme.create(&URL::from_str("file:///dir/")).is_some());
+        {
+            let mut file = scheme.create(&URL::from_str("file:///dir/file")).unwrap();
+            file.write(b"hello");
+        }
+
+        assert!(!scheme.rename(&"dir/".to_string(), &"dir/inside/".to_string()));
+        assert!(scheme.rename(&"dir/".to_string(), &"moved".to_string()));
+
+        let mut scheme = remount(scheme);
+        assert!(scheme.fs.node(&"dir/".to_string()).is_none());
+        assert!(scheme.fs.node(&"dir/file".to_string()).is_none());
+        assert!(scheme.fs.node(&"moved/".to_string()).unwrap().is_dir());
+        assert_eq!(scheme.fs.list(&"moved/".to_string()), vec!["file".to_string()]);
+
+        let mut file = scheme.open(&URL::from_str("file:///moved/file")).unwrap();
+        assert_eq!(read_all(&mut file), b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_name_length() {
+        let mut longest = String::new();
+        for _ in 0..NODE_NAME_LEN {
+            longest.push('a');
+        }
+        let mut too_long = longest.clone();
+        too_long.push('a');
+
+        let mut scheme = scheme(4);
+        assert!(scheme.fs.create(&too_long).is_none());
+        assert!(scheme.fs.create(&longest).is_some());
+        assert!(!scheme.fs.rename(&longest, &too_long));
+
+        let scheme = remount(scheme);
+        assert_eq!(scheme.fs.list(&String::new()), vec![longest]);
+    }
+
+    #[test]
+    fn test_partial_sector() {
+        // Fill the free sectors with garbage, which must not be left after the end of the file
+        let mut disk = format(1024, 4);
+        let garbage = [0xFF; 512];
+        for block in 6..64 {
+            write_sector(&mut disk, block, garbage.as_ptr() as usize);
+        }
+
+        let mut scheme = mount(disk);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///partial")).unwrap();
+            file.write(&[1; 700]);
+        }
+
+        let node = scheme.fs.node(&"partial".to_string()).unwrap();
+        assert_eq!(node.extents.len(), 1);
+        assert_eq!(node.extents[0].length, 700);
+
+        let mut sectors = vec![0; 1024];
+        unsafe {
+            scheme.fs.request(&Extent {
+                                  block: node.extents[0].block,
+                                  length: 1024,
+                              },
+                              sectors.as_mut_ptr() as usize,
+                              true);
+        }
+        assert!(sectors[.. 700].iter().all(|&b| b == 1));
+        assert!(sectors[700 ..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_open_disk_errors() {
+        assert!(FileSystem::open_disk(Disk::memory(0)).err() == Some(FsError::NoDisk));
+        assert!(FileSystem::open_disk(Disk::memory(16)).err() == Some(FsError::BadSignature));
+        assert!(FileSystem::open_disk(format_version(16, 1, 0x12345678)).err() ==
+                Some(FsError::UnsupportedVersion(0x12345678)));
+        assert_eq!(FsError::BadSignature.message(), "Unknown Filesystem");
+
+        assert!(FileSystem::open_disk(format_version(16, 1, VERSION)).is_ok());
+        assert!(FileSystem::open_disk(format_version(16, 1, VERSION_OLD)).is_ok());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///ro")).unwrap();
+            file.write(b"original");
+        }
+
+        {
+            let mut file = scheme.open(&URL::from_str("file:///ro?ro")).unwrap();
+            assert_eq!(file.write(b"changed"), None);
+            assert!(!file.truncate(0));
+            assert_eq!(read_all(&mut file), b"original".to_vec());
+        }
+
+        let mut scheme = remount(scheme);
+        let mut file = scheme.open(&URL::from_str("file:///ro")).unwrap();
+        assert_eq!(read_all(&mut file), b"original".to_vec());
+        assert_eq!(file.write(b"changed"), Some(7));
+    }
+
+    #[test]
+    fn test_lazy_read() {
+        let data: Vec<u8> = (0..100000).map(|i| (i / 7) as u8).collect();
+
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///large")).unwrap();
+            file.write(&data);
+        }
+
+        let node = scheme.fs.node(&"large".to_string()).unwrap();
+        let mut file = FileResource::new(&mut *scheme, node, true);
+        assert!(!file.loaded);
+        assert!(file.pages.is_empty());
+        assert_eq!(file.size, data.len());
+
+        // Pages are read as needed, keeping only the last few
+        let mut buf = [0; 1000];
+        file.seek(ResourceSeek::Start(50000));
+        assert_eq!(file.read(&mut buf), Some(1000));
+        assert_eq!(&buf[..], &data[50000 .. 51000]);
+        let mut read = Vec::new();
+        file.seek(ResourceSeek::Start(0));
+        file.read_to_end(&mut read);
+        assert!(read == data);
+        assert!(file.cache.len() <= FILE_CACHE_PAGES);
+
+        // Writing loads the whole file first
+        file.seek(ResourceSeek::Start(99999));
+        assert_eq!(file.write(&[0xAA]), Some(1));
+        assert!(file.loaded);
+        assert!(file.cache.is_empty());
+        drop(file);
+
+        let mut file = scheme.open(&URL::from_str("file:///large")).unwrap();
+        let read = read_all(&mut file);
+        assert!(read[.. 99999] == data[.. 99999]);
+        assert_eq!(read[99999], 0xAA);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut scheme = scheme(4);
+        for name in ["a", "b", "c"].iter() {
+            let mut file = scheme.create(&URL::from_string(&("file:///".to_string() + *name))).unwrap();
+            file.write(&[1; 1024]);
+        }
+        assert!(scheme.create(&URL::from_str("file:///d")).is_some());
+        assert!(scheme.fs.check().is_empty());
+
+        let a = scheme.fs.node(&"a".to_string()).unwrap();
+        let b = scheme.fs.node(&"b".to_string()).unwrap();
+        let c = scheme.fs.node(&"c".to_string()).unwrap();
+        let d = scheme.fs.node(&"d".to_string()).unwrap();
+
+        // b moves onto the first sector of a, c takes the name of a and reaches into the node
+        // slots and past the end of the disk, and d loses its name
+        for node in scheme.fs.nodes.iter_mut() {
+            if node.block == b.block {
+                node.extents = vec![Extent {
+                    block: a.extents[0].block,
+                    length: 512,
+                }];
+            } else if node.block == c.block {
+                node.name = "a".to_string();
+                node.extents.push(Extent {
+                    block: 3,
+                    length: 512,
+                });
+                node.extents.push(Extent {
+                    block: 1020,
+                    length: 8 * 512,
+                });
+            } else if node.block == d.block {
+                node.name = String::new();
+            }
+        }
+
+        let (mut overlap, mut slots, mut bounds, mut duplicate, mut empty) = (false, false, false, false, false);
+        for problem in scheme.fs.check() {
+            match problem {
+                Problem::Overlap { node, other } => {
+                    if node == cmp::max(a.block, b.block) && other == cmp::min(a.block, b.block) {
+                        assert!(!overlap);
+                        overlap = true;
+                    } else if node == c.block && other == 0 {
+                        assert!(!slots);
+                        slots = true;
+                    } else {
+                        panic!("unexpected overlap of {} and {}", node, other);
+                    }
+                }
+                Problem::ExtentOutOfBounds { node, extent } => {
+                    assert_eq!(node, c.block);
+                    assert_eq!(extent.block, 1020);
+                    bounds = true;
+                }
+                Problem::DuplicateName { node, other } => {
+                    assert!((node, other) == (a.block, c.block) || (node, other) == (c.block, a.block));
+                    duplicate = true;
+                }
+                Problem::EmptyName { node } => {
+                    assert_eq!(node, d.block);
+                    empty = true;
                 }
-                None => return None
             }
         }
+        assert!(overlap && slots && bounds && duplicate && empty);
+    }
+
+    #[test]
+    fn test_replace_open() {
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///config")).unwrap();
+            file.write(b"old");
+        }
+        let old = scheme.fs.node(&"config".to_string()).unwrap();
+
+        // Two resources keep the old file open while a temporary file replaces it
+        let mut reader = scheme.open(&URL::from_str("file:///config")).unwrap();
+        let dup = reader.dup().unwrap();
+        {
+            let mut file = scheme.create(&URL::from_str("file:///config.tmp")).unwrap();
+            file.write(b"new");
+        }
+        assert!(scheme.rename(&"config.tmp".to_string(), &"config".to_string()));
+        assert!(scheme.fs.node(&"config.tmp".to_string()).is_none());
+        assert!(scheme.fs.is_retired(old.block));
+        assert!(!scheme.fs.free_nodes.contains(&old.block));
+        assert!(used(&scheme.fs, old.extents[0].block));
+        assert!(scheme.fs.check().is_empty());
+
+        // The old data can still be read, but changes to it are dropped
+        assert_eq!(read_all(&mut reader), b"old".to_vec());
+        reader.seek(ResourceSeek::Start(0));
+        reader.write(b"lost");
+        assert!(!reader.sync());
+
+        // The old node is freed when the last resource is closed
+        drop(reader);
+        assert!(scheme.fs.is_retired(old.block));
+        drop(dup);
+        assert!(!scheme.fs.is_retired(old.block));
+        assert!(scheme.fs.free_nodes.contains(&old.block));
+        assert!(!used(&scheme.fs, old.extents[0].block));
+
+        let mut file = scheme.open(&URL::from_str("file:///config")).unwrap();
+        assert_eq!(read_all(&mut file), b"new".to_vec());
+        drop(file);
+
+        let mut scheme = remount(scheme);
+        assert_eq!(scheme.fs.list(&String::new()), vec!["config".to_string()]);
+        let mut file = scheme.open(&URL::from_str("file:///config")).unwrap();
+        assert_eq!(read_all(&mut file), b"new".to_vec());
+    }
+
+    #[test]
+    fn test_open_block() {
+        let mut scheme = scheme(4);
+        {
+            let mut file = scheme.create(&URL::from_str("file:///named")).unwrap();
+            file.write(b"by block");
+        }
+        let block = scheme.fs.node(&"named".to_string()).unwrap().block;
+        let url = "file://#".to_string() + &block.to_string();
+
+        // The node is found by its block after it is renamed
+        assert!(scheme.rename(&"named".to_string(), &"renamed".to_string()));
+        {
+            let mut file = scheme.open(&URL::from_string(&url)).unwrap();
+            assert_eq!(read_all(&mut file), b"by block".to_vec());
+            assert_eq!(file.url().to_string(), "file:///renamed");
+            file.seek(ResourceSeek::End(0));
+            assert_eq!(file.write(b"!"), Some(1));
+        }
+
+        let mut file = scheme.open(&URL::from_str("file:///renamed")).unwrap();
+        assert_eq!(read_all(&mut file), b"by block!".to_vec());
+
+        let mut file = scheme.open(&URL::from_string(&("file://?ro#".to_string() + &block.to_string()))).unwrap();
+        assert_eq!(file.write(b"changed"), None);
+
+        // Blocks without a node, such as the header, open nothing
+        assert!(scheme.open(&URL::from_str("file://#1")).is_none());
+        assert!(scheme.open(&URL::from_str("file://#1000")).is_none());
+    }
+
+    /// Check that the index maps the name of every node to its position, and nothing else
+    fn indexed(fs: &FileSystem) -> bool {
+        fs.index.len() == fs.nodes.len() &&
+        fs.index.iter().all(|(name, &i)| i < fs.nodes.len() && fs.nodes[i].name == *name)
+    }
+
+    #[test]
+    fn test_index() {
+        let mut scheme = scheme(8);
+        assert!(scheme.fs.mkdir(&"dir".to_string()).is_some());
+        for name in ["a", "b", "c", "d", "dir/e"].iter() {
+            assert!(scheme.fs.create(&name.to_string()).is_some());
+            assert!(indexed(&scheme.fs));
+        }
+
+        // Removing a node moves the nodes after it
+        assert!(scheme.fs.unlink(&"b".to_string()));
+        assert!(indexed(&scheme.fs));
+        assert!(scheme.fs.node(&"b".to_string()).is_none());
+
+        // A plain rename, a rename replacing an earlier node, and a directory rename
+        assert!(scheme.fs.rename(&"c".to_string(), &"f".to_string()));
+        assert!(indexed(&scheme.fs));
+        assert!(scheme.fs.rename(&"d".to_string(), &"a".to_string()));
+        assert!(indexed(&scheme.fs));
+        assert!(scheme.fs.rename(&"dir/".to_string(), &"moved/".to_string()));
+        assert!(indexed(&scheme.fs));
+
+        // A node created after the removals
+        assert!(scheme.fs.create(&"g".to_string()).is_some());
+        assert!(indexed(&scheme.fs));
+
+        let names = ["a", "f", "g", "moved/", "moved/e"];
+        assert_eq!(scheme.fs.nodes.len(), names.len());
+        for name in names.iter() {
+            assert_eq!(scheme.fs.node(&name.to_string()).unwrap().name, *name);
+        }
+
+        let scheme = remount(scheme);
+        assert!(indexed(&scheme.fs));
+        for name in names.iter() {
+            assert_eq!(scheme.fs.node(&name.to_string()).unwrap().name, *name);
+        }
+    }
+
+    #[test]
+    fn test_holes() {
+        let mut scheme = mount(format(4096, 4));
+        {
+            let mut file = scheme.create(&URL::from_str("file:///sparse")).unwrap();
+            file.seek(ResourceSeek::Start(1 << 20));
+            assert_eq!(file.write(b"end"), Some(3));
+            assert!(file.sync());
+        }
+
+        // The gap is a hole, leaving only the bitmap and the last sector used after the node slots
+        let node = scheme.fs.node(&"sparse".to_string()).unwrap();
+        assert_eq!(node.extents.len(), 2);
+        assert_eq!(node.extents[0].block, 0);
+        assert_eq!(node.extents[0].length, 1 << 20);
+        assert!(node.extents[1].block > 0);
+        assert_eq!(node.extents[1].length, 3);
+        assert_eq!((6..4096).filter(|&sector| used(&scheme.fs, sector)).count(), 2);
+        assert!(scheme.fs.check().is_empty());
+
+        let mut scheme = remount(scheme);
+        let mut file = scheme.open(&URL::from_str("file:///sparse")).unwrap();
+        let mut buf = [0xFF; 5];
+        file.seek(ResourceSeek::Start(500000));
+        assert_eq!(file.read(&mut buf), Some(5));
+        assert_eq!(buf, [0; 5]);
+        file.seek(ResourceSeek::Start((1 << 20) - 2));
+        assert_eq!(file.read(&mut buf), Some(5));
+        assert_eq!(&buf, b"\0\0end");
+
+        // Writing into the hole only uses sectors for the data written
+        file.seek(ResourceSeek::Start(4096));
+        assert_eq!(file.write(&[1; 512]), Some(512));
+        assert!(file.sync());
+        drop(file);
+        let node = scheme.fs.node(&"sparse".to_string()).unwrap();
+        assert_eq!(node.extents.len(), 4);
+        assert_eq!(node.extents[0].block, 0);
+        assert_eq!(node.extents[0].length, 4096);
+        assert!(node.extents[1].block > 0);
+        assert_eq!(node.extents[1].length, 512);
+        assert_eq!(node.extents[2].block, 0);
+        assert_eq!((6..4096).filter(|&sector| used(&scheme.fs, sector)).count(), 3);
     }
 }
@@ -41,9 +41,40 @@ impl Resource for IPResource {
         return URL::from_string(&format!("ip://{}/{:X}", self.peer_addr.to_string(), self.proto));
     }
 
+    /// Read the data of a single packet if one is queued, without blocking
     fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
-        debug::d("TODO: Implement read for ip://\n");
-        return None;
+        if self.data.len() > 0 {
+            let mut bytes: Vec<u8> = Vec::new();
+            mem::swap(&mut self.data, &mut bytes);
+
+            let mut i = 0;
+            while i < buf.len() && i < bytes.len() {
+                buf[i] = bytes[i];
+                i += 1;
+            }
+            return Some(i);
+        }
+
+        loop {
+            let mut bytes = [0; 2048];
+            match self.link.read(&mut bytes) {
+                Some(0) => return Some(0),
+                Some(count) => {
+                    if let Some(packet) = IPv4::from_bytes(bytes[.. count].to_vec()) {
+                        if packet.header.proto == self.proto && packet.header.dst.equals(IP_ADDR) &&
+                           packet.header.src.equals(self.peer_addr) {
+                            let mut i = 0;
+                            while i < buf.len() && i < packet.data.len() {
+                                buf[i] = packet.data[i];
+                                i += 1;
+                            }
+                            return Some(i);
+                        }
+                    }
+                }
+                None => return None,
+            }
+        }
     }
 
     fn read_to_end(&mut self, vec: &mut Vec<u8>) -> Option<usize> {
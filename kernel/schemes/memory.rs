@@ -13,7 +13,57 @@ impl KScheme for MemoryScheme {
     }
 
     fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
-        let string = format!("Memory Used: {} KB\nMemory Free: {} KB", memory::memory_used() / 1024, memory::memory_free() / 1024);
+        let string = format!("Memory Used: {} KB\nMemory Free: {} KB\nAllocations: {}",
+                             memory::memory_used() / 1024,
+                             memory::memory_free() / 1024,
+                             memory::memory_allocations());
         Some(box VecResource::new(URL::from_str("memory://"), string.into_bytes()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use collections::string::{String, ToString};
+    use collections::vec::Vec;
+
+    use common::memory;
+    use common::to_num::ToNum;
+
+    use schemes::{KScheme, Resource, URL};
+
+    use super::*;
+
+    /// Read the fields of the memory scheme, in the order they are listed
+    fn fields() -> Vec<(String, usize)> {
+        let mut resource = MemoryScheme.open(&URL::from_str("memory://")).unwrap();
+        let mut bytes = Vec::new();
+        resource.read_to_end(&mut bytes);
+
+        String::from_utf8(bytes).unwrap().lines().map(|line| {
+            let mut parts = line.split(": ");
+            let name = parts.next().unwrap().to_string();
+            let value = parts.next().unwrap().trim_right_matches(" KB").to_string().to_num();
+            (name, value)
+        }).collect()
+    }
+
+    #[test]
+    fn test_fields() {
+        let before = fields();
+        assert_eq!(before.len(), 3);
+        assert_eq!(before[0].0, "Memory Used");
+        assert_eq!(before[1].0, "Memory Free");
+        assert_eq!(before[2].0, "Allocations");
+        assert!(before[0].1 > 0);
+        assert!(before[2].1 > 0);
+
+        // A live allocation is counted once, until it is freed
+        let ptr = unsafe { memory::alloc(4096) };
+        assert!(ptr > 0);
+        assert_eq!(memory::memory_allocations(), before[2].1 + 1);
+        assert_eq!(fields()[2].1, before[2].1 + 1);
+
+        unsafe { memory::unalloc(ptr) };
+        assert_eq!(memory::memory_allocations(), before[2].1);
+    }
+}
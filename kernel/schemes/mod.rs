@@ -22,7 +22,11 @@ pub mod file;
 pub mod ip;
 /// Memory scheme
 pub mod memory;
-/// Pseudo random generation scheme
+/// Null and zero schemes
+pub mod null;
+/// Pipe scheme
+pub mod pipe;
+/// Pseudo random generation schemes
 pub mod random;
 /// Time scheme
 pub mod time;
@@ -46,6 +50,14 @@ pub trait KScheme {
     fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
         None
     }
+
+    fn create(&mut self, url: &URL) -> Option<Box<Resource>> {
+        None
+    }
+
+    fn unlink(&mut self, url: &URL) -> bool {
+        false
+    }
 }
 
 /// Resource seek
@@ -74,6 +86,10 @@ pub trait Resource {
     fn seek(&mut self, pos: ResourceSeek) -> Option<usize>;
     /// Sync the resource
     fn sync(&mut self) -> bool;
+    /// Truncate or extend the resource to a given length
+    fn truncate(&mut self, len: usize) -> bool {
+        false
+    }
 
     //Helper functions
     fn read_to_end(&mut self, vec: &mut Vec<u8>) -> Option<usize> {
@@ -142,9 +158,37 @@ impl URL {
         }
     }
 
+    /// Get the URL without its query and fragment
+    fn base(&self) -> &str {
+        match self.string.find(|c| c == '?' || c == '#') {
+            Some(i) => &self.string[..i],
+            None => &self.string,
+        }
+    }
+
+    /// Get the query (the part after ?, without the fragment)
+    pub fn query(&self) -> String {
+        let string = match self.string.find('#') {
+            Some(i) => &self.string[..i],
+            None => &self.string,
+        };
+        match string.find('?') {
+            Some(i) => string[i + 1..].to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Get the fragment (the part after #)
+    pub fn fragment(&self) -> String {
+        match self.string.find('#') {
+            Some(i) => self.string[i + 1..].to_string(),
+            None => String::new(),
+        }
+    }
+
     /// Return the scheme of this url
     pub fn scheme(&self) -> String {
-        if let Some(part) = self.string.split('/').next() {
+        if let Some(part) = self.base().split('/').next() {
             if let Some(scheme_part) = part.split(':').next() {
                 return scheme_part.to_string();
             }
@@ -158,7 +202,7 @@ impl URL {
         let mut host = String::new();
 
         let mut part_i = 0;
-        for part in self.string.split('/') {
+        for part in self.base().split('/') {
             match part_i {
                 0 => (),
                 1 => (),
@@ -201,7 +245,7 @@ impl URL {
         let mut port = String::new();
 
         let mut part_i = 0;
-        for part in self.string.split('/') {
+        for part in self.base().split('/') {
             match part_i {
                 0 => (),
                 1 => (),
@@ -243,7 +287,7 @@ impl URL {
         let mut host = String::new();
 
         let mut part_i = 0;
-        for part in self.string.split('/') {
+        for part in self.base().split('/') {
             match part_i {
                 0 => (),
                 1 => (),
@@ -285,7 +329,7 @@ impl URL {
         let mut port = String::new();
 
         let mut part_i = 0;
-        for part in self.string.split('/') {
+        for part in self.base().split('/') {
             match part_i {
                 0 => (),
                 1 => (),
@@ -326,7 +370,7 @@ impl URL {
         let mut path = String::new();
 
         let mut part_i = 0;
-        for part in self.string.split('/') {
+        for part in self.base().split('/') {
             match part_i {
                 0 => (),
                 1 => (),
@@ -345,7 +389,7 @@ impl URL {
         let mut path_parts: Vec<String> = Vec::new();
 
         let mut part_i = 0;
-        for part in self.string.split('/') {
+        for part in self.base().split('/') {
             match part_i {
                 0 => (),
                 1 => (),
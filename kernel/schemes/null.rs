@@ -0,0 +1,111 @@
+use alloc::boxed::Box;
+
+use schemes::{KScheme, Resource, ResourceSeek, URL};
+
+/// A scheme that discards writes and reads nothing
+pub struct NullScheme;
+
+/// A scheme that discards writes and reads zeros
+pub struct ZeroScheme;
+
+/// A resource of the null or zero scheme
+pub struct NullResource {
+    /// Fill reads with zeros, instead of reading nothing
+    pub zero: bool,
+}
+
+impl Resource for NullResource {
+    fn dup(&self) -> Option<Box<Resource>> {
+        Some(box NullResource {
+            zero: self.zero,
+        })
+    }
+
+    fn url(&self) -> URL {
+        if self.zero {
+            URL::from_str("zero://")
+        } else {
+            URL::from_str("null://")
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.zero {
+            for b in buf.iter_mut() {
+                *b = 0;
+            }
+            Some(buf.len())
+        } else {
+            Some(0)
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        Some(buf.len())
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Option<usize> {
+        Some(0)
+    }
+
+    fn sync(&mut self) -> bool {
+        true
+    }
+
+    fn truncate(&mut self, len: usize) -> bool {
+        true
+    }
+}
+
+impl KScheme for NullScheme {
+    fn scheme(&self) -> &str {
+        "null"
+    }
+
+    fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
+        Some(box NullResource {
+            zero: false,
+        })
+    }
+
+    fn create(&mut self, url: &URL) -> Option<Box<Resource>> {
+        self.open(url)
+    }
+}
+
+impl KScheme for ZeroScheme {
+    fn scheme(&self) -> &str {
+        "zero"
+    }
+
+    fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
+        Some(box NullResource {
+            zero: true,
+        })
+    }
+
+    fn create(&mut self, url: &URL) -> Option<Box<Resource>> {
+        self.open(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schemes::{KScheme, Resource, URL};
+
+    use super::*;
+
+    #[test]
+    fn test_null_and_zero() {
+        let mut null = NullScheme.open(&URL::from_str("null://")).unwrap();
+        assert_eq!(null.write(b"discarded"), Some(9));
+        let mut buf = [0xFF; 8];
+        assert_eq!(null.read(&mut buf), Some(0));
+        assert_eq!(buf, [0xFF; 8]);
+
+        let mut zero = ZeroScheme.open(&URL::from_str("zero://")).unwrap();
+        assert_eq!(zero.write(b"discarded"), Some(9));
+        assert_eq!(zero.read(&mut buf), Some(8));
+        assert_eq!(buf, [0; 8]);
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::boxed::Box;
+
+use collections::string::{String, ToString};
+use collections::vec::Vec;
+use collections::vec_deque::VecDeque;
+
+use common::context::context_switch;
+
+use schemes::{KScheme, Resource, ResourceSeek, URL};
+
+/// The number of bytes a pipe holds before writers wait for a reader
+pub const PIPE_SIZE: usize = 4096;
+
+/// A named pipe
+pub struct Pipe {
+    /// The name of the pipe
+    pub name: String,
+    /// The bytes written and not yet read
+    pub buffer: VecDeque<u8>,
+    /// The number of open read ends
+    pub readers: usize,
+    /// The number of open write ends
+    pub writers: usize,
+    /// Set when the last write end is closed, so reads return the end of the stream
+    pub closed: bool,
+    /// Set when the last read end is closed, so writes fail instead of waiting
+    pub broken: bool,
+}
+
+/// A pipe scheme, where `pipe:///name` opens the read end of a pipe and `pipe:///name?w` its write end
+pub struct PipeScheme {
+    pub pipes: Vec<Pipe>,
+}
+
+impl PipeScheme {
+    /// Get the pipe with a given name
+    fn pipe(&mut self, name: &str) -> Option<&mut Pipe> {
+        self.pipes.iter_mut().find(|pipe| pipe.name == name)
+    }
+}
+
+/// An end of a pipe
+pub struct PipeResource {
+    pub scheme: *mut PipeScheme,
+    pub name: String,
+    /// True for the write end, false for the read end
+    pub write: bool,
+}
+
+impl PipeResource {
+    /// Open an end of a pipe, creating the pipe if needed
+    fn new(scheme: *mut PipeScheme, name: String, write: bool) -> PipeResource {
+        let scheme_ref = unsafe { &mut *scheme };
+        let exists = scheme_ref.pipe(&name).is_some();
+        if ! exists {
+            scheme_ref.pipes.push(Pipe {
+                name: name.clone(),
+                buffer: VecDeque::new(),
+                readers: 0,
+                writers: 0,
+                closed: false,
+                broken: false,
+            });
+        }
+
+        if let Some(pipe) = scheme_ref.pipe(&name) {
+            if write {
+                pipe.writers += 1;
+                pipe.closed = false;
+            } else {
+                pipe.readers += 1;
+                pipe.broken = false;
+            }
+        }
+
+        PipeResource {
+            scheme: scheme,
+            name: name,
+            write: write,
+        }
+    }
+
+    /// Get the pipe of this end, which lives as long as one of its ends is open
+    fn pipe(&mut self) -> &mut Pipe {
+        unsafe { (*self.scheme).pipe(&self.name).unwrap() }
+    }
+}
+
+impl Resource for PipeResource {
+    fn dup(&self) -> Option<Box<Resource>> {
+        Some(box PipeResource::new(self.scheme, self.name.clone(), self.write))
+    }
+
+    fn url(&self) -> URL {
+        if self.write {
+            URL::from_string(&("pipe:///".to_string() + &self.name + "?w"))
+        } else {
+            URL::from_string(&("pipe:///".to_string() + &self.name))
+        }
+    }
+
+    /// Read from the read end, waiting for data until the write ends are closed
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.write {
+            return None;
+        }
+
+        loop {
+            {
+                let pipe = self.pipe();
+                if pipe.buffer.len() > 0 || buf.len() == 0 {
+                    let mut i = 0;
+                    while i < buf.len() {
+                        match pipe.buffer.pop_front() {
+                            Some(b) => buf[i] = b,
+                            None => break,
+                        }
+                        i += 1;
+                    }
+                    return Some(i);
+                }
+
+                if pipe.closed {
+                    return Some(0);
+                }
+            }
+
+            unsafe { context_switch(false) };
+        }
+    }
+
+    /// Write to the write end, waiting for a read end to be opened and for room in the pipe
+    ///
+    /// Fails if the read ends were all closed before anything was written.
+    fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        if ! self.write {
+            return None;
+        }
+
+        let mut i = 0;
+        while i < buf.len() {
+            {
+                let pipe = self.pipe();
+                if pipe.broken {
+                    break;
+                }
+
+                while pipe.readers > 0 && i < buf.len() && pipe.buffer.len() < PIPE_SIZE {
+                    pipe.buffer.push_back(buf[i]);
+                    i += 1;
+                }
+            }
+
+            if i < buf.len() {
+                unsafe { context_switch(false) };
+            }
+        }
+
+        if i > 0 || buf.len() == 0 {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Option<usize> {
+        None
+    }
+
+    fn sync(&mut self) -> bool {
+        true
+    }
+}
+
+impl Drop for PipeResource {
+    fn drop(&mut self) {
+        let unused = {
+            let write = self.write;
+            let pipe = self.pipe();
+            if write {
+                pipe.writers -= 1;
+                if pipe.writers == 0 {
+                    pipe.closed = true;
+                }
+            } else {
+                pipe.readers -= 1;
+                if pipe.readers == 0 {
+                    pipe.broken = true;
+                }
+            }
+            pipe.readers == 0 && pipe.writers == 0
+        };
+
+        if unused {
+            let scheme = unsafe { &mut *self.scheme };
+            scheme.pipes.retain(|pipe| pipe.name != self.name);
+        }
+    }
+}
+
+impl KScheme for PipeScheme {
+    fn scheme(&self) -> &str {
+        "pipe"
+    }
+
+    fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
+        let name = url.path();
+        if name.len() == 0 {
+            return None;
+        }
+
+        let write = url.query() == "w";
+        Some(box PipeResource::new(self, name, write))
+    }
+
+    fn create(&mut self, url: &URL) -> Option<Box<Resource>> {
+        self.open(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use collections::string::ToString;
+    use collections::vec::Vec;
+
+    use schemes::Resource;
+
+    use super::*;
+
+    #[test]
+    fn test_write_then_read() {
+        let mut scheme = PipeScheme { pipes: Vec::new() };
+        let mut reader = PipeResource::new(&mut scheme, "test".to_string(), false);
+        let mut writer = PipeResource::new(&mut scheme, "test".to_string(), true);
+
+        assert_eq!(writer.write(b"hello"), Some(5));
+        assert_eq!(reader.read(&mut []), Some(0));
+
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf), Some(5));
+        assert_eq!(&buf[.. 5], b"hello");
+
+        // Closing the write end ends the stream
+        drop(writer);
+        assert_eq!(reader.read(&mut buf), Some(0));
+
+        // Writes fail once the read end is closed
+        let mut writer = PipeResource::new(&mut scheme, "test".to_string(), true);
+        drop(reader);
+        assert_eq!(writer.write(b"hello"), None);
+    }
+}
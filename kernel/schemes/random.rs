@@ -2,11 +2,48 @@ use alloc::boxed::Box;
 
 use common::random;
 
-use schemes::{KScheme, Resource, URL, VecResource};
+use schemes::{KScheme, Resource, ResourceSeek, URL, VecResource};
 
 /// A pseudorandomness scheme
 pub struct RandomScheme;
 
+/// A scheme reading an endless stream of pseudorandom bytes
+pub struct UrandomScheme;
+
+/// A resource of the urandom scheme
+pub struct UrandomResource;
+
+impl Resource for UrandomResource {
+    fn dup(&self) -> Option<Box<Resource>> {
+        Some(box UrandomResource)
+    }
+
+    fn url(&self) -> URL {
+        URL::from_str("urandom://")
+    }
+
+    /// Fill the buffer with bytes from the generator behind `rand`, so each read gives new bytes
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        // The low bits of the generator repeat quickly, use one of the higher bytes
+        for b in buf.iter_mut() {
+            *b = (random::rand() >> 16) as u8;
+        }
+        Some(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn seek(&mut self, pos: ResourceSeek) -> Option<usize> {
+        None
+    }
+
+    fn sync(&mut self) -> bool {
+        true
+    }
+}
+
 impl KScheme for RandomScheme {
     fn scheme(&self) -> &str {
         "random"
@@ -16,3 +53,36 @@ impl KScheme for RandomScheme {
         Some(box VecResource::new(URL::from_str("random://"), format!("{}", random::rand()).into_bytes()))
     }
 }
+
+impl KScheme for UrandomScheme {
+    fn scheme(&self) -> &str {
+        "urandom"
+    }
+
+    fn open(&mut self, url: &URL) -> Option<Box<Resource>> {
+        Some(box UrandomResource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schemes::{KScheme, Resource, URL};
+
+    use super::*;
+
+    #[test]
+    fn test_urandom() {
+        let mut resource = UrandomScheme.open(&URL::from_str("urandom://")).unwrap();
+
+        let mut first = [0; 64];
+        let mut second = [0; 64];
+        assert_eq!(resource.read(&mut first), Some(64));
+        assert_eq!(resource.read(&mut second[.. 3]), Some(3));
+        assert_eq!(resource.read(&mut second[3 ..]), Some(61));
+
+        // Neither read is a run of one byte, and the second does not repeat the first
+        assert!(first.iter().any(|&b| b != first[0]));
+        assert!(second.iter().any(|&b| b != second[0]));
+        assert!(&first[..] != &second[..]);
+    }
+}
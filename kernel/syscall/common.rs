@@ -3,6 +3,9 @@ pub const SYS_DEBUG: usize = 0;
 //Linux compatible
 pub const SYS_BRK: usize = 45;
 pub const SYS_CHDIR: usize = 12;
+pub const SYS_CLOCK_GETTIME: usize = 265;
+    pub const CLOCK_REALTIME: usize = 0;
+    pub const CLOCK_MONOTONIC: usize = 1;
 pub const SYS_CLOSE: usize = 6;
 pub const SYS_DUP: usize = 41;
 pub const SYS_EXECVE: usize = 11;
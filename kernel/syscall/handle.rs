@@ -118,6 +118,32 @@ pub unsafe fn do_sys_brk(addr: usize) -> usize {
 
 //TODO: chdir
 
+pub unsafe fn do_sys_clock_gettime(clock: usize, tv: *mut TV) -> usize {
+    let mut ret = usize::MAX;
+
+    let reenable = scheduler::start_no_ints();
+
+    if tv as usize > 0 {
+        match clock {
+            CLOCK_REALTIME => {
+                (*tv).tv_sec = ::clock_realtime.secs;
+                (*tv).tv_usec = ::clock_realtime.nanos/1000;
+                ret = 0;
+            },
+            CLOCK_MONOTONIC => {
+                (*tv).tv_sec = ::clock_monotonic.secs;
+                (*tv).tv_usec = ::clock_monotonic.nanos/1000;
+                ret = 0;
+            },
+            _ => (),
+        }
+    }
+
+    scheduler::end_no_ints(reenable);
+
+    ret
+}
+
 pub unsafe fn do_sys_close(fd: usize) -> usize {
     let mut ret = usize::MAX;
 
@@ -326,6 +352,35 @@ pub unsafe fn do_sys_fsync(fd: usize) -> usize {
     ret
 }
 
+pub unsafe fn do_sys_ftruncate(fd: usize, len: usize) -> usize {
+    let mut ret = usize::MAX;
+
+    let reenable = scheduler::start_no_ints();
+
+    let contexts = &mut *contexts_ptr;
+    if let Some(mut current) = contexts.get_mut(context_i) {
+        for i in 0..current.files.len() {
+            if let Some(mut file) = current.files.get_mut(i) {
+                if file.fd == fd {
+                    scheduler::end_no_ints(reenable);
+
+                    if file.resource.truncate(len) {
+                        ret = 0;
+                    }
+
+                    scheduler::start_no_ints();
+
+                    break;
+                }
+            }
+        }
+    }
+
+    scheduler::end_no_ints(reenable);
+
+    ret
+}
+
 #[repr(packed)]
 pub struct TV {
     pub tv_sec: i64,
@@ -384,7 +439,8 @@ pub unsafe fn do_sys_lseek(fd: usize, offset: isize, whence: usize) -> usize {
     ret
 }
 
-pub unsafe fn do_sys_open(path: *const u8, flags: isize, mode: isize) -> usize {
+/// Read a null terminated path, and make it absolute using the current working directory
+unsafe fn resolve_path(path: *const u8) -> String {
     let mut len = 0;
     while *path.offset(len as isize) > 0 {
         len += 1;
@@ -410,9 +466,25 @@ pub unsafe fn do_sys_open(path: *const u8, flags: isize, mode: isize) -> usize {
         scheduler::end_no_ints(reenable);
     }
 
+    path_str
+}
+
+pub unsafe fn do_sys_open(path: *const u8, flags: isize, mode: isize) -> usize {
+    let path_str = resolve_path(path);
+
     let mut fd = usize::MAX;
 
-    if let Some(resource) = (*::session_ptr).open(&URL::from_string(&path_str)) {
+    let url = URL::from_string(&path_str);
+    let mut resource_option = (*::session_ptr).open(&url);
+    if resource_option.is_none() && flags as usize & O_CREAT == O_CREAT {
+        resource_option = (*::session_ptr).create(&url);
+    }
+
+    if let Some(mut resource) = resource_option {
+        if flags as usize & O_TRUNC == O_TRUNC {
+            resource.truncate(0);
+        }
+
         let reenable = scheduler::start_no_ints();
 
         let contexts = &mut *contexts_ptr;
@@ -463,7 +535,15 @@ pub unsafe fn do_sys_read(fd: usize, buf: *mut u8, count: usize) -> usize {
     ret
 }
 
-//TODO: unlink
+pub unsafe fn do_sys_unlink(path: *const u8) -> usize {
+    let path_str = resolve_path(path);
+
+    if (*::session_ptr).unlink(&URL::from_string(&path_str)) {
+        0
+    } else {
+        usize::MAX
+    }
+}
 
 pub unsafe fn do_sys_write(fd: usize, buf: *const u8, count: usize) -> usize {
     let mut ret = usize::MAX;
@@ -518,6 +598,7 @@ pub unsafe fn syscall_handle(mut eax: usize, ebx: usize, ecx: usize, edx: usize)
         // Linux
         SYS_BRK => eax = do_sys_brk(ebx),
         //TODO: chdir
+        SYS_CLOCK_GETTIME => eax = do_sys_clock_gettime(ebx, ecx as *mut TV),
         SYS_CLOSE => eax = do_sys_close(ebx as usize),
         SYS_DUP => eax = do_sys_dup(ebx),
         SYS_EXECVE => eax = do_sys_execve(ebx as *const u8),
@@ -526,12 +607,13 @@ pub unsafe fn syscall_handle(mut eax: usize, ebx: usize, ecx: usize, edx: usize)
         SYS_FPATH => eax = do_sys_fpath(ebx, ecx as *mut u8, edx),
         //TODO: fstat
         SYS_FSYNC => eax = do_sys_fsync(ebx),
+        SYS_FTRUNCATE => eax = do_sys_ftruncate(ebx, ecx),
         SYS_GETTIMEOFDAY => eax = do_sys_gettimeofday(ebx as *mut TV),
         //TODO: link
         SYS_LSEEK => eax = do_sys_lseek(ebx, ecx as isize, edx as usize),
         SYS_OPEN => eax = do_sys_open(ebx as *const u8, ecx as isize, edx as isize),
         SYS_READ => eax = do_sys_read(ebx, ecx as *mut u8, edx),
-        //TODO: unlink
+        SYS_UNLINK => eax = do_sys_unlink(ebx as *const u8),
         SYS_WRITE => eax = do_sys_write(ebx, ecx as *mut u8, edx),
         SYS_YIELD => do_sys_yield(),
 
@@ -4,7 +4,7 @@ use io::{Read, Write, Seek, SeekFrom};
 use string::{String, ToString};
 use vec::Vec;
 
-use syscall::{sys_open, sys_dup, sys_close, sys_execve, sys_fpath, sys_ftruncate, sys_read, sys_write, sys_lseek, sys_fsync};
+use syscall::{sys_open, sys_dup, sys_close, sys_execve, sys_fpath, sys_ftruncate, sys_read, sys_write, sys_lseek, sys_fsync, sys_unlink};
 use syscall::common::{O_RDONLY, O_WRONLY, O_CREAT, O_TRUNC};
 
 /// A Unix-style file
@@ -137,3 +137,8 @@ impl Drop for File {
         }
     }
 }
+
+/// Remove a file
+pub fn remove_file(path: &str) -> bool {
+    unsafe { sys_unlink((path.to_string() + "\0").as_ptr()) == 0 }
+}
@@ -0,0 +1,268 @@
+//! A resolver of host names to IPv4 addresses, sending DNS queries over UDP
+
+use fs::File;
+use io::{Read, Write};
+use rand_old::rand;
+use string::{String, ToString};
+use syscall::sys_yield;
+use time::{Duration, Instant};
+use vec::Vec;
+
+use super::IPv4Addr;
+
+/// The file naming the name server queried by `resolve`, by its address on the first line
+pub const DNS_CONFIG: &'static str = "file:///etc/net/dns";
+
+/// The name server queried when `DNS_CONFIG` is missing or holds no address
+pub static DNS_ADDR: IPv4Addr = IPv4Addr { bytes: [10, 85, 85, 1] };
+
+/// The port of a name server
+pub const DNS_PORT: u16 = 53;
+/// The number of seconds to wait for an answer
+pub const DNS_TIMEOUT: i64 = 5;
+
+/// The type and class of an IPv4 address record
+pub const DNS_TYPE_A: u16 = 1;
+pub const DNS_CLASS_IN: u16 = 1;
+
+/// Read a big endian word
+fn word(bytes: &[u8], pos: usize) -> Option<u16> {
+    if pos + 2 <= bytes.len() {
+        Some(((bytes[pos] as u16) << 8) | bytes[pos + 1] as u16)
+    } else {
+        None
+    }
+}
+
+/// Append a big endian word
+fn push_word(bytes: &mut Vec<u8>, word: u16) {
+    bytes.push((word >> 8) as u8);
+    bytes.push(word as u8);
+}
+
+/// Get the position after a name, which ends with an empty label or a pointer to another name
+fn skip_name(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = match bytes.get(pos) {
+            Some(&len) => len as usize,
+            None => return None,
+        };
+
+        if len == 0 {
+            return Some(pos + 1);
+        } else if len & 0xC0 == 0xC0 {
+            return Some(pos + 2);
+        } else {
+            pos += 1 + len;
+        }
+    }
+}
+
+/// Build a recursive query for the address records of a name
+///
+/// Returns `None` if the name has an empty label, or one longer than 63 bytes.
+pub fn query(id: u16, name: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    push_word(&mut bytes, id);
+    push_word(&mut bytes, 0x0100);
+    push_word(&mut bytes, 1);
+    push_word(&mut bytes, 0);
+    push_word(&mut bytes, 0);
+    push_word(&mut bytes, 0);
+
+    for label in name.trim_right_matches('.').split('.') {
+        if label.len() == 0 || label.len() > 63 {
+            return None;
+        }
+        bytes.push(label.len() as u8);
+        bytes.push_all(label.as_bytes());
+    }
+    bytes.push(0);
+
+    push_word(&mut bytes, DNS_TYPE_A);
+    push_word(&mut bytes, DNS_CLASS_IN);
+
+    Some(bytes)
+}
+
+/// Get the first address record of the response to a query
+///
+/// Returns `None` if the response is for another query, reports an error, or has no address.
+pub fn parse_response(id: u16, bytes: &[u8]) -> Option<IPv4Addr> {
+    let (flags, questions, answers) = match (word(bytes, 0), word(bytes, 2), word(bytes, 4), word(bytes, 6)) {
+        (Some(response_id), Some(flags), Some(questions), Some(answers)) if response_id == id => (flags, questions, answers),
+        _ => return None,
+    };
+
+    // Only take responses without an error code
+    if flags & 0x8000 == 0 || flags & 0xF != 0 {
+        return None;
+    }
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = match skip_name(bytes, pos) {
+            Some(end) => end + 4,
+            None => return None,
+        };
+    }
+
+    for _ in 0..answers {
+        pos = match skip_name(bytes, pos) {
+            Some(end) => end,
+            None => return None,
+        };
+
+        let (_type, class, len) = match (word(bytes, pos), word(bytes, pos + 2), word(bytes, pos + 8)) {
+            (Some(_type), Some(class), Some(len)) => (_type, class, len as usize),
+            _ => return None,
+        };
+        pos += 10;
+
+        if pos + len > bytes.len() {
+            return None;
+        }
+
+        if _type == DNS_TYPE_A && class == DNS_CLASS_IN && len == 4 {
+            return Some(IPv4Addr { bytes: [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]] });
+        }
+        pos += len;
+    }
+
+    None
+}
+
+/// Parse the address of a name server from the first line of a config
+pub fn parse_config(config: &str) -> Option<IPv4Addr> {
+    match config.lines().next() {
+        Some(line) => IPv4Addr::parse(line.trim()),
+        None => None,
+    }
+}
+
+/// Get the name server from `DNS_CONFIG`, or `DNS_ADDR` if it can not be read
+pub fn server() -> IPv4Addr {
+    if let Some(mut file) = File::open(DNS_CONFIG) {
+        let mut config = String::new();
+        if file.read_to_string(&mut config).is_some() {
+            if let Some(addr) = parse_config(&config) {
+                return addr;
+            }
+        }
+    }
+
+    DNS_ADDR
+}
+
+/// Resolve a host name using the name server from `server`, returning an address as it is
+pub fn resolve(name: &str) -> Option<IPv4Addr> {
+    resolve_with(&server(), name, Duration::new(DNS_TIMEOUT, 0))
+}
+
+/// Resolve a host name using a name server, waiting for the answer until the timeout passes
+pub fn resolve_with(server: &IPv4Addr, name: &str, timeout: Duration) -> Option<IPv4Addr> {
+    if let Some(addr) = IPv4Addr::parse(name) {
+        return Some(addr);
+    }
+
+    let id = rand() as u16;
+    let query = match query(id, name) {
+        Some(query) => query,
+        None => return None,
+    };
+
+    let mut udp = match File::open(&("udp://".to_string() + &server.to_string() + ":" + &DNS_PORT.to_string())) {
+        Some(udp) => udp,
+        None => return None,
+    };
+
+    if udp.write(&query).is_none() {
+        return None;
+    }
+
+    // Answers to other queries are skipped
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        let mut bytes = [0; 4096];
+        match udp.read(&mut bytes) {
+            Some(0) => unsafe { sys_yield() },
+            Some(count) => {
+                if let Some(addr) = parse_response(id, &bytes[.. count]) {
+                    return Some(addr);
+                }
+            }
+            None => return None,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use string::String;
+    use vec::Vec;
+    use super::*;
+
+    /// A response to `query(0x1234, "example.com")`, with a CNAME and then an address record
+    fn response() -> Vec<u8> {
+        let mut bytes = query(0x1234, "example.com").unwrap();
+        bytes[2] = 0x81;
+        bytes[3] = 0x80;
+        bytes[7] = 2;
+        bytes.push_all(&[0xC0, 0x0C, 0, 5, 0, 1, 0, 0, 0x0E, 0x10, 0, 2, 0xC0, 0x0C]);
+        bytes.push_all(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 93, 184, 216, 34]);
+        bytes
+    }
+
+    #[test]
+    fn test_query() {
+        let bytes = query(0x1234, "example.com").unwrap();
+        assert_eq!(bytes,
+                   vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0,
+                        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+                        0, 1, 0, 1]);
+        assert_eq!(query(0x1234, "example.com.").unwrap(), bytes);
+
+        assert!(query(0x1234, "example..com").is_none());
+        assert!(query(0x1234, "").is_none());
+        let long: Vec<u8> = (0..64).map(|_| b'a').collect();
+        assert!(query(0x1234, &String::from_utf8(long).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let bytes = response();
+        assert_eq!(parse_response(0x1234, &bytes).map(|addr| addr.bytes), Some([93, 184, 216, 34]));
+
+        // For another query
+        assert!(parse_response(0x4321, &bytes).is_none());
+
+        // An error code
+        let mut error = bytes.clone();
+        error[3] |= 3;
+        assert!(parse_response(0x1234, &error).is_none());
+
+        // Not a response
+        let mut request = bytes.clone();
+        request[2] &= 0x7F;
+        assert!(parse_response(0x1234, &request).is_none());
+
+        // Truncated in the address
+        assert!(parse_response(0x1234, &bytes[.. bytes.len() - 1]).is_none());
+
+        // Only the CNAME
+        let mut cname = bytes[.. bytes.len() - 16].to_vec();
+        cname[7] = 1;
+        assert!(parse_response(0x1234, &cname).is_none());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(parse_config("10.0.2.3").map(|addr| addr.bytes), Some([10, 0, 2, 3]));
+        assert_eq!(parse_config("  8.8.8.8 \n8.8.4.4\n").map(|addr| addr.bytes), Some([8, 8, 8, 8]));
+        assert!(parse_config("").is_none());
+        assert!(parse_config("\n10.0.2.3").is_none());
+        assert!(parse_config("dns.example.com").is_none());
+    }
+}
@@ -4,6 +4,9 @@ use string::{String, ToString};
 use to_num::ToNum;
 use vec::Vec;
 
+/// A resolver of names to addresses
+pub mod dns;
+
 pub trait FromBytes {
     fn from_bytes(bytes: Vec<u8>) -> Option<Self> where Self: Sized;
 }
@@ -136,6 +139,31 @@ impl IPv4Addr {
         addr
     }
 
+    pub fn parse(string: &str) -> Option<Self> {
+        let mut addr = IPv4Addr { bytes: [0, 0, 0, 0] };
+
+        let mut i = 0;
+        for part in string.split('.') {
+            if i >= 4 || part.len() == 0 || part.len() > 3 || ! part.chars().all(|c| c >= '0' && c <= '9') {
+                return None;
+            }
+
+            let octet = part.to_string().to_num();
+            if octet > 255 {
+                return None;
+            }
+
+            addr.bytes[i] = octet as u8;
+            i += 1;
+        }
+
+        if i == 4 {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
     pub fn to_string(&self) -> String {
         format!("{}.{}.{}.{}", self.bytes[0], self.bytes[1], self.bytes[2], self.bytes[3])
     }
@@ -206,4 +234,78 @@ impl Checksum {
 
         0xFFFF - (sum as u16)
     }
+
+    /// Update a checksum for one changed 16-bit word, without summing the rest again (RFC 1624)
+    pub fn update(old: u16, removed: u16, added: u16) -> u16 {
+        let mut sum = (!old) as usize + (!removed) as usize + added as usize;
+        while (sum >> 16) > 0 {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_parse() {
+        assert_eq!(IPv4Addr::parse("10.85.85.2").map(|addr| addr.bytes), Some([10, 85, 85, 2]));
+        assert_eq!(IPv4Addr::parse("0.0.0.0").map(|addr| addr.bytes), Some([0, 0, 0, 0]));
+        assert_eq!(IPv4Addr::parse("255.255.255.255").map(|addr| addr.bytes), Some([255, 255, 255, 255]));
+        assert_eq!(IPv4Addr::parse("010.1.1.1").map(|addr| addr.bytes), Some([10, 1, 1, 1]));
+    }
+
+    #[test]
+    fn test_ipv4_parse_out_of_range() {
+        assert!(IPv4Addr::parse("256.1.1.1").is_none());
+        assert!(IPv4Addr::parse("1.1.1.300").is_none());
+        assert!(IPv4Addr::parse("1.1.1.1000").is_none());
+    }
+
+    #[test]
+    fn test_ipv4_parse_malformed() {
+        assert!(IPv4Addr::parse("").is_none());
+        assert!(IPv4Addr::parse("1.1.1").is_none());
+        assert!(IPv4Addr::parse("1.1.1.1.1").is_none());
+        assert!(IPv4Addr::parse("1..1.1").is_none());
+        assert!(IPv4Addr::parse("1.1.1.").is_none());
+        assert!(IPv4Addr::parse("1.1.1.-1").is_none());
+        assert!(IPv4Addr::parse("1.1.1.a").is_none());
+        assert!(IPv4Addr::parse(" 1.1.1.1").is_none());
+        assert!(IPv4Addr::parse("example.com").is_none());
+    }
+
+    /// Compute the checksum of words from scratch
+    fn full(words: &[u16]) -> u16 {
+        unsafe { Checksum::compile(Checksum::sum(words.as_ptr() as usize, words.len() * 2)) }
+    }
+
+    #[test]
+    fn test_checksum_update() {
+        let mut words: [u16; 10] = [0x4500, 0x0073, 0x0000, 0x4000, 0x4011, 0x0000, 0xC0A8, 0x0001, 0xC0A8, 0x00C7];
+        let changes: [(usize, u16); 6] = [(2, 0x1234), (5, 0xFFFF), (5, 0x0000), (9, 0xABCD), (0, 0x0001), (9, 0x00C7)];
+
+        for &(i, added) in changes.iter() {
+            let old = full(&words);
+            let removed = words[i];
+            words[i] = added;
+            assert_eq!(Checksum::update(old, removed, added), full(&words));
+        }
+    }
+
+    #[test]
+    fn test_checksum_update_many() {
+        let mut words: [u16; 4] = [0x0102, 0x0304, 0x0506, 0x0708];
+        let mut checksum = full(&words);
+        for n in 0..1000u16 {
+            let i = (n % 4) as usize;
+            let added = n.wrapping_mul(0x9E37);
+            checksum = Checksum::update(checksum, words[i], added);
+            words[i] = added;
+        }
+        assert_eq!(checksum, full(&words));
+    }
 }
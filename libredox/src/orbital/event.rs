@@ -90,6 +90,8 @@ pub const K_ESC: u8 = 0x01;
 pub const K_BKSP: u8 = 0x0E;
 /// Tab key
 pub const K_TAB: u8 = 0x0F;
+/// Enter key
+pub const K_ENTER: u8 = 0x1C;
 /// Control key
 pub const K_CTRL: u8 = 0x1D;
 /// Alt key
@@ -1,6 +1,6 @@
 use alloc::boxed::Box;
 
-use core::mem;
+use core::{cmp, mem, ptr};
 use core::ops::DerefMut;
 use core::slice;
 
@@ -175,6 +175,34 @@ impl Window {
         return self.file.sync();
     }
 
+    /// Move rows of pixels within the window, such as when scrolling
+    pub fn copy_rows(&mut self, from_y: usize, to_y: usize, h: usize) {
+        let w = self.w;
+        let h = cmp::min(h, self.h - cmp::min(cmp::max(from_y, to_y), self.h));
+        if h > 0 {
+            unsafe {
+                ptr::copy(self.data.as_ptr().offset((from_y * w) as isize),
+                          self.data.as_mut_ptr().offset((to_y * w) as isize),
+                          h * w);
+            }
+        }
+    }
+
+    /// Flip only some rows of the window buffer, given as pairs of the first row and the height
+    pub fn sync_rows(&mut self, rows: &[(usize, usize)]) -> bool {
+        for &(y, h) in rows.iter() {
+            let y = cmp::min(y, self.h);
+            let h = cmp::min(h, self.h - y);
+            self.file.seek(SeekFrom::Start(y * self.w * 4));
+            let to_write: &[u8] = unsafe {
+                slice::from_raw_parts(self.data.as_ptr().offset((y * self.w) as isize) as *const u8,
+                                      h * self.w * 4)
+            };
+            self.file.write(to_write);
+        }
+        return self.file.sync();
+    }
+
     /// Return a iterator over events
     pub fn event_iter<'a>(&'a mut self) -> EventIter<'a> {
         EventIter {
@@ -41,6 +41,10 @@ pub unsafe fn sys_chdir(path: *const u8) -> usize {
     syscall(SYS_CHDIR, path as usize, 0, 0)
 }
 
+pub unsafe fn sys_clock_gettime(clock: usize, tv: *mut TV) -> usize {
+    syscall(SYS_CLOCK_GETTIME, clock, tv as usize, 0)
+}
+
 pub unsafe fn sys_close(fd: usize) -> usize {
     syscall(SYS_CLOSE, fd, 0, 0)
 }
@@ -1,9 +1,12 @@
 //! A module for time
 
 use core::cmp::{Ordering, PartialEq};
-use core::ops::{Add, Sub};
+use core::hash::{Hash, Hasher};
+use core::i64;
+use core::ops::{Add, Div, Mul, Sub};
 
-use syscall::{TV, sys_gettimeofday, sys_yield};
+use syscall::{TV, sys_clock_gettime, sys_gettimeofday, sys_yield};
+use syscall::common::CLOCK_MONOTONIC;
 
 pub const NANOS_PER_MICRO: i32 = 1_000;
 pub const NANOS_PER_MILLI: i32 = 1_000_000;
@@ -34,6 +37,132 @@ impl Duration {
         }
     }
 
+    /// Create a duration of zero length
+    pub fn zero() -> Self {
+        Duration {
+            secs: 0,
+            nanos: 0,
+        }
+    }
+
+    /// Check if the duration has zero length, even if it was not normalized
+    pub fn is_zero(&self) -> bool {
+        let normal = Duration::new(self.secs, self.nanos);
+        normal.secs == 0 && normal.nanos == 0
+    }
+
+    /// Create a duration from a number of seconds
+    pub fn from_secs(secs: i64) -> Self {
+        Duration::new(secs, 0)
+    }
+
+    /// Create a duration from a number of milliseconds
+    pub fn from_millis(millis: i64) -> Self {
+        Duration::new(millis / 1000, (millis % 1000) as i32 * NANOS_PER_MILLI)
+    }
+
+    /// Create a duration from a total number of nanoseconds
+    pub fn from_total_nanos(nanos: i64) -> Self {
+        Duration::new(nanos / NANOS_PER_SEC as i64, (nanos % NANOS_PER_SEC as i64) as i32)
+    }
+
+    /// Get the total number of nanoseconds, saturating if it does not fit
+    pub fn total_nanos(&self) -> i64 {
+        let normal = Duration::new(self.secs, self.nanos);
+        match normal.secs.checked_mul(NANOS_PER_SEC as i64) {
+            Some(nanos) => match nanos.checked_add(normal.nanos as i64) {
+                Some(nanos) => nanos,
+                None => if normal.secs < 0 { i64::MIN } else { i64::MAX },
+            },
+            None => if normal.secs < 0 { i64::MIN } else { i64::MAX },
+        }
+    }
+
+    /// Create a new duration, returning `None` if normalizing overflows the seconds
+    ///
+    /// The nanoseconds are given as an `i64`, so that sums of two `i32` nanosecond fields
+    /// cannot overflow before they are normalized.
+    fn checked_new(secs: i64, nanos: i64) -> Option<Self> {
+        let mut secs = match secs.checked_add(nanos / NANOS_PER_SEC as i64) {
+            Some(secs) => secs,
+            None => return None,
+        };
+        let mut nanos = (nanos % NANOS_PER_SEC as i64) as i32;
+
+        while nanos >= NANOS_PER_SEC || (nanos > 0 && secs < 0) {
+            secs = match secs.checked_add(1) {
+                Some(secs) => secs,
+                None => return None,
+            };
+            nanos -= NANOS_PER_SEC;
+        }
+
+        while nanos < 0 && secs > 0 {
+            secs -= 1;
+            nanos += NANOS_PER_SEC;
+        }
+
+        Some(Duration {
+            secs: secs,
+            nanos: nanos,
+        })
+    }
+
+    /// Add two durations, returning `None` if the seconds overflow
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        match self.secs.checked_add(other.secs) {
+            Some(secs) => Duration::checked_new(secs, self.nanos as i64 + other.nanos as i64),
+            None => None,
+        }
+    }
+
+    /// Subtract two durations, returning `None` if the seconds overflow
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.secs.checked_sub(other.secs) {
+            Some(secs) => Duration::checked_new(secs, self.nanos as i64 - other.nanos as i64),
+            None => None,
+        }
+    }
+
+    /// Multiply the duration, returning `None` if the seconds overflow
+    pub fn checked_mul(self, other: i32) -> Option<Self> {
+        match self.secs.checked_mul(other as i64) {
+            Some(secs) => Duration::checked_new(secs, self.nanos as i64 * other as i64),
+            None => None,
+        }
+    }
+
+    /// Divide the duration, returning `None` if the divisor is zero or the seconds overflow
+    pub fn checked_div(self, other: i32) -> Option<Self> {
+        match (self.secs.checked_div(other as i64), self.secs.checked_rem(other as i64)) {
+            (Some(secs), Some(rem)) => {
+                // Carry the seconds that do not divide evenly down into the nanoseconds
+                let carry = rem * NANOS_PER_SEC as i64 + self.nanos as i64;
+                Duration::checked_new(secs, carry / other as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Create a duration from the time value of a syscall, which may be negative or not normalized
+    pub fn from_tv(tv: TV) -> Self {
+        let micros_per_sec = NANOS_PER_SEC / NANOS_PER_MICRO;
+        Duration::new(tv.tv_sec + (tv.tv_usec / micros_per_sec) as i64,
+                      tv.tv_usec % micros_per_sec * NANOS_PER_MICRO)
+    }
+
+    /// Convert to the time value of a syscall
+    ///
+    /// The nanoseconds are rounded toward zero, so the time value is never longer than the
+    /// duration. A negative duration gives a time value with negative seconds and microseconds.
+    pub fn to_tv(&self) -> TV {
+        let normal = Duration::new(self.secs, self.nanos);
+        TV {
+            tv_sec: normal.secs,
+            tv_usec: normal.nanos / NANOS_PER_MICRO,
+        }
+    }
+
     /// Get the realtime
     pub fn realtime() -> Self {
         let mut tv = TV {
@@ -43,14 +172,14 @@ impl Duration {
 
         unsafe { sys_gettimeofday(&mut tv) };
 
-        Duration::new(tv.tv_sec, tv.tv_usec * 1000)
+        Duration::from_tv(tv)
     }
 
     /// Sleep the duration
     pub fn sleep(&self) {
-        let start_time = Duration::realtime();
+        let start_time = Instant::now();
         loop {
-            let elapsed = Duration::realtime() - start_time;
+            let elapsed = start_time.elapsed();
             if elapsed > *self {
                 break;
             } else {
@@ -64,7 +193,13 @@ impl Add for Duration {
     type Output = Duration;
 
     fn add(self, other: Self) -> Self {
-        Duration::new(self.secs + other.secs, self.nanos + other.nanos)
+        if cfg!(debug_assertions) {
+            self.checked_add(other).expect("overflow when adding durations")
+        } else {
+            let nanos = self.nanos as i64 + other.nanos as i64;
+            Duration::new(self.secs.wrapping_add(other.secs).wrapping_add(nanos / NANOS_PER_SEC as i64),
+                          (nanos % NANOS_PER_SEC as i64) as i32)
+        }
     }
 }
 
@@ -72,14 +207,62 @@ impl Sub for Duration {
     type Output = Duration;
 
     fn sub(self, other: Self) -> Self {
-        Duration::new(self.secs - other.secs, self.nanos - other.nanos)
+        if cfg!(debug_assertions) {
+            self.checked_sub(other).expect("overflow when subtracting durations")
+        } else {
+            let nanos = self.nanos as i64 - other.nanos as i64;
+            Duration::new(self.secs.wrapping_sub(other.secs).wrapping_add(nanos / NANOS_PER_SEC as i64),
+                          (nanos % NANOS_PER_SEC as i64) as i32)
+        }
+    }
+}
+
+impl Mul<i32> for Duration {
+    type Output = Duration;
+
+    fn mul(self, other: i32) -> Self {
+        if cfg!(debug_assertions) {
+            self.checked_mul(other).expect("overflow when multiplying a duration")
+        } else {
+            let nanos = self.nanos as i64 * other as i64;
+            Duration::new(self.secs.wrapping_mul(other as i64).wrapping_add(nanos / NANOS_PER_SEC as i64),
+                          (nanos % NANOS_PER_SEC as i64) as i32)
+        }
+    }
+}
+
+impl Div<i32> for Duration {
+    type Output = Duration;
+
+    fn div(self, other: i32) -> Self {
+        if other == 0 {
+            panic!("attempt to divide a duration by zero");
+        }
+
+        if cfg!(debug_assertions) {
+            self.checked_div(other).expect("overflow when dividing a duration")
+        } else {
+            // Carry the seconds that do not divide evenly down into the nanoseconds
+            let carry = self.secs.wrapping_rem(other as i64) * NANOS_PER_SEC as i64 + self.nanos as i64;
+            Duration::new(self.secs.wrapping_div(other as i64), (carry / other as i64) as i32)
+        }
     }
 }
 
 impl PartialEq for Duration {
     fn eq(&self, other: &Self) -> bool {
-        let dif = *self - *other;
-        dif.secs == 0 && dif.nanos == 0
+        (*self - *other).is_zero()
+    }
+}
+
+impl Eq for Duration {}
+
+impl Hash for Duration {
+    /// Hash the normalized duration, so that equal durations hash equally
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let normal = Duration::new(self.secs, self.nanos);
+        normal.secs.hash(state);
+        normal.nanos.hash(state);
     }
 }
 
@@ -99,3 +282,334 @@ impl PartialOrd for Duration {
         }
     }
 }
+
+/// A point in time read from the monotonic clock
+///
+/// Unlike `Duration::realtime`, the monotonic clock counts time since boot and is never
+/// adjusted, so it only moves forward. It is advanced by the timer interrupt, so while the
+/// value is reported with microsecond precision, it only changes once per timer tick
+/// (roughly every 2.25 milliseconds).
+#[derive(Copy, Clone)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Get the current instant
+    pub fn now() -> Self {
+        let mut tv = TV {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+
+        unsafe { sys_clock_gettime(CLOCK_MONOTONIC, &mut tv) };
+
+        Instant(Duration::from_tv(tv))
+    }
+
+    /// Get the duration that has passed since this instant
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - *self
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        Instant(self.0 + other)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, other: Self) -> Duration {
+        self.0 - other.0
+    }
+}
+
+impl PartialEq for Instant {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl PartialOrd for Instant {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+/// A deadline for a wait, measured on the monotonic clock, for loops that poll until
+/// something happens or the time runs out
+#[derive(Copy, Clone)]
+pub struct Timeout {
+    deadline: Instant,
+}
+
+impl Timeout {
+    /// Start a timeout that expires when the duration has passed from now
+    pub fn start(duration: Duration) -> Self {
+        Timeout {
+            deadline: Instant::now() + duration,
+        }
+    }
+
+    /// Get the instant the timeout expires at
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Check if the duration has passed
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Get the time left before the timeout expires, which is zero once it has
+    pub fn remaining(&self) -> Duration {
+        let remaining = self.deadline - Instant::now();
+        if remaining > Duration::zero() {
+            remaining
+        } else {
+            Duration::zero()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::hash::{Hash, Hasher, SipHasher};
+    use core::i64;
+    use syscall::TV;
+    use vec::Vec;
+    use super::*;
+
+    #[test]
+    fn test_checked_add() {
+        let max = Duration::new(i64::MAX, 0);
+        assert!(max.checked_add(Duration::new(1, 0)).is_none());
+        assert!(max.checked_add(Duration::zero()) == Some(max));
+
+        let sum = Duration::new(i64::MAX - 1, 600_000_000).checked_add(Duration::new(0, 600_000_000));
+        assert!(sum == Some(Duration::new(i64::MAX, 200_000_000)));
+        assert!(Duration::new(i64::MAX, 500_000_000).checked_add(Duration::new(0, 600_000_000)).is_none());
+    }
+
+    #[test]
+    fn test_checked_sub() {
+        let min = Duration::new(i64::MIN, 0);
+        assert!(min.checked_sub(Duration::new(1, 0)).is_none());
+        assert!(min.checked_sub(Duration::zero()) == Some(min));
+        assert!(Duration::new(-1, 0).checked_sub(Duration::new(i64::MAX, 0)) == Some(min));
+        assert!(Duration::new(-2, 0).checked_sub(Duration::new(i64::MAX, 0)).is_none());
+        assert!(Duration::new(i64::MAX, 0).checked_sub(Duration::new(i64::MAX, 0)) == Some(Duration::zero()));
+    }
+
+    #[test]
+    fn test_add_sub_near_max() {
+        assert!(Duration::new(i64::MAX - 1, 0) + Duration::new(1, 0) == Duration::new(i64::MAX, 0));
+        assert!(Duration::new(i64::MAX, 0) - Duration::new(1, 0) == Duration::new(i64::MAX - 1, 0));
+        assert!(Duration::new(i64::MIN + 1, 0) - Duration::new(1, 0) == Duration::new(i64::MIN, 0));
+    }
+
+    #[test]
+    fn test_zero() {
+        assert!(Duration::zero().is_zero());
+        assert!(Duration::zero() == Duration::new(0, 0));
+        assert!(Duration { secs: 1, nanos: -NANOS_PER_SEC }.is_zero());
+        assert!(Duration { secs: -1, nanos: NANOS_PER_SEC }.is_zero());
+        assert!(! Duration::new(0, 1).is_zero());
+        assert!(! Duration::from_secs(-1).is_zero());
+        assert!((Duration::from_millis(250) - Duration::from_millis(250)).is_zero());
+    }
+
+    fn hash(duration: &Duration) -> u64 {
+        let mut hasher = SipHasher::new();
+        duration.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_eq_hash_normalized() {
+        let pairs = [(Duration { secs: 1, nanos: -500_000_000 }, Duration::new(0, 500_000_000)),
+                     (Duration { secs: 0, nanos: 1_500_000_000 }, Duration::new(1, 500_000_000)),
+                     (Duration { secs: -1, nanos: 500_000_000 }, Duration::new(0, -500_000_000)),
+                     (Duration { secs: 2, nanos: -NANOS_PER_SEC }, Duration::from_secs(1))];
+        for &(a, b) in pairs.iter() {
+            assert!(a == b);
+            assert_eq!(hash(&a), hash(&b));
+        }
+
+        assert!(Duration::new(1, 0) != Duration::new(1, 1));
+    }
+
+    /// Insert a duration into a set bucketed by hash, as a hash set would, returning false if it was present
+    fn insert(set: &mut Vec<(u64, Vec<Duration>)>, duration: Duration) -> bool {
+        let key = hash(&duration);
+        match set.iter().position(|&(bucket, _)| bucket == key) {
+            Some(i) => if set[i].1.iter().any(|&other| other == duration) {
+                false
+            } else {
+                set[i].1.push(duration);
+                true
+            },
+            None => {
+                set.push((key, vec![duration]));
+                true
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_set_key() {
+        let mut set = Vec::new();
+        assert!(insert(&mut set, Duration::new(1, 500_000_000)));
+        assert!(insert(&mut set, Duration::from_millis(250)));
+        assert!(insert(&mut set, Duration::new(-1, 0)));
+
+        // Unnormalized durations find the entry of their normalized equal
+        assert!(! insert(&mut set, Duration { secs: 0, nanos: 1_500_000_000 }));
+        assert!(! insert(&mut set, Duration { secs: 1, nanos: -750_000_000 }));
+        assert!(! insert(&mut set, Duration { secs: 0, nanos: -NANOS_PER_SEC }));
+        assert!(! insert(&mut set, Duration::from_secs(1) + Duration::from_millis(500)));
+
+        assert!(insert(&mut set, Duration::new(1, 500_000_001)));
+        assert_eq!(set.iter().fold(0, |count, &(_, ref bucket)| count + bucket.len()), 4);
+    }
+
+    #[test]
+    fn test_mul() {
+        assert!(Duration::from_millis(1500) * 2 == Duration::from_secs(3));
+        assert!(Duration::from_millis(1500) * -2 == Duration::from_secs(-3));
+        assert!(Duration::new(2, 700_000_000) * 3 == Duration::new(8, 100_000_000));
+        assert!(Duration::from_secs(5) * 0 == Duration::zero());
+    }
+
+    #[test]
+    fn test_checked_mul() {
+        assert!(Duration::new(i64::MAX / 2, 0).checked_mul(2) == Some(Duration::new(i64::MAX - 1, 0)));
+        assert!(Duration::new(i64::MAX / 2 + 1, 0).checked_mul(2).is_none());
+        assert!(Duration::new(i64::MAX, 600_000_000).checked_mul(1) == Some(Duration::new(i64::MAX, 600_000_000)));
+        assert!(Duration::new(i64::MAX - 1, 600_000_000).checked_mul(2).is_none());
+        assert!(Duration::new(i64::MIN, 0).checked_mul(-1).is_none());
+    }
+
+    #[test]
+    fn test_div() {
+        assert!(Duration::from_secs(3) / 2 == Duration::from_millis(1500));
+        assert!(Duration::new(1, 500_000_000) / 2 == Duration::new(0, 750_000_000));
+        assert!(Duration::from_secs(-3) / 2 == Duration::from_millis(-1500));
+        assert!(Duration::new(7, 0) / 4 == Duration::new(1, 750_000_000));
+    }
+
+    #[test]
+    fn test_checked_div() {
+        assert!(Duration::from_secs(1).checked_div(0).is_none());
+        assert!(Duration::new(i64::MIN, 0).checked_div(-1).is_none());
+        assert!(Duration::new(i64::MIN, 0).checked_div(1) == Some(Duration::new(i64::MIN, 0)));
+    }
+
+    #[test]
+    fn test_total_nanos_round_trip() {
+        let values: [i64; 9] = [0, 1, -1, 999_999_999, -1_000_000_001, 123_456_789_012, -987_654_321_098_765,
+                                i64::MAX, i64::MIN];
+        for &nanos in values.iter() {
+            assert_eq!(Duration::from_total_nanos(nanos).total_nanos(), nanos);
+        }
+
+        assert!(Duration::from_total_nanos(1_500_000_000) == Duration::from_millis(1500));
+        assert!(Duration::from_total_nanos(-1_500_000_000) == Duration::from_millis(-1500));
+        assert_eq!(Duration::from_millis(-1500).total_nanos(), -1_500_000_000);
+    }
+
+    #[test]
+    fn test_total_nanos_saturates() {
+        assert_eq!(Duration::new(i64::MAX, 0).total_nanos(), i64::MAX);
+        assert_eq!(Duration::new(i64::MAX / NANOS_PER_SEC as i64 + 1, 0).total_nanos(), i64::MAX);
+        assert_eq!(Duration::new(i64::MIN, 0).total_nanos(), i64::MIN);
+        assert_eq!(Duration::new(i64::MIN / NANOS_PER_SEC as i64 - 1, 0).total_nanos(), i64::MIN);
+    }
+
+    #[test]
+    fn test_add_unnormalized_nanos() {
+        // Each field fits in an i32, but their sum does not
+        let a = Duration { secs: 1, nanos: 2_000_000_000 };
+        let b = Duration { secs: 2, nanos: 1_500_000_000 };
+        assert!(a + b == Duration::new(6, 500_000_000));
+        assert!(a.checked_add(b).unwrap() == Duration::new(6, 500_000_000));
+
+        let c = Duration { secs: 0, nanos: -2_000_000_000 };
+        assert!(c - a == Duration::new(-5, 0));
+        assert!(c.checked_sub(a).unwrap() == Duration::new(-5, 0));
+    }
+
+    #[test]
+    fn test_tv_round_trip() {
+        let tvs = [(0, 0), (1, 250_000), (-3, -999_999), (0, -1), (i64::MAX, 999_999), (i64::MIN, -999_999)];
+        for &(sec, usec) in tvs.iter() {
+            let tv = Duration::from_tv(TV { tv_sec: sec, tv_usec: usec }).to_tv();
+            assert_eq!((tv.tv_sec, tv.tv_usec), (sec, usec));
+        }
+    }
+
+    #[test]
+    fn test_from_tv() {
+        assert!(Duration::from_tv(TV { tv_sec: 1, tv_usec: 500_000 }) == Duration::new(1, 500_000_000));
+        assert!(Duration::from_tv(TV { tv_sec: 1, tv_usec: 1_500_000 }) == Duration::new(2, 500_000_000));
+        assert!(Duration::from_tv(TV { tv_sec: -1, tv_usec: -500_000 }) == Duration::new(-1, -500_000_000));
+        assert!(Duration::from_tv(TV { tv_sec: 0, tv_usec: -1_500_000 }) == Duration::new(-1, -500_000_000));
+        assert!(Duration::from_tv(TV { tv_sec: 1, tv_usec: -500_000 }) == Duration::new(0, 500_000_000));
+    }
+
+    #[test]
+    fn test_to_tv() {
+        // The nanoseconds are rounded toward zero
+        let tv = Duration::new(1, 500_000_999).to_tv();
+        assert_eq!((tv.tv_sec, tv.tv_usec), (1, 500_000));
+        let tv = Duration::new(-1, -500_000_999).to_tv();
+        assert_eq!((tv.tv_sec, tv.tv_usec), (-1, -500_000));
+        let tv = Duration { secs: -1, nanos: 500_000_000 }.to_tv();
+        assert_eq!((tv.tv_sec, tv.tv_usec), (0, -500_000));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = Timeout::start(Duration::from_millis(50));
+        assert!(! timeout.expired());
+        assert!(timeout.remaining() > Duration::zero());
+        assert!(timeout.remaining() <= Duration::from_millis(50));
+
+        Duration::from_millis(50).sleep();
+        assert!(timeout.expired());
+        assert!(timeout.remaining() == Duration::zero());
+
+        assert!(Timeout::start(Duration::zero()).expired());
+        assert!(Timeout::start(Duration::from_millis(-50)).remaining() == Duration::zero());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_div_by_zero() {
+        Duration::from_secs(1) / 0;
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_mul_overflow() {
+        Duration::new(i64::MAX, 0) * 2;
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_add_overflow() {
+        Duration::new(i64::MAX, 0) + Duration::new(1, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_sub_overflow() {
+        Duration::new(i64::MIN, 0) - Duration::new(1, 0);
+    }
+}